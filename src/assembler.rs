@@ -0,0 +1,677 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::dwarf;
+use crate::encoder::{self, FixupKind};
+use crate::ir::{Data, EquValue, Instruction, Item, Memory, Operand, Program, Term, Value};
+use crate::target;
+use crate::tokenizer::Loc;
+
+/// What a relocation refers to: a place in a section of this object or a symbol defined elsewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Section(usize),
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationKind {
+    Absolute64,
+    Absolute32,
+    // 32 bits the CPU sign-extends to 64
+    Absolute32Signed,
+    // 32-bit distance from the end of the field
+    Relative32,
+    // The 16-bit forms of 16-bit code
+    Absolute16,
+    Relative16,
+}
+
+impl RelocationKind {
+    /// Size of the field in bytes.
+    pub fn width(&self) -> usize {
+        match self {
+            RelocationKind::Absolute64 => 8,
+            RelocationKind::Absolute32 | RelocationKind::Absolute32Signed | RelocationKind::Relative32 => 4,
+            RelocationKind::Absolute16 | RelocationKind::Relative16 => 2,
+        }
+    }
+}
+
+/// `target + addend` to be written at `offset` once the address of `target` is known.
+#[derive(Debug, Clone)]
+pub struct Relocation {
+    pub offset: u64,
+    pub target: Target,
+    pub kind: RelocationKind,
+    pub addend: i64,
+}
+
+#[derive(Debug)]
+pub struct Section {
+    pub name: String,
+    // Empty for `.bss`, which only has a size
+    pub data: Vec<u8>,
+    pub size: u64,
+    pub bss: bool,
+    pub alignment: u64,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Section {
+    pub fn new(name: &str) -> Section {
+        let bss = name == ".bss";
+        // NASM's defaults for the sections it knows
+        let alignment = if name == ".text" { 16 } else { 4 };
+        Section { name: name.to_string(), data: Vec::new(), size: 0, bss, alignment, relocations: Vec::new() }
+    }
+
+    pub fn is_code(&self) -> bool {
+        self.name == ".text"
+    }
+
+    pub fn is_writable(&self) -> bool {
+        !self.is_code() && self.name != ".rodata" && !self.is_debug()
+    }
+
+    /// Debug information, which is kept in the file but never loaded.
+    pub fn is_debug(&self) -> bool {
+        self.name.starts_with(".debug_")
+    }
+}
+
+/// A label of the program; `section` is `None` for symbols that are only declared.
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Option<usize>,
+    pub offset: u64,
+    pub global: bool,
+}
+
+/// Machine code and data of a program, before it is linked.
+#[derive(Debug, Default)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub target: target::Target,
+    // Address given with `org`, where a flat binary is loaded
+    pub origin: Option<u64>,
+}
+
+/// Why a program cannot be assembled, with the statement the problem is in when the program
+/// has line markers.
+#[derive(Debug)]
+pub struct Error {
+    pub loc: Option<Loc>,
+    pub message: String,
+}
+
+/// Where the bytes of an instruction, or of any item outside of a function, ended up.
+#[derive(Debug)]
+pub struct Placed {
+    pub section: usize,
+    pub offset: u64,
+    pub size: u64,
+}
+
+// A field whose value is only known once every label is placed
+struct PendingFixup {
+    section: usize,
+    offset: u64,
+    width: usize,
+    terms: Vec<Term>,
+    kind: FixupKind,
+    // Offset of the end of the instruction, relative values are measured from there
+    end: u64,
+    statement: Option<Loc>,
+}
+
+// A value once every label is placed: a number, or an address that still needs relocating
+enum Resolved {
+    Constant(i64),
+    Address(Target, i64),
+}
+
+struct Assembler {
+    target: target::Target,
+    origin: Option<u64>,
+    sections: Vec<Section>,
+    current: usize,
+    labels: HashMap<String, (usize, u64)>,
+    label_order: Vec<String>,
+    constants: HashMap<String, i64>,
+    defines: HashMap<String, Operand>,
+    externs: HashSet<String>,
+    globals: HashSet<String>,
+    fixups: Vec<PendingFixup>,
+    // Where the code of every source position and function went, for `-g`
+    rows: Vec<dwarf::Row>,
+    spans: HashMap<String, dwarf::Span>,
+    // Every instruction and item in program order, for listings
+    placed: Vec<Placed>,
+    // The statement the code being assembled was generated for, when the program has line
+    // markers
+    statement: Option<Loc>,
+}
+
+impl Assembler {
+    fn section(&mut self, name: &str) {
+        match self.sections.iter().position(|section| section.name == name) {
+            Some(index) => self.current = index,
+            None => {
+                self.sections.push(Section::new(name));
+                self.current = self.sections.len() - 1;
+            }
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        self.sections[self.current].size
+    }
+
+    fn label(&mut self, name: &str) -> Result<(), String> {
+        if self.labels.insert(name.to_string(), (self.current, self.offset())).is_some() {
+            return Err(format!("label '{}' is defined more than once", name));
+        }
+        self.label_order.push(name.to_string());
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let section = &mut self.sections[self.current];
+        if section.bss && bytes.iter().any(|byte| *byte != 0) {
+            return Err("'.bss' can only reserve space, data has to go into '.data' or '.rodata'".to_string());
+        }
+        if !section.bss {
+            section.data.extend_from_slice(bytes);
+        }
+        section.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn reserve(&mut self, count: u64) -> Result<(), String> {
+        let section = &mut self.sections[self.current];
+        if !section.bss {
+            return Err(format!("space can only be reserved in '.bss', not in '{}'", section.name));
+        }
+        section.size += count;
+        Ok(())
+    }
+
+    /// A `%define` stands for its operand wherever its name is used.
+    fn substitute(&self, operand: &Operand) -> Result<Operand, String> {
+        match operand {
+            Operand::Immediate(Value::Symbol(name)) => match self.defines.get(name) {
+                Some(defined) => self.substitute(defined),
+                None => Ok(operand.clone()),
+            },
+            Operand::Immediate(value) => Ok(Operand::Immediate(self.substitute_value(value)?)),
+            Operand::Memory(memory) => {
+                let displacement = memory.displacement.iter()
+                    .map(|term| Ok(Term { negative: term.negative, value: self.substitute_value(&term.value)? }))
+                    .collect::<Result<_, String>>()?;
+                Ok(Operand::Memory(Memory { displacement, ..memory.clone() }))
+            }
+            Operand::Register(_) => Ok(operand.clone()),
+        }
+    }
+
+    fn substitute_value(&self, value: &Value) -> Result<Value, String> {
+        match value {
+            Value::Symbol(name) => match self.defines.get(name) {
+                Some(defined) => match self.substitute(defined)? {
+                    Operand::Immediate(value) => Ok(value),
+                    _ => Err(format!("'{}' is not a value and cannot be used here", name)),
+                },
+                None => Ok(value.clone()),
+            },
+            Value::Or(left, right) => Ok(Value::Or(Box::new(self.substitute_value(left)?), Box::new(self.substitute_value(right)?))),
+            Value::Number(_) => Ok(value.clone()),
+        }
+    }
+
+    /// A value that has to be known right away, such as a repeat count.
+    fn constant(&self, value: &Value) -> Result<i64, String> {
+        match self.resolve(&[Term { negative: false, value: self.substitute_value(value)? }])? {
+            Resolved::Constant(constant) => Ok(constant),
+            Resolved::Address(..) => Err(format!("'{}' is an address, a number is needed here", value)),
+        }
+    }
+
+    fn resolve(&self, terms: &[Term]) -> Result<Resolved, String> {
+        let mut constant = 0i64;
+        let mut target = None;
+        for term in terms {
+            let value = match &term.value {
+                Value::Number(number) => *number,
+                Value::Or(left, right) => self.constant(left)? | self.constant(right)?,
+                Value::Symbol(name) => {
+                    if let Some(value) = self.constants.get(name) {
+                        *value
+                    } else {
+                        let (address, offset) = match self.labels.get(name) {
+                            Some((section, offset)) => (Target::Section(*section), *offset as i64),
+                            None if self.externs.contains(name) || self.globals.contains(name) => (Target::Symbol(name.clone()), 0),
+                            None => return Err(format!("undefined symbol '{}'", name)),
+                        };
+                        if term.negative || target.is_some() {
+                            return Err(format!("'{}' is an address and can only be added to a number", name));
+                        }
+                        target = Some(address);
+                        offset
+                    }
+                }
+            };
+            constant = if term.negative { constant.wrapping_sub(value) } else { constant.wrapping_add(value) };
+        }
+        Ok(match target {
+            Some(target) => Resolved::Address(target, constant),
+            None => Resolved::Constant(constant),
+        })
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) -> Result<(), String> {
+        let instruction = match instruction {
+            Instruction::Binary(opcode, dest, src) => Instruction::Binary(*opcode, self.substitute(dest)?, self.substitute(src)?),
+            Instruction::Push(operand) => Instruction::Push(self.substitute(operand)?),
+            Instruction::Pop(operand) => Instruction::Pop(self.substitute(operand)?),
+            instruction => instruction.clone(),
+        };
+        let encoded = encoder::encode(&instruction, self.target.bits())?;
+        let start = self.offset();
+        let end = start + encoded.bytes.len() as u64;
+        for fixup in encoded.fixups {
+            self.fixups.push(PendingFixup {
+                section: self.current,
+                offset: start + fixup.offset as u64,
+                width: fixup.width,
+                terms: fixup.terms,
+                kind: fixup.kind,
+                end,
+                statement: self.statement.clone(),
+            });
+        }
+        self.emit(&encoded.bytes)
+    }
+
+    fn data(&mut self, data: &Data) -> Result<(), String> {
+        match data {
+            Data::Values { data_type, values, repeat } => {
+                let repeat = match repeat {
+                    Some(count) => self.constant(count)?,
+                    None => 1,
+                };
+                let width = data_type.size();
+                for _ in 0..repeat {
+                    for value in values {
+                        let value = self.substitute_value(value)?;
+                        match value {
+                            Value::Number(number) => {
+                                if !encoder::fits(number, width, false) {
+                                    return Err(format!("{} does not fit in {} bytes", number, width));
+                                }
+                                self.emit(&number.to_le_bytes()[..width])?;
+                            }
+                            value => {
+                                if self.sections[self.current].bss {
+                                    return Err(format!("'{}' cannot be stored in '.bss', which only reserves space", value));
+                                }
+                                let offset = self.offset();
+                                self.fixups.push(PendingFixup {
+                                    section: self.current,
+                                    offset,
+                                    width,
+                                    terms: vec![Term { negative: false, value }],
+                                    kind: FixupKind::Absolute { signed: false },
+                                    end: offset + width as u64,
+                                    statement: self.statement.clone(),
+                                });
+                                self.emit(&vec![0; width])?;
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Data::Bytes(bytes) => self.emit(bytes),
+            Data::Reserve { data_type, count } => {
+                let count = self.constant(count)?;
+                self.reserve(count as u64 * data_type.size() as u64)
+            }
+            Data::Zero(count) => self.emit(&vec![0; *count]),
+        }
+    }
+
+    fn equ(&mut self, name: &str, value: &EquValue) -> Result<(), String> {
+        let value = match value {
+            EquValue::Since(label) => match self.labels.get(label) {
+                Some((section, offset)) if *section == self.current => (self.offset() - offset) as i64,
+                Some(_) => return Err(format!("'{}' is in another section", label)),
+                None => return Err(format!("undefined symbol '{}'", label)),
+            },
+            EquValue::Quotient(symbol, divisor) => self.constant(&Value::Symbol(symbol.clone()))? / *divisor as i64,
+            EquValue::Symbol(symbol) => self.constant(&Value::Symbol(symbol.clone()))?,
+            EquValue::Number(number) => *number,
+        };
+        self.constants.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    fn align(&mut self, alignment: &Value) -> Result<(), String> {
+        let alignment = self.constant(alignment)?;
+        if alignment <= 0 || alignment & (alignment - 1) != 0 {
+            return Err(format!("alignment {} is not a power of two", alignment));
+        }
+        let alignment = alignment as u64;
+        let section = &mut self.sections[self.current];
+        section.alignment = section.alignment.max(alignment);
+        let padding = (alignment - section.size % alignment) % alignment;
+        if section.bss {
+            return self.reserve(padding);
+        }
+        // Code is padded with `nop`s, data with zeroes
+        let fill = if section.is_code() { 0x90 } else { 0 };
+        self.emit(&vec![fill; padding as usize])
+    }
+
+    /// Fills the current section up to `size` bytes from its start.
+    fn pad(&mut self, size: &Value) -> Result<(), String> {
+        let size = self.constant(size)?;
+        let section = &self.sections[self.current];
+        if size < 0 || (size as u64) < section.size {
+            return Err(format!("'{}' is already {} bytes long and cannot be padded to {}", section.name, section.size, size));
+        }
+        let padding = size as u64 - section.size;
+        if section.bss {
+            return self.reserve(padding);
+        }
+        self.emit(&vec![0; padding as usize])
+    }
+
+    fn item(&mut self, item: &Item) -> Result<(), String> {
+        match item {
+            Item::Define(..) | Item::Extern(_) | Item::Global(_) => Ok(()),
+            Item::Section(name) => {
+                self.section(&format!(".{}", name));
+                Ok(())
+            }
+            Item::Function(function) => {
+                self.statement = None;
+                self.label(&function.name)?;
+                let start = self.offset();
+                for block in &function.blocks {
+                    if let Some(label) = &block.label {
+                        self.label(label)?;
+                    }
+                    let mut lines = block.lines.iter().peekable();
+                    for (index, instruction) in block.instructions.iter().enumerate() {
+                        if let Some((_, loc)) = lines.next_if(|(line_index, _)| *line_index == index) {
+                            self.line(loc);
+                        }
+                        let offset = self.offset();
+                        self.instruction(instruction)?;
+                        self.placed.push(Placed { section: self.current, offset, size: self.offset() - offset });
+                    }
+                }
+                let span = dwarf::Span { section: self.current, start, end: self.offset() };
+                self.spans.insert(function.name.clone(), span);
+                Ok(())
+            }
+            Item::Label(label) => self.label(label),
+            Item::Instruction(instruction) => self.instruction(instruction),
+            Item::Data(label, data) => {
+                if let Some(label) = label {
+                    self.label(label)?;
+                }
+                self.data(data)
+            }
+            Item::Equ(name, value) => self.equ(name, value),
+            Item::Align { alignment, .. } => self.align(alignment),
+            Item::Pad(size) => self.pad(size),
+            Item::Org(origin) => {
+                if self.origin.is_some() {
+                    return Err("'org' is given more than once".to_string());
+                }
+                self.origin = Some(self.constant(origin)? as u64);
+                Ok(())
+            }
+            Item::Line(loc) => {
+                self.line(loc);
+                Ok(())
+            }
+        }
+    }
+
+    fn line(&mut self, loc: &Loc) {
+        // Debuggers only step through code, data statements are marked for error messages
+        if self.sections[self.current].is_code() {
+            self.rows.push(dwarf::Row { section: self.current, offset: self.offset(), loc: loc.clone() });
+        }
+        self.statement = Some(loc.clone());
+    }
+
+    fn apply(&mut self, fixup: &PendingFixup) -> Result<(), String> {
+        let resolved = self.resolve(&fixup.terms)?;
+        let section = &mut self.sections[fixup.section];
+        let (value, relocation) = match (fixup.kind, resolved) {
+            (FixupKind::Relative, Resolved::Address(Target::Section(target), offset)) if target == fixup.section => {
+                (offset - fixup.end as i64, None)
+            }
+            (FixupKind::Relative, Resolved::Address(target, offset)) => {
+                // The field is measured from its own start, the CPU from the end of the instruction
+                let addend = offset - (fixup.end - fixup.offset) as i64;
+                let kind = if fixup.width == 2 { RelocationKind::Relative16 } else { RelocationKind::Relative32 };
+                (0, Some((target, kind, addend)))
+            }
+            (FixupKind::Relative, Resolved::Constant(_)) => {
+                return Err("the target of a jump or call has to be a label".to_string());
+            }
+            (FixupKind::Absolute { .. }, Resolved::Constant(constant)) => (constant, None),
+            (FixupKind::Absolute { signed }, Resolved::Address(target, offset)) => {
+                let kind = match (fixup.width, signed) {
+                    (8, _) if self.target != target::Target::X86_64 => {
+                        return Err(format!("an address takes at most 4 bytes in {}-bit code, not 8", self.target.bits()));
+                    }
+                    (8, _) => RelocationKind::Absolute64,
+                    (4, true) => RelocationKind::Absolute32Signed,
+                    (4, false) => RelocationKind::Absolute32,
+                    (2, _) => RelocationKind::Absolute16,
+                    (width, _) => return Err(format!("an address does not fit in {} bytes", width)),
+                };
+                (0, Some((target, kind, offset)))
+            }
+        };
+        let signed = matches!(fixup.kind, FixupKind::Relative | FixupKind::Absolute { signed: true });
+        if !encoder::fits(value, fixup.width, signed) {
+            return Err(format!("{} does not fit in {} bytes", value, fixup.width));
+        }
+        let start = fixup.offset as usize;
+        section.data[start..start + fixup.width].copy_from_slice(&value.to_le_bytes()[..fixup.width]);
+        if let Some((target, kind, addend)) = relocation {
+            section.relocations.push(Relocation { offset: fixup.offset, target, kind, addend });
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a program into sections of machine code and data, with the symbols it defines
+/// and uses and the relocations a linker has to apply. Also tells where every instruction of
+/// a function and every other item went, in the order they appear in the program.
+pub fn assemble(program: &Program) -> Result<(Object, Vec<Placed>), Error> {
+    let mut assembler = Assembler {
+        target: program.target,
+        origin: None,
+        sections: Vec::new(),
+        current: 0,
+        labels: HashMap::new(),
+        label_order: Vec::new(),
+        constants: HashMap::new(),
+        defines: HashMap::new(),
+        externs: HashSet::new(),
+        globals: HashSet::new(),
+        fixups: Vec::new(),
+        rows: Vec::new(),
+        spans: HashMap::new(),
+        placed: Vec::new(),
+        statement: None,
+    };
+    // Code before the first `section` goes into `.text`
+    assembler.section(".text");
+
+    for item in &program.items {
+        match item {
+            Item::Define(name, operand) => {
+                assembler.defines.insert(name.clone(), operand.clone());
+            }
+            Item::Extern(name) => {
+                assembler.externs.insert(name.clone());
+            }
+            Item::Global(name) => {
+                assembler.globals.insert(name.clone());
+            }
+            _ => {}
+        }
+    }
+
+    for item in &program.items {
+        let (section, offset) = (assembler.current, assembler.offset());
+        assembler.item(item).map_err(|message| Error { loc: assembler.statement.clone(), message })?;
+        // Functions are placed an instruction at a time
+        if !matches!(item, Item::Function(_)) {
+            let size = if assembler.current == section { assembler.offset() - offset } else { 0 };
+            assembler.placed.push(Placed { section, offset, size });
+        }
+    }
+
+    for fixup in std::mem::take(&mut assembler.fixups) {
+        assembler.apply(&fixup).map_err(|message| Error { loc: fixup.statement.clone(), message })?;
+    }
+
+    let mut symbols = Vec::new();
+    for name in &assembler.label_order {
+        // `.name` labels are local to the function they are in and stay out of the symbol table
+        if name.starts_with('.') {
+            continue;
+        }
+        let (section, offset) = assembler.labels[name];
+        symbols.push(Symbol { name: name.clone(), section: Some(section), offset, global: assembler.globals.contains(name) });
+    }
+    let undefined: BTreeSet<&String> = assembler.externs.iter()
+        .chain(assembler.globals.iter())
+        .filter(|name| !assembler.labels.contains_key(*name))
+        .collect();
+    for name in undefined {
+        symbols.push(Symbol { name: name.clone(), section: None, offset: 0, global: true });
+    }
+
+    let mut sections = assembler.sections;
+    if let Some(debug) = &program.debug {
+        let debug_sections = dwarf::sections(debug, &assembler.rows, &assembler.spans, &sections, program.target);
+        sections.extend(debug_sections);
+    }
+    let object = Object { sections, symbols, target: program.target, origin: assembler.origin };
+    Ok((object, assembler.placed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Code, Condition, Function, Opcode};
+    use crate::registers;
+
+    fn reg(name: &str) -> Operand {
+        Operand::Register(registers::lookup(name).unwrap())
+    }
+
+    fn term(value: Value) -> Term {
+        Term { negative: false, value }
+    }
+
+    fn symbol(name: &str) -> Value {
+        Value::Symbol(name.to_string())
+    }
+
+    fn assemble_items(items: Vec<Item>, target: target::Target) -> Result<Object, Error> {
+        assemble(&Program { items, target, debug: None }).map(|(object, _)| object)
+    }
+
+    fn function(name: &str, code: Vec<Code>) -> Item {
+        Item::Function(Function::new(name.to_string(), code))
+    }
+
+    fn instruction(instruction: Instruction) -> Code {
+        Code::Instruction(instruction)
+    }
+
+    // Jumps and calls within a section are resolved right away, measured from the end of the
+    // instruction
+    #[test]
+    fn resolves_jumps_and_calls_to_their_distance() {
+        let items = vec![
+            Item::Section("text".to_string()),
+            function("f", vec![instruction(Instruction::Ret)]),
+            function("_start", vec![
+                Code::Label(".loop".to_string()),
+                instruction(Instruction::Binary(Opcode::Add, reg("rax"), Operand::Immediate(Value::Number(1)))),
+                instruction(Instruction::Jump(Some(Condition::NotEqual), ".loop".to_string())),
+                instruction(Instruction::Jump(None, ".end".to_string())),
+                instruction(Instruction::Ret),
+                Code::Label(".end".to_string()),
+                instruction(Instruction::Call("f".to_string())),
+            ]),
+        ];
+        let object = assemble_items(items, target::Target::X86_64).unwrap();
+        assert_eq!(object.sections[0].data, [
+            0xc3,
+            0x48, 0x83, 0xc0, 0x01,
+            0x0f, 0x85, 0xf6, 0xff, 0xff, 0xff,
+            0xe9, 0x01, 0x00, 0x00, 0x00,
+            0xc3,
+            0xe8, 0xea, 0xff, 0xff, 0xff,
+        ]);
+        assert!(object.sections[0].relocations.is_empty());
+    }
+
+    #[test]
+    fn resolves_16_bit_jumps_to_a_16_bit_distance() {
+        let items = vec![function("start", vec![
+            Code::Label(".again".to_string()),
+            instruction(Instruction::Jump(None, ".again".to_string())),
+        ])];
+        let object = assemble_items(items, target::Target::X86_16).unwrap();
+        assert_eq!(object.sections[0].data, [0xe9, 0xfd, 0xff]);
+    }
+
+    // Addresses are left to the linker, as the offset of the label in its section plus
+    // whatever is added to it
+    #[test]
+    fn leaves_addresses_to_relocations() {
+        let displacement = vec![term(symbol("msg")), term(Value::Number(4))];
+        let memory = Operand::Memory(Memory { size: None, base: registers::lookup("rbx"), index: None, displacement });
+        let items = vec![
+            Item::Section("data".to_string()),
+            Item::Data(Some("pad".to_string()), Data::Bytes(vec![0; 3])),
+            Item::Data(Some("msg".to_string()), Data::Bytes(b"hi".to_vec())),
+            Item::Section("text".to_string()),
+            Item::Instruction(Instruction::Binary(Opcode::Mov, reg("rax"), Operand::Immediate(symbol("msg")))),
+            Item::Instruction(Instruction::Binary(Opcode::Mov, reg("eax"), memory)),
+        ];
+        let object = assemble_items(items, target::Target::X86_64).unwrap();
+        let text = &object.sections[0];
+        assert_eq!(text.data, [0x48, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0x8b, 0x83, 0, 0, 0, 0]);
+        let relocations: Vec<_> = text.relocations.iter()
+            .map(|relocation| (relocation.offset, relocation.target.clone(), relocation.kind, relocation.addend))
+            .collect();
+        assert_eq!(relocations, [
+            (2, Target::Section(1), RelocationKind::Absolute64, 3),
+            (12, Target::Section(1), RelocationKind::Absolute32Signed, 7),
+        ]);
+    }
+
+    #[test]
+    fn reports_undefined_labels() {
+        let items = vec![function("_start", vec![instruction(Instruction::Jump(None, ".nowhere".to_string()))])];
+        let error = assemble_items(items, target::Target::X86_64).unwrap_err();
+        assert_eq!(error.message, "undefined symbol '.nowhere'");
+    }
+}
@@ -0,0 +1,254 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::gas;
+
+pub const USAGE: &str = "\
+Usage: asmpp [options] <input file>
+
+Output:
+  -o <file>                   Write the output to <file>, by default named after the input
+  -f, --format <format>       elf64 (default), elf32 or bin
+  --emit <kind>               What to write: tokens, ast, listing, asm, obj or exe (default)
+  -S                          Same as --emit asm
+  -c                          Same as --emit obj
+  --run                       Run the executable once it is built
+  --keep-temps                Keep the intermediate files next to the output
+
+Compilation:
+  -I <dir>                    Search <dir> for included files
+  --stdlib <dir>              Load the libraries from <dir> instead of the bundled ones
+  -O                          Run the peephole optimiser
+  -g                          Add debug information
+  -Wclobber                   Report callee-saved registers written without a `uses` clause
+
+Assembling and linking:
+  --assembler builtin|nasm    Assembler to encode the program with
+  --asm-syntax nasm|gas|gas-intel
+                              Assembly source to write and assemble it from
+  --linker builtin|ld         Linker to build the executable with
+  --entry <symbol>            Where execution starts, `_start` by default
+
+  -h, --help                  Show this help
+  --version                   Show the version
+";
+
+/// The source an external assembler is given.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Nasm,
+    Gas(gas::Dialect),
+}
+
+/// What a compilation writes to its output, picked with `--emit`, `-S` or `-c`. Every kind
+/// stops after the step that produces it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    // The generated code under the statements it came from
+    Listing,
+    Asm,
+    Obj,
+    Exe,
+}
+
+impl Emit {
+    fn from_name(name: &str) -> Option<Emit> {
+        match name {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "listing" => Some(Emit::Listing),
+            "asm" => Some(Emit::Asm),
+            "obj" => Some(Emit::Obj),
+            "exe" => Some(Emit::Exe),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub input: PathBuf,
+    // Named after the input when not given, see `output_path`
+    pub output: Option<PathBuf>,
+    pub format: String,
+    pub emit: Emit,
+    // Every -I directory in order, then the ASMPP_PATH entries
+    pub include_dirs: Vec<PathBuf>,
+    pub stdlib_dir: Option<PathBuf>,
+    pub warn_clobbers: bool,
+    pub optimise: bool,
+    pub debug: bool,
+    // `None` for the built-in assembler
+    pub syntax: Option<Syntax>,
+    pub use_ld: bool,
+    pub entry: String,
+    pub run: bool,
+    pub keep_temps: bool,
+}
+
+impl Options {
+    /// A flat binary is the memory image itself, it is written by the assembler and never linked.
+    pub fn flat(&self) -> bool {
+        self.format == "bin"
+    }
+
+    /// Where the output goes when `-o` does not say: the name of the input in the current
+    /// directory, with the extension of what is written. Tokens and the syntax tree go to
+    /// standard output.
+    pub fn output_path(&self) -> Option<PathBuf> {
+        if self.output.is_some() {
+            return self.output.clone();
+        }
+        let stem = Path::new(self.input.file_stem().unwrap_or(self.input.as_os_str()));
+        let extension = match self.emit {
+            Emit::Tokens | Emit::Ast => return None,
+            Emit::Listing => "lst",
+            Emit::Asm if matches!(self.syntax, Some(Syntax::Gas(_))) => "s",
+            Emit::Asm => "asm",
+            Emit::Obj => "o",
+            Emit::Exe if self.flat() => "bin",
+            Emit::Exe => return Some(stem.to_path_buf()),
+        };
+        Some(stem.with_extension(extension))
+    }
+}
+
+/// What the command line asks for.
+pub enum Command {
+    Compile(Options),
+    Help,
+    Version,
+}
+
+// The argument after an option that takes one
+fn value<'a>(args: &'a [String], index: &mut usize, option: &str) -> Result<&'a str, String> {
+    *index += 1;
+    args.get(*index).map(String::as_str).ok_or_else(|| format!("missing argument after {}", option))
+}
+
+/// Reads the arguments that follow the program name, in any order.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut format = "elf64".to_string();
+    let mut emit = Emit::Exe;
+    let mut include_dirs = Vec::new();
+    let mut stdlib_dir = None;
+    let (mut warn_clobbers, mut optimise, mut debug, mut run, mut keep_temps) = (false, false, false, false, false);
+    let mut syntax = None;
+    let mut assembler = None;
+    let mut linker = None;
+    let mut entry = "_start".to_string();
+
+    let mut index = 0;
+    while index < args.len() {
+        let arg = args[index].as_str();
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--version" => return Ok(Command::Version),
+            "-o" => output = Some(PathBuf::from(value(args, &mut index, arg)?)),
+            "-f" | "--format" => format = value(args, &mut index, arg)?.to_string(),
+            "--emit" => {
+                let name = value(args, &mut index, arg)?;
+                emit = Emit::from_name(name)
+                    .ok_or_else(|| format!("unknown output '{}' after --emit, use tokens, ast, listing, asm, obj or exe", name))?;
+            }
+            "-S" => emit = Emit::Asm,
+            "-c" => emit = Emit::Obj,
+            "--run" => run = true,
+            "--keep-temps" => keep_temps = true,
+            "-I" => include_dirs.push(PathBuf::from(value(args, &mut index, arg)?)),
+            "--stdlib" => stdlib_dir = Some(PathBuf::from(value(args, &mut index, arg)?)),
+            "-Wclobber" => warn_clobbers = true,
+            "-O" => optimise = true,
+            "-g" => debug = true,
+            "--asm-syntax" => {
+                syntax = Some(match value(args, &mut index, arg)? {
+                    "nasm" => Syntax::Nasm,
+                    "gas" => Syntax::Gas(gas::Dialect::Att),
+                    "gas-intel" => Syntax::Gas(gas::Dialect::Intel),
+                    name => return Err(format!("unknown syntax '{}' after --asm-syntax, use nasm, gas or gas-intel", name)),
+                });
+            }
+            "--assembler" => assembler = Some(value(args, &mut index, arg)?.to_string()),
+            "--linker" => linker = Some(value(args, &mut index, arg)?.to_string()),
+            "--entry" => entry = value(args, &mut index, arg)?.to_string(),
+            _ => {
+                if let Some(dir) = arg.strip_prefix("-I") {
+                    include_dirs.push(PathBuf::from(dir));
+                } else if arg.starts_with('-') && arg != "-" {
+                    return Err(format!("unknown option '{}'", arg));
+                } else if input.replace(PathBuf::from(arg)).is_some() {
+                    return Err("only one input file can be compiled at a time".to_string());
+                }
+            }
+        }
+        index += 1;
+    }
+    let input = input.ok_or("no input file")?;
+    if let Some(asmpp_path) = env::var_os("ASMPP_PATH") {
+        include_dirs.extend(env::split_paths(&asmpp_path));
+    }
+
+    // Encode the object file with the built-in assembler, or write the generated source out in
+    // the syntax of an external assembler and run that on it
+    match (assembler.as_deref(), syntax) {
+        (Some("builtin"), Some(_)) => {
+            return Err("the built-in assembler does not read source, drop --asm-syntax or pick an external assembler".to_string());
+        }
+        (Some("builtin") | None, _) => {}
+        (Some("nasm"), None | Some(Syntax::Nasm)) => syntax = Some(Syntax::Nasm),
+        (Some("nasm"), Some(_)) => return Err("NASM only reads NASM syntax, use --asm-syntax nasm with --assembler nasm".to_string()),
+        (Some(name), _) => return Err(format!("unknown assembler '{}' after --assembler, use builtin or nasm", name)),
+    }
+
+    let flat = format == "bin";
+    let gas = matches!(syntax, Some(Syntax::Gas(_)));
+    if flat && gas {
+        return Err("the GNU assembler cannot write flat binaries, use the built-in assembler or --asm-syntax nasm with -f bin".to_string());
+    }
+    if emit == Emit::Listing && gas {
+        return Err("listings are written by the built-in assembler or by NASM, drop --asm-syntax gas with --emit listing".to_string());
+    }
+    if flat && debug {
+        return Err("flat binaries have no room for debug information, drop -g with -f bin".to_string());
+    }
+    if flat && emit == Emit::Obj {
+        return Err("flat binaries have no object files, the assembler writes the binary itself".to_string());
+    }
+    if run && (emit != Emit::Exe || flat) {
+        return Err("--run needs an executable, it cannot be combined with --emit, -S, -c or -f bin".to_string());
+    }
+
+    // Write the executable with the built-in linker, or run `ld` on the object file. The output
+    // of an external assembler is always linked with `ld`
+    let use_ld = match linker.as_deref() {
+        Some(_) if flat => return Err("flat binaries are not linked, drop --linker with -f bin".to_string()),
+        Some("builtin") if syntax.is_some() => {
+            return Err("the built-in linker needs the built-in assembler, use --linker ld with --asm-syntax".to_string());
+        }
+        Some("builtin") => false,
+        Some("ld") => true,
+        Some(name) => return Err(format!("unknown linker '{}' after --linker, use builtin or ld", name)),
+        None => syntax.is_some(),
+    };
+
+    Ok(Command::Compile(Options {
+        input,
+        output,
+        format,
+        emit,
+        include_dirs,
+        stdlib_dir,
+        warn_clobbers,
+        optimise,
+        debug,
+        syntax,
+        use_ld,
+        entry,
+        run,
+        keep_temps,
+    }))
+}
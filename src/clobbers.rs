@@ -0,0 +1,86 @@
+use crate::parser::{NodeExpr, NodeFunc, NodeStmt};
+use crate::registers;
+use crate::target::Target;
+
+/// Registers a function has to give back to its caller unchanged, besides `rbp` and `rsp`
+/// which the frame itself restores, by the name of their 64-bit register.
+pub fn callee_saved(target: Target) -> &'static [&'static str] {
+    match target {
+        Target::X86_64 => &["rbx", "r12", "r13", "r14", "r15"],
+        Target::X86 | Target::X86_16 => &["rbx", "rsi", "rdi"],
+    }
+}
+
+pub fn is_callee_saved(full_name: &str, target: Target) -> bool {
+    callee_saved(target).contains(&full_name)
+}
+
+/// The callee-saved registers by the name they have at the width of a word, `ebx` on x86.
+pub fn callee_saved_names(target: Target) -> Vec<&'static str> {
+    callee_saved(target).iter().map(|full| target.register(full).name).collect()
+}
+
+fn written_register(expr: &NodeExpr) -> Option<&'static str> {
+    match expr {
+        NodeExpr::Ident(ident) => registers::lookup(&ident.name).map(|register| register.full),
+        _ => None,
+    }
+}
+
+fn collect_writes(body: &[NodeStmt], written: &mut Vec<&'static str>) {
+    for stmt in body {
+        let dest = match stmt {
+            NodeStmt::Mov(mov) => written_register(&mov.dest),
+            NodeStmt::Add(add) => written_register(&add.dest),
+            NodeStmt::Xor(xor) => written_register(&xor.dest),
+            NodeStmt::Pop(pop) => registers::lookup(&pop.ident.name).map(|register| register.full),
+            NodeStmt::If(if_stmt) => {
+                collect_writes(&if_stmt.body, written);
+                None
+            }
+            NodeStmt::While(while_stmt) => {
+                collect_writes(&while_stmt.body, written);
+                None
+            }
+            _ => None,
+        };
+        if let Some(full) = dest {
+            if !written.contains(&full) {
+                written.push(full);
+            }
+        }
+    }
+}
+
+/// Every register written anywhere in the body of `func`, by the name of its 64-bit register.
+pub fn written(func: &NodeFunc) -> Vec<&'static str> {
+    let mut written = Vec::new();
+    collect_writes(&func.body, &mut written);
+    written
+}
+
+/// Callee-saved registers written anywhere in the body of `func`, at any width, in the
+/// order of `callee_saved` and named at the width of a word.
+pub fn clobbered(func: &NodeFunc, target: Target) -> Vec<&'static str> {
+    let written = written(func);
+    callee_saved(target).iter()
+        .filter(|name| written.contains(name))
+        .map(|full| target.register(full).name)
+        .collect()
+}
+
+/// Registers the prologue of `func` saves and its epilogue restores: the `uses` list when the
+/// function has one, the callee-saved registers its body writes otherwise. `_start` and
+/// `noreturn` functions never give control back, and `naked` functions manage it themselves.
+pub fn saved(func: &NodeFunc, target: Target) -> Vec<&'static str> {
+    if func.name.name == "_start" || func.noreturn || func.naked {
+        return Vec::new();
+    }
+    match &func.uses {
+        Some(uses) => callee_saved(target).iter()
+            .filter(|name| uses.iter().any(|used| registers::lookup(&used.name).is_some_and(|register| register.full == **name)))
+            .map(|full| target.register(full).name)
+            .collect(),
+        None => clobbered(func, target),
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{NodeConstBlock, NodeEnum, NodeExpr};
+use crate::tokenizer::Loc;
+
+enum Definition<'a> {
+    // An explicit `= value`, evaluated in the scope of its enum so members can refer to each other
+    Explicit(&'a NodeExpr, Option<&'a str>),
+    // Auto-numbered member: one more than (or the next flag after) the previous member
+    Auto(Option<String>, bool),
+}
+
+// Why a constant has no value: its expression is not constant, or the problem has been
+// reported already
+enum Failure {
+    NotConstant,
+    Reported,
+}
+
+struct Evaluator<'a> {
+    // Every definition with the member it was written at
+    definitions: HashMap<String, (Definition<'a>, &'a Loc)>,
+    values: HashMap<String, i64>,
+    visiting: HashSet<String>,
+    failed: HashSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn value_of(&mut self, name: &str) -> Result<i64, Failure> {
+        if let Some(value) = self.values.get(name) {
+            return Ok(*value);
+        }
+        if self.failed.contains(name) {
+            return Err(Failure::Reported);
+        }
+        let Some((_, loc)) = self.definitions.get(name) else {
+            return Err(Failure::NotConstant);
+        };
+        let loc = *loc;
+        if !self.visiting.insert(name.to_string()) {
+            self.diagnostics.push(Diagnostic::error(loc, format!("'{}' is defined in terms of itself", name)));
+            return Err(Failure::Reported);
+        }
+
+        let value = match &self.definitions[name].0 {
+            Definition::Explicit(expr, scope) => {
+                let (expr, scope) = (*expr, *scope);
+                self.eval(expr, scope).inspect_err(|failure| {
+                    if let Failure::NotConstant = failure {
+                        self.diagnostics.push(Diagnostic::error(loc, format!("value of '{}' is not a constant expression", name)));
+                    }
+                })
+            }
+            Definition::Auto(previous, flags) => {
+                let (previous, flags) = (previous.clone(), *flags);
+                match (previous, flags) {
+                    (None, false) => Ok(0),
+                    (None, true) => Ok(1),
+                    (Some(previous), flags) => {
+                        let next = self.value_of(&previous)
+                            .map(|value| if flags { next_flag(value) } else { value.checked_add(1) });
+                        match next {
+                            Ok(Some(value)) => Ok(value),
+                            Ok(None) => {
+                                let message = if flags {
+                                    format!("no bit left for '{}', '{}' already has the highest bit set", name, previous)
+                                } else {
+                                    format!("no value left for '{}', '{}' is already the largest number", name, previous)
+                                };
+                                self.diagnostics.push(Diagnostic::error(loc, message));
+                                Err(Failure::Reported)
+                            }
+                            Err(_) => Err(Failure::Reported),
+                        }
+                    }
+                }
+            }
+        };
+
+        self.visiting.remove(name);
+        match value {
+            Ok(value) => {
+                self.values.insert(name.to_string(), value);
+                Ok(value)
+            }
+            Err(_) => {
+                // Reported once, whatever is defined in terms of it is left out as well
+                self.failed.insert(name.to_string());
+                Err(Failure::Reported)
+            }
+        }
+    }
+
+    fn eval(&mut self, expr: &NodeExpr, scope: Option<&str>) -> Result<i64, Failure> {
+        match expr {
+            NodeExpr::Number(number) => Ok(number.value),
+            NodeExpr::Ident(ident) => {
+                if let Some(scope) = scope {
+                    let scoped = format!("{}.{}", scope, ident.name);
+                    if self.definitions.contains_key(&scoped) {
+                        return self.value_of(&scoped);
+                    }
+                }
+                self.value_of(&ident.name)
+            }
+            NodeExpr::BitOr(bit_or) => {
+                let left = self.eval(&bit_or.left, scope)?;
+                let right = self.eval(&bit_or.right, scope)?;
+                Ok(left | right)
+            }
+            _ => Err(Failure::NotConstant),
+        }
+    }
+}
+
+// The next power of two above the highest bit set so far, none once bit 63 is taken
+fn next_flag(previous: i64) -> Option<i64> {
+    match previous {
+        0 => Some(1),
+        previous if previous < 0 => None,
+        previous => Some(1 << (64 - (previous as u64).leading_zeros())),
+    }
+}
+
+/// Resolves every `enum` member and `const` to its integer value. Enum members are
+/// stored under their qualified name (`Name.MEMBER`), const block members unqualified.
+/// Members that cannot be given a value are left out and reported.
+pub fn collect_constants(enums: &[NodeEnum], consts: &[NodeConstBlock]) -> (HashMap<String, i64>, Vec<Diagnostic>) {
+    let mut definitions = HashMap::new();
+    let mut order = Vec::new();
+
+    for enumeration in enums {
+        let mut previous: Option<String> = None;
+        for member in &enumeration.members {
+            let name = format!("{}.{}", enumeration.name.name, member.ident.name);
+            let definition = match &member.value {
+                Some(value) => Definition::Explicit(value, Some(enumeration.name.name.as_str())),
+                None => Definition::Auto(previous.clone(), enumeration.flags),
+            };
+            // The resolver reports a member declared twice, the first one counts
+            order.push(name.clone());
+            definitions.entry(name.clone()).or_insert((definition, &member.ident.loc));
+            previous = Some(name);
+        }
+    }
+
+    for block in consts {
+        for member in &block.members {
+            let name = member.ident.name.clone();
+            let value = member.value.as_ref().unwrap();
+            order.push(name.clone());
+            definitions.entry(name).or_insert((Definition::Explicit(value, None), &member.ident.loc));
+        }
+    }
+
+    let mut evaluator = Evaluator {
+        definitions,
+        values: HashMap::new(),
+        visiting: HashSet::new(),
+        failed: HashSet::new(),
+        diagnostics: Vec::new(),
+    };
+    for name in &order {
+        let _ = evaluator.value_of(name);
+    }
+    (evaluator.values, evaluator.diagnostics)
+}
+
+/// Folds an expression made of numbers, known constants and `|` into a single value.
+pub fn eval_expr(expr: &NodeExpr, constants: &HashMap<String, i64>) -> Option<i64> {
+    match expr {
+        NodeExpr::Number(number) => Some(number.value),
+        NodeExpr::Ident(ident) => constants.get(&ident.name).copied(),
+        NodeExpr::BitOr(bit_or) => Some(eval_expr(&bit_or.left, constants)? | eval_expr(&bit_or.right, constants)?),
+        _ => None,
+    }
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+
+use crate::tokenizer::Loc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the source after parsing, reported as `file:line:column: error: message`
+/// (or `warning:`). Only errors stop the compilation.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub loc: Loc,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(loc: &Loc, message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, loc: loc.clone(), message }
+    }
+
+    pub fn warning(loc: &Loc, message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, loc: loc.clone(), message }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", self.loc, severity, self.message)
+    }
+}
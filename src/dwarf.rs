@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::assembler::{Relocation, RelocationKind, Section, Target};
+use crate::ir::{DebugFunction, DebugInfo, Parameter};
+use crate::parser::ParamType;
+use crate::target;
+use crate::tokenizer::Loc;
+
+const DW_TAG_FORMAL_PARAMETER: u8 = 0x05;
+const DW_TAG_POINTER_TYPE: u8 = 0x0f;
+const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+const DW_TAG_BASE_TYPE: u8 = 0x24;
+const DW_TAG_SUBPROGRAM: u8 = 0x2e;
+
+const DW_AT_LOCATION: u8 = 0x02;
+const DW_AT_NAME: u8 = 0x03;
+const DW_AT_BYTE_SIZE: u8 = 0x0b;
+const DW_AT_STMT_LIST: u8 = 0x10;
+const DW_AT_LOW_PC: u8 = 0x11;
+const DW_AT_HIGH_PC: u8 = 0x12;
+const DW_AT_LANGUAGE: u8 = 0x13;
+const DW_AT_COMP_DIR: u8 = 0x1b;
+const DW_AT_PRODUCER: u8 = 0x25;
+const DW_AT_DECL_FILE: u8 = 0x3a;
+const DW_AT_DECL_LINE: u8 = 0x3b;
+const DW_AT_ENCODING: u8 = 0x3e;
+const DW_AT_TYPE: u8 = 0x49;
+
+const DW_FORM_ADDR: u8 = 0x01;
+const DW_FORM_DATA2: u8 = 0x05;
+const DW_FORM_DATA4: u8 = 0x06;
+const DW_FORM_STRING: u8 = 0x08;
+const DW_FORM_DATA1: u8 = 0x0b;
+const DW_FORM_UDATA: u8 = 0x0f;
+const DW_FORM_REF4: u8 = 0x13;
+const DW_FORM_SEC_OFFSET: u8 = 0x17;
+const DW_FORM_EXPRLOC: u8 = 0x18;
+
+const DW_ATE_UNSIGNED: u8 = 0x07;
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+// `DW_OP_breg0` plus the DWARF number of the register
+const DW_OP_BREG0: u8 = 0x70;
+
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNS_SET_COLUMN: u8 = 0x05;
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+
+// Abbreviation codes of the entries in `.debug_info`
+const ABBREV_COMPILE_UNIT: u8 = 1;
+const ABBREV_SUBPROGRAM: u8 = 2;
+const ABBREV_PARAMETER: u8 = 3;
+// A parameter of a naked function, which has no frame to find it in
+const ABBREV_PARAMETER_WITHOUT_LOCATION: u8 = 4;
+const ABBREV_BASE_TYPE: u8 = 5;
+const ABBREV_POINTER_TYPE: u8 = 6;
+
+/// Where the code generated for a source position starts.
+#[derive(Debug)]
+pub struct Row {
+    pub section: usize,
+    pub offset: u64,
+    pub loc: Loc,
+}
+
+/// The code of a function, from its label up to the next item.
+#[derive(Debug)]
+pub struct Span {
+    pub section: usize,
+    pub start: u64,
+    pub end: u64,
+}
+
+// LEB128, seven bits a byte with the top bit set on all but the last
+fn uleb(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn sleb(mut value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        // Done once the rest is only copies of the sign bit of this byte
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// A section being written, with the relocations its addresses and offsets need.
+struct Writer {
+    section: Section,
+    address_size: usize,
+}
+
+impl Writer {
+    fn new(name: &str, target: target::Target) -> Writer {
+        let mut section = Section::new(name);
+        section.alignment = 1;
+        Writer { section, address_size: target.word_size().max(4) }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.section.data.extend_from_slice(bytes);
+        self.section.size = self.section.data.len() as u64;
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn uleb(&mut self, value: u64) {
+        self.bytes(&uleb(value));
+    }
+
+    fn sleb(&mut self, value: i64) {
+        self.bytes(&sleb(value));
+    }
+
+    fn string(&mut self, string: &str) {
+        self.bytes(string.as_bytes());
+        self.u8(0);
+    }
+
+    fn offset(&self) -> u64 {
+        self.section.size
+    }
+
+    // `section + offset`, filled in by whoever places the section
+    fn address(&mut self, section: usize, offset: u64) {
+        let kind = if self.address_size == 8 { RelocationKind::Absolute64 } else { RelocationKind::Absolute32 };
+        self.relocation(section, offset, kind);
+    }
+
+    // Offset of another debug section, 4 bytes in 32-bit DWARF whatever the target
+    fn section_offset(&mut self, section: usize) {
+        self.relocation(section, 0, RelocationKind::Absolute32);
+    }
+
+    fn relocation(&mut self, section: usize, addend: u64, kind: RelocationKind) {
+        let offset = self.offset();
+        self.section.relocations.push(Relocation { offset, target: Target::Section(section), kind, addend: addend as i64 });
+        self.bytes(&vec![0; kind.width()]);
+    }
+
+    // Fills in a length field at `at`, which counts the bytes that follow it
+    fn patch_length(&mut self, at: u64) {
+        let length = (self.offset() - at - 4) as u32;
+        self.section.data[at as usize..at as usize + 4].copy_from_slice(&length.to_le_bytes());
+    }
+}
+
+// Attribute and form pairs of an abbreviation, ended by a pair of zeroes
+fn abbreviation(writer: &mut Writer, code: u8, tag: u8, children: bool, attributes: &[(u8, u8)]) {
+    writer.uleb(code as u64);
+    writer.uleb(tag as u64);
+    writer.u8(children as u8);
+    for (attribute, form) in attributes {
+        writer.uleb(*attribute as u64);
+        writer.uleb(*form as u64);
+    }
+    writer.bytes(&[0, 0]);
+}
+
+fn debug_abbrev(target: target::Target) -> Section {
+    let mut writer = Writer::new(".debug_abbrev", target);
+    abbreviation(&mut writer, ABBREV_COMPILE_UNIT, DW_TAG_COMPILE_UNIT, true, &[
+        (DW_AT_PRODUCER, DW_FORM_STRING),
+        (DW_AT_LANGUAGE, DW_FORM_DATA2),
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_COMP_DIR, DW_FORM_STRING),
+        (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+        (DW_AT_HIGH_PC, DW_FORM_DATA4),
+    ]);
+    abbreviation(&mut writer, ABBREV_SUBPROGRAM, DW_TAG_SUBPROGRAM, true, &[
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_DECL_FILE, DW_FORM_UDATA),
+        (DW_AT_DECL_LINE, DW_FORM_UDATA),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+        (DW_AT_HIGH_PC, DW_FORM_DATA4),
+    ]);
+    abbreviation(&mut writer, ABBREV_PARAMETER, DW_TAG_FORMAL_PARAMETER, false, &[
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_TYPE, DW_FORM_REF4),
+        (DW_AT_LOCATION, DW_FORM_EXPRLOC),
+    ]);
+    abbreviation(&mut writer, ABBREV_PARAMETER_WITHOUT_LOCATION, DW_TAG_FORMAL_PARAMETER, false, &[
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_TYPE, DW_FORM_REF4),
+    ]);
+    abbreviation(&mut writer, ABBREV_BASE_TYPE, DW_TAG_BASE_TYPE, false, &[
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_ENCODING, DW_FORM_DATA1),
+        (DW_AT_BYTE_SIZE, DW_FORM_DATA1),
+    ]);
+    abbreviation(&mut writer, ABBREV_POINTER_TYPE, DW_TAG_POINTER_TYPE, false, &[
+        (DW_AT_BYTE_SIZE, DW_FORM_DATA1),
+    ]);
+    writer.u8(0);
+    writer.section
+}
+
+// The type of a parameter, an untyped one holds a whole word
+fn parameter_type(parameter: &Parameter, target: target::Target) -> ParamType {
+    parameter.param_type.unwrap_or(ParamType::Int(target.word_type()))
+}
+
+// DWARF number of the frame pointer
+fn frame_register(target: target::Target) -> u8 {
+    match target {
+        target::Target::X86_64 => 6,
+        target::Target::X86 | target::Target::X86_16 => 5,
+    }
+}
+
+struct Sections {
+    text: usize,
+    abbrev: usize,
+    line: usize,
+}
+
+fn debug_info(debug: &DebugInfo, spans: &HashMap<String, Span>, files: &[Rc<PathBuf>], sections: &[Section], indices: &Sections, target: target::Target) -> Section {
+    let mut writer = Writer::new(".debug_info", target);
+    writer.u32(0);
+    writer.u16(4);
+    writer.section_offset(indices.abbrev);
+    writer.u8(writer.address_size as u8);
+
+    writer.u8(ABBREV_COMPILE_UNIT);
+    writer.string(&format!("asmpp {}", env!("CARGO_PKG_VERSION")));
+    writer.u16(DW_LANG_MIPS_ASSEMBLER);
+    writer.string(&debug.source.display().to_string());
+    writer.string(&env::current_dir().map(|dir| dir.display().to_string()).unwrap_or_default());
+    writer.section_offset(indices.line);
+    writer.address(indices.text, 0);
+    writer.u32(sections[indices.text].size as u32);
+
+    // Every type a parameter uses, ahead of the functions that refer to them
+    let mut types: Vec<(ParamType, u32)> = Vec::new();
+    for parameter in debug.functions.iter().flat_map(|function| &function.parameters) {
+        let param_type = parameter_type(parameter, target);
+        if types.iter().any(|(known, _)| *known == param_type) {
+            continue;
+        }
+        types.push((param_type, writer.offset() as u32));
+        match param_type {
+            ParamType::Ptr => {
+                writer.u8(ABBREV_POINTER_TYPE);
+                writer.u8(target.word_size() as u8);
+            }
+            ParamType::Int(data_type) => {
+                writer.u8(ABBREV_BASE_TYPE);
+                writer.string(param_type.name());
+                writer.u8(DW_ATE_UNSIGNED);
+                writer.u8(data_type.size() as u8);
+            }
+        }
+    }
+
+    for function in &debug.functions {
+        // Functions that were not assembled, such as unused library code, have no address
+        let Some(span) = spans.get(&function.name) else {
+            continue;
+        };
+        subprogram(&mut writer, function, span, files, &types, target);
+    }
+    writer.u8(0);
+    writer.patch_length(0);
+    writer.section
+}
+
+fn subprogram(writer: &mut Writer, function: &DebugFunction, span: &Span, files: &[Rc<PathBuf>], types: &[(ParamType, u32)], target: target::Target) {
+    writer.u8(ABBREV_SUBPROGRAM);
+    writer.string(&function.name);
+    writer.uleb(file_number(files, &function.loc.file));
+    writer.uleb(function.loc.line as u64);
+    writer.address(span.section, span.start);
+    writer.u32((span.end - span.start) as u32);
+    for parameter in &function.parameters {
+        let param_type = parameter_type(parameter, target);
+        let type_offset = types.iter().find(|(known, _)| *known == param_type).map(|(_, offset)| *offset).unwrap();
+        match parameter.offset {
+            Some(offset) => {
+                writer.u8(ABBREV_PARAMETER);
+                writer.string(&parameter.name);
+                writer.u32(type_offset);
+                // `[rbp + offset]`
+                let mut location = vec![DW_OP_BREG0 + frame_register(target)];
+                location.extend(sleb(offset));
+                writer.uleb(location.len() as u64);
+                writer.bytes(&location);
+            }
+            None => {
+                writer.u8(ABBREV_PARAMETER_WITHOUT_LOCATION);
+                writer.string(&parameter.name);
+                writer.u32(type_offset);
+            }
+        }
+    }
+    writer.u8(0);
+}
+
+// Index of a file in the file table of the line program, which counts from 1
+fn file_number(files: &[Rc<PathBuf>], file: &Rc<PathBuf>) -> u64 {
+    files.iter().position(|known| known == file).unwrap() as u64 + 1
+}
+
+fn debug_line(rows: &[Row], files: &[Rc<PathBuf>], sections: &[Section], target: target::Target) -> Section {
+    let mut writer = Writer::new(".debug_line", target);
+    writer.u32(0);
+    writer.u16(4);
+    let header_length = writer.offset();
+    writer.u32(0);
+    // Minimum instruction length, operations per instruction, `is_stmt` by default
+    writer.bytes(&[1, 1, 1]);
+    // Line base, line range and the first special opcode, which are never used
+    writer.bytes(&[(-5i8) as u8, 14, 13]);
+    // Number of operands of each standard opcode
+    writer.bytes(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+    // No include directories, every file is named as it was given
+    writer.u8(0);
+    for file in files {
+        writer.string(&file.display().to_string());
+        writer.bytes(&[0, 0, 0]);
+    }
+    writer.u8(0);
+    writer.patch_length(header_length);
+
+    // A sequence for every section with code in it
+    let mut order: Vec<usize> = rows.iter().map(|row| row.section).collect();
+    order.sort();
+    order.dedup();
+    for section in order {
+        writer.bytes(&[0, 1 + writer.address_size as u8, DW_LNE_SET_ADDRESS]);
+        writer.address(section, 0);
+        let (mut address, mut file, mut line, mut column) = (0, 1, 1, 0);
+        for row in rows.iter().filter(|row| row.section == section) {
+            let row_file = file_number(files, &row.loc.file);
+            if row_file != file {
+                writer.u8(DW_LNS_SET_FILE);
+                writer.uleb(row_file);
+                file = row_file;
+            }
+            if row.loc.line as i64 != line {
+                writer.u8(DW_LNS_ADVANCE_LINE);
+                writer.sleb(row.loc.line as i64 - line);
+                line = row.loc.line as i64;
+            }
+            if row.loc.column as u64 != column {
+                writer.u8(DW_LNS_SET_COLUMN);
+                writer.uleb(row.loc.column as u64);
+                column = row.loc.column as u64;
+            }
+            if row.offset != address {
+                writer.u8(DW_LNS_ADVANCE_PC);
+                writer.uleb(row.offset - address);
+                address = row.offset;
+            }
+            writer.u8(DW_LNS_COPY);
+        }
+        // The sequence ends right after the last instruction of the section
+        writer.u8(DW_LNS_ADVANCE_PC);
+        writer.uleb(sections[section].size - address);
+        writer.bytes(&[0, 1, DW_LNE_END_SEQUENCE]);
+    }
+    writer.patch_length(0);
+    writer.section
+}
+
+/// DWARF 4 line table and description of the functions and their parameters of an assembled
+/// program, as `.debug_abbrev`, `.debug_info` and `.debug_line` to go after `sections`.
+pub fn sections(debug: &DebugInfo, rows: &[Row], spans: &HashMap<String, Span>, sections: &[Section], target: target::Target) -> Vec<Section> {
+    // The file the compilation started from comes first, as compilers do
+    let mut files = vec![Rc::new(debug.source.clone())];
+    for loc in rows.iter().map(|row| &row.loc).chain(debug.functions.iter().map(|function| &function.loc)) {
+        if !files.contains(&loc.file) {
+            files.push(loc.file.clone());
+        }
+    }
+    let indices = Sections {
+        text: sections.iter().position(|section| section.is_code()).unwrap_or(0),
+        abbrev: sections.len(),
+        line: sections.len() + 2,
+    };
+    vec![
+        debug_abbrev(target),
+        debug_info(debug, spans, &files, sections, &indices, target),
+        debug_line(rows, &files, sections, target),
+    ]
+}
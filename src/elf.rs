@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+
+use crate::assembler::{Object, RelocationKind, Section, Target};
+use crate::linker::{Executable, PAGE_SIZE};
+use crate::target;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHT_REL: u32 = 9;
+
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_32: u32 = 10;
+const R_X86_64_32S: u32 = 11;
+const R_X86_64_16: u32 = 12;
+const R_X86_64_PC16: u32 = 13;
+
+const R_386_32: u32 = 1;
+const R_386_PC32: u32 = 2;
+const R_386_16: u32 = 20;
+const R_386_PC16: u32 = 21;
+
+// x86-64 files are ELF64, x86 ones ELF32
+fn is_elf64(target: target::Target) -> bool {
+    target == target::Target::X86_64
+}
+
+/// Size of the ELF header.
+pub fn header_size(target: target::Target) -> u64 {
+    if is_elf64(target) { 64 } else { 52 }
+}
+
+pub fn program_header_size(target: target::Target) -> u64 {
+    if is_elf64(target) { 56 } else { 32 }
+}
+
+fn section_header_size(target: target::Target) -> u64 {
+    if is_elf64(target) { 64 } else { 40 }
+}
+
+fn symbol_size(target: target::Target) -> u64 {
+    if is_elf64(target) { 24 } else { 16 }
+}
+
+// ELF64 relocations carry their addend (`.rela`), ELF32 ones find it in the field (`.rel`)
+fn relocation_size(target: target::Target) -> u64 {
+    if is_elf64(target) { 24 } else { 8 }
+}
+
+/// Appends little-endian fields to a byte buffer.
+struct Writer {
+    bytes: Vec<u8>,
+    // Addresses, offsets and sizes take 8 bytes in ELF64 and 4 in ELF32
+    elf64: bool,
+}
+
+impl Writer {
+    fn new(target: target::Target) -> Writer {
+        Writer { bytes: Vec::new(), elf64: is_elf64(target) }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn word(&mut self, value: u64) {
+        if self.elf64 {
+            self.u64(value);
+        } else {
+            self.u32(value as u32);
+        }
+    }
+
+    fn align(&mut self, alignment: u64) {
+        while !(self.bytes.len() as u64).is_multiple_of(alignment) {
+            self.bytes.push(0);
+        }
+    }
+}
+
+/// A string table, `\0` separated with an empty string at offset 0.
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> StringTable {
+        StringTable { bytes: vec![0] }
+    }
+
+    fn add(&mut self, string: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(string.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+/// `e_ident` up to and including the class, little-endian and version fields.
+fn identification(writer: &mut Writer) {
+    let class = if writer.elf64 { 2 } else { 1 };
+    writer.bytes.extend_from_slice(&[0x7f, b'E', b'L', b'F', class, 1, 1, 0]);
+    writer.bytes.extend_from_slice(&[0; 8]);
+}
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    alignment: u64,
+    entry_size: u64,
+}
+
+impl SectionHeader {
+    fn write(&self, writer: &mut Writer) {
+        writer.u32(self.name);
+        writer.u32(self.kind);
+        writer.word(self.flags);
+        writer.word(self.address);
+        writer.word(self.offset);
+        writer.word(self.size);
+        writer.u32(self.link);
+        writer.u32(self.info);
+        writer.word(self.alignment);
+        writer.word(self.entry_size);
+    }
+}
+
+fn null_section() -> SectionHeader {
+    SectionHeader { name: 0, kind: 0, flags: 0, address: 0, offset: 0, size: 0, link: 0, info: 0, alignment: 0, entry_size: 0 }
+}
+
+fn relocation_type(kind: RelocationKind, target: &Target, machine: target::Target) -> u32 {
+    if !is_elf64(machine) {
+        return match kind {
+            RelocationKind::Relative32 => R_386_PC32,
+            RelocationKind::Absolute16 => R_386_16,
+            RelocationKind::Relative16 => R_386_PC16,
+            _ => R_386_32,
+        };
+    }
+    match (kind, target) {
+        (RelocationKind::Absolute64, _) => R_X86_64_64,
+        (RelocationKind::Absolute32, _) => R_X86_64_32,
+        (RelocationKind::Absolute32Signed, _) => R_X86_64_32S,
+        // Calls to symbols of other objects may end up going through a PLT
+        (RelocationKind::Relative32, Target::Symbol(_)) => R_X86_64_PLT32,
+        (RelocationKind::Relative32, Target::Section(_)) => R_X86_64_PC32,
+        (RelocationKind::Absolute16, _) => R_X86_64_16,
+        (RelocationKind::Relative16, _) => R_X86_64_PC16,
+    }
+}
+
+// The symbol table of a file, with the index of every global symbol
+struct Symbols {
+    table: Writer,
+    strings: StringTable,
+    count: u64,
+    first_global: u64,
+    globals: HashMap<String, u64>,
+}
+
+impl Symbols {
+    fn add(&mut self, name: u32, info: u8, section: u16, value: u64) {
+        self.table.u32(name);
+        if self.table.elf64 {
+            self.table.u8(info);
+            self.table.u8(0);
+            self.table.u16(section);
+            self.table.u64(value);
+            self.table.u64(0);
+        } else {
+            self.table.u32(value as u32);
+            self.table.u32(0);
+            self.table.u8(info);
+            self.table.u8(0);
+            self.table.u16(section);
+        }
+        self.count += 1;
+    }
+}
+
+// Null, one symbol per section, the local labels and then the global ones. `addresses` are
+// those of the sections, all zero in an object file
+fn symbol_table(object: &Object, addresses: &[u64]) -> Symbols {
+    let mut symbols = Symbols { table: Writer::new(object.target), strings: StringTable::new(), count: 0, first_global: 0, globals: HashMap::new() };
+    symbols.add(0, 0, 0, 0);
+    for (index, address) in addresses.iter().enumerate() {
+        symbols.add(0, STB_LOCAL << 4 | STT_SECTION, index as u16 + 1, *address);
+    }
+    for global in [false, true] {
+        if global {
+            symbols.first_global = symbols.count;
+        }
+        for symbol in object.symbols.iter().filter(|symbol| symbol.global == global) {
+            if global {
+                symbols.globals.insert(symbol.name.clone(), symbols.count);
+            }
+            let (section, value) = match symbol.section {
+                Some(section) => (section as u16 + 1, addresses[section] + symbol.offset),
+                None => (0, 0),
+            };
+            let name = symbols.strings.add(&symbol.name);
+            let bind = if global { STB_GLOBAL } else { STB_LOCAL };
+            symbols.add(name, bind << 4 | STT_NOTYPE, section, value);
+        }
+    }
+    symbols
+}
+
+fn section_header(section: &Section, name: u32, address: u64, offset: u64) -> SectionHeader {
+    let flags = if section.is_debug() { 0 } else { SHF_ALLOC }
+        | if section.is_code() { SHF_EXECINSTR } else { 0 }
+        | if section.is_writable() { SHF_WRITE } else { 0 };
+    SectionHeader {
+        name,
+        kind: if section.bss { SHT_NOBITS } else { SHT_PROGBITS },
+        flags,
+        address,
+        offset,
+        size: section.size,
+        link: 0,
+        info: 0,
+        alignment: section.alignment,
+        entry_size: 0,
+    }
+}
+
+// What the ELF header says about the file besides its sections
+struct FileKind {
+    kind: u16,
+    entry: u64,
+    program_headers: u16,
+}
+
+// Appends the symbol and string tables and the section headers, then fills in the ELF header
+// that was left blank at the start of `writer`
+fn finish(mut writer: Writer, mut headers: Vec<SectionHeader>, mut names: StringTable, symbols: Symbols, file: FileKind, target: target::Target) -> Vec<u8> {
+    let symtab_index = headers.len() as u32;
+    let word_size = target.word_size() as u64;
+    writer.align(word_size);
+    headers.push(SectionHeader {
+        name: names.add(".symtab"),
+        kind: SHT_SYMTAB,
+        flags: 0,
+        address: 0,
+        offset: writer.bytes.len() as u64,
+        size: symbols.count * symbol_size(target),
+        link: symtab_index + 1,
+        info: symbols.first_global as u32,
+        alignment: word_size,
+        entry_size: symbol_size(target),
+    });
+    writer.bytes.extend_from_slice(&symbols.table.bytes);
+
+    headers.push(SectionHeader {
+        name: names.add(".strtab"),
+        kind: SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: writer.bytes.len() as u64,
+        size: symbols.strings.bytes.len() as u64,
+        link: 0,
+        info: 0,
+        alignment: 1,
+        entry_size: 0,
+    });
+    writer.bytes.extend_from_slice(&symbols.strings.bytes);
+
+    let shstrtab_name = names.add(".shstrtab");
+    headers.push(SectionHeader {
+        name: shstrtab_name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: writer.bytes.len() as u64,
+        size: names.bytes.len() as u64,
+        link: 0,
+        info: 0,
+        alignment: 1,
+        entry_size: 0,
+    });
+    writer.bytes.extend_from_slice(&names.bytes);
+
+    writer.align(word_size);
+    let section_headers = writer.bytes.len() as u64;
+    for header in &headers {
+        header.write(&mut writer);
+    }
+
+    let mut header = Writer::new(target);
+    identification(&mut header);
+    header.u16(file.kind);
+    header.u16(if is_elf64(target) { EM_X86_64 } else { EM_386 });
+    header.u32(1);
+    header.word(file.entry);
+    header.word(if file.program_headers > 0 { header_size(target) } else { 0 });
+    header.word(section_headers);
+    header.u32(0);
+    header.u16(header_size(target) as u16);
+    header.u16(if file.program_headers > 0 { program_header_size(target) as u16 } else { 0 });
+    header.u16(file.program_headers);
+    header.u16(section_header_size(target) as u16);
+    header.u16(headers.len() as u16);
+    header.u16(headers.len() as u16 - 1);
+    writer.bytes[..header_size(target) as usize].copy_from_slice(&header.bytes);
+
+    writer.bytes
+}
+
+/// Writes an object as a relocatable ELF64 file for x86-64 or ELF32 file for x86, as
+/// `nasm -f elf64` or `nasm -f elf32` would.
+pub fn write_object(object: &Object) -> Vec<u8> {
+    let target = object.target;
+    let mut names = StringTable::new();
+    let symbols = symbol_table(object, &vec![0; object.sections.len()]);
+
+    let mut writer = Writer::new(target);
+    writer.bytes.resize(header_size(target) as usize, 0);
+    let mut headers = vec![null_section()];
+
+    for section in &object.sections {
+        writer.align(section.alignment);
+        let offset = writer.bytes.len() as u64;
+        writer.bytes.extend_from_slice(&section.data);
+        // `.rel` has no room for the addends, the linker reads them from the fields
+        if !is_elf64(target) {
+            for relocation in &section.relocations {
+                let start = (offset + relocation.offset) as usize;
+                let width = relocation.kind.width();
+                writer.bytes[start..start + width].copy_from_slice(&relocation.addend.to_le_bytes()[..width]);
+            }
+        }
+        headers.push(section_header(section, names.add(&section.name), 0, offset));
+    }
+
+    // One `.rela` or `.rel` section for every section with relocations, the symbol table
+    // follows them
+    let relocated: Vec<usize> = (0..object.sections.len()).filter(|index| !object.sections[*index].relocations.is_empty()).collect();
+    let symtab_index = headers.len() + relocated.len();
+    let (prefix, kind) = if is_elf64(target) { (".rela", SHT_RELA) } else { (".rel", SHT_REL) };
+    for index in relocated {
+        let section = &object.sections[index];
+        writer.align(target.word_size() as u64);
+        let offset = writer.bytes.len() as u64;
+        for relocation in &section.relocations {
+            let symbol = match &relocation.target {
+                Target::Section(target) => 1 + *target as u64,
+                Target::Symbol(name) => symbols.globals[name],
+            };
+            let kind = relocation_type(relocation.kind, &relocation.target, target) as u64;
+            if is_elf64(target) {
+                writer.u64(relocation.offset);
+                writer.u64(symbol << 32 | kind);
+                writer.u64(relocation.addend as u64);
+            } else {
+                writer.u32(relocation.offset as u32);
+                writer.u32((symbol << 8 | kind) as u32);
+            }
+        }
+        headers.push(SectionHeader {
+            name: names.add(&format!("{}{}", prefix, section.name)),
+            kind,
+            flags: SHF_INFO_LINK,
+            address: 0,
+            offset,
+            size: section.relocations.len() as u64 * relocation_size(target),
+            link: symtab_index as u32,
+            info: index as u32 + 1,
+            alignment: target.word_size() as u64,
+            entry_size: relocation_size(target),
+        });
+    }
+
+    finish(writer, headers, names, symbols, FileKind { kind: ET_REL, entry: 0, program_headers: 0 }, target)
+}
+
+/// Writes a linked program as a static ELF executable, with its sections and symbols kept
+/// for debuggers and `objdump`.
+pub fn write_executable(executable: &Executable) -> Vec<u8> {
+    let object = &executable.object;
+    let target = object.target;
+    let mut names = StringTable::new();
+    let symbols = symbol_table(object, &executable.addresses);
+
+    let mut writer = Writer::new(target);
+    writer.bytes.resize(header_size(target) as usize, 0);
+    for segment in &executable.segments {
+        let flags = PF_R
+            | if segment.writable { PF_W } else { 0 }
+            | if segment.executable { PF_X } else { 0 };
+        writer.u32(PT_LOAD);
+        // ELF64 moved the flags up to keep the wider fields aligned
+        if writer.elf64 {
+            writer.u32(flags);
+        }
+        writer.word(segment.offset);
+        writer.word(segment.address);
+        writer.word(segment.address);
+        writer.word(segment.file_size);
+        writer.word(segment.memory_size);
+        if !writer.elf64 {
+            writer.u32(flags);
+        }
+        writer.word(PAGE_SIZE);
+    }
+
+    // Sections are laid out by segment rather than in the order they were declared, so every
+    // one is copied to its own offset in a buffer that holds them all
+    let end = object.sections.iter().enumerate()
+        .filter(|(_, section)| !section.bss)
+        .map(|(index, section)| executable.offsets[index] as usize + section.data.len())
+        .max()
+        .unwrap_or(0);
+    writer.bytes.resize(end.max(writer.bytes.len()), 0);
+    let mut headers = vec![null_section()];
+    for (index, section) in object.sections.iter().enumerate() {
+        let offset = executable.offsets[index];
+        if !section.bss {
+            writer.bytes[offset as usize..offset as usize + section.data.len()].copy_from_slice(&section.data);
+        }
+        headers.push(section_header(section, names.add(&section.name), executable.addresses[index], offset));
+    }
+
+    let file = FileKind { kind: ET_EXEC, entry: executable.entry, program_headers: executable.segments.len() as u16 };
+    finish(writer, headers, names, symbols, file, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{Section, Symbol};
+    use crate::linker;
+
+    fn section(name: &str, data: &[u8]) -> Section {
+        let mut section = Section::new(name);
+        section.data = data.to_vec();
+        section.size = data.len() as u64;
+        section
+    }
+
+    // `.data` is declared before `.rodata` but loaded after it, in a later segment
+    #[test]
+    fn sections_are_written_at_their_offsets_whatever_their_order() {
+        for target in [target::Target::X86_64, target::Target::X86] {
+            let object = Object {
+                sections: vec![
+                    section(".text", &[0xC3]),
+                    section(".data", b"status: ok\n"),
+                    section(".rodata", b"ro\n"),
+                    section(".bss", &[]),
+                ],
+                symbols: vec![Symbol { name: "_start".to_string(), section: Some(0), offset: 0, global: true }],
+                target,
+                origin: None,
+            };
+            let executable = linker::link(object, "_start").unwrap();
+            assert!(executable.offsets[1] > executable.offsets[2]);
+
+            let bytes = write_executable(&executable);
+            for (index, section) in executable.object.sections.iter().enumerate() {
+                let offset = executable.offsets[index] as usize;
+                assert_eq!(&bytes[offset..offset + section.data.len()], &section.data[..], "{} of {:?}", section.name, target);
+            }
+        }
+    }
+}
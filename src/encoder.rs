@@ -0,0 +1,746 @@
+use crate::ir::{Condition, Instruction, Memory, Opcode, Operand, Term, Value};
+use crate::registers::Register;
+
+/// How a value that is only known after layout is filled into the instruction bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FixupKind {
+    // The value itself, sign-extended by the CPU when `signed`
+    Absolute { signed: bool },
+    // The distance from the end of the instruction to the value
+    Relative,
+}
+
+/// A field of `width` bytes at `offset` into the instruction that holds the sum of `terms`.
+#[derive(Debug, Clone)]
+pub struct Fixup {
+    pub offset: usize,
+    pub width: usize,
+    pub terms: Vec<Term>,
+    pub kind: FixupKind,
+}
+
+/// Machine code of one instruction, with the fields that still need a value.
+#[derive(Debug, Default)]
+pub struct Encoded {
+    pub bytes: Vec<u8>,
+    pub fixups: Vec<Fixup>,
+}
+
+impl Encoded {
+    fn immediate(&mut self, value: &Value, width: usize, signed: bool) -> Result<(), String> {
+        match value {
+            Value::Number(number) => {
+                if !fits(*number, width, signed) {
+                    return Err(format!("{} does not fit in a {}-bit immediate", number, width * 8));
+                }
+                self.bytes.extend_from_slice(&number.to_le_bytes()[..width]);
+            }
+            value => self.fixup(vec![Term { negative: false, value: value.clone() }], width, FixupKind::Absolute { signed }),
+        }
+        Ok(())
+    }
+
+    fn fixup(&mut self, terms: Vec<Term>, width: usize, kind: FixupKind) {
+        self.fixups.push(Fixup { offset: self.bytes.len(), width, terms, kind });
+        self.bytes.extend(std::iter::repeat_n(0, width));
+    }
+}
+
+/// Whether `value` can be stored in `width` bytes, sign-extended when `signed`, and either
+/// way when not.
+pub fn fits(value: i64, width: usize, signed: bool) -> bool {
+    if width >= 8 {
+        return true;
+    }
+    let bits = width as u32 * 8;
+    let min = -(1i64 << (bits - 1));
+    let max = if signed { (1i64 << (bits - 1)) - 1 } else { (1i64 << bits) - 1 };
+    value >= min && value <= max
+}
+
+/// Number of a register in ModRM, SIB and opcode fields, bit 3 goes into a REX prefix.
+fn number(register: &Register) -> u8 {
+    let low = match register.full {
+        "rax" => 0,
+        "rcx" => 1,
+        "rdx" => 2,
+        "rbx" => 3,
+        "rsp" => 4,
+        "rbp" => 5,
+        "rsi" => 6,
+        "rdi" => 7,
+        full => return full[1..].parse().unwrap(),
+    };
+    // `ah`, `ch`, `dh` and `bh` take the numbers of `spl` ... `dil` when there is no REX prefix
+    if register.high_byte { low + 4 } else { low }
+}
+
+// Registers that force a REX prefix although none of its bits is set
+fn needs_rex(register: &Register) -> bool {
+    register.rex && register.size == 1
+}
+
+// Whether an operation on `size` bytes needs the 66 prefix, which switches between 16 and 32
+// bits: 32 is the default everywhere but in 16-bit code
+fn operand_prefix(size: usize, bits: u32) -> bool {
+    match size {
+        2 => bits != 16,
+        4 => bits == 16,
+        _ => false,
+    }
+}
+
+/// The r/m operand of a ModRM byte.
+enum Rm<'a> {
+    Register(&'static Register),
+    Memory(&'a Memory),
+}
+
+/// ModRM byte, SIB and displacement for `reg` and `rm`, with the REX bits they need.
+struct Addressing {
+    rex: u8,
+    bytes: Vec<u8>,
+    // Displacement that is only known after layout, at an offset into `bytes`
+    fixup: Option<Fixup>,
+}
+
+fn addressing(reg: u8, rm: &Rm, bits: u32) -> Result<Addressing, String> {
+    let reg_bits = (reg & 7) << 3;
+    let mut rex = if reg & 8 != 0 { 0b100 } else { 0 };
+    let memory = match rm {
+        Rm::Register(register) => {
+            let rm = number(register);
+            if rm & 8 != 0 {
+                rex |= 0b001;
+            }
+            return Ok(Addressing { rex, bytes: vec![0b1100_0000 | reg_bits | (rm & 7)], fixup: None });
+        }
+        Rm::Memory(memory) => memory,
+    };
+
+    for register in memory.base.iter().chain(memory.index.iter()) {
+        if register.bits() != bits as usize {
+            return Err(format!("'{}' cannot be used in an address, only {}-bit registers can", register.name, bits));
+        }
+    }
+    if bits == 16 {
+        return addressing16(reg_bits, memory);
+    }
+
+    let (constant, symbolic) = split_displacement(&memory.displacement);
+    let mut bytes = Vec::new();
+    // 0 for no displacement, 1 for disp8, 4 for disp32
+    let displacement_size;
+
+    match (memory.base, memory.index) {
+        // [disp32] goes through a SIB byte without base and index in 64-bit code, plain
+        // mod 00 rm 101 would be relative to rip there
+        (None, None) if bits == 64 => {
+            bytes.push(reg_bits | 0b100);
+            bytes.push(0b00_100_101);
+            displacement_size = 4;
+        }
+        (None, None) => {
+            bytes.push(reg_bits | 0b101);
+            displacement_size = 4;
+        }
+        (Some(base), index) => {
+            let base_number = number(base);
+            if base_number & 8 != 0 {
+                rex |= 0b001;
+            }
+            displacement_size = match (symbolic, constant) {
+                (false, 0) if base_number & 7 != 5 => 0,
+                (false, constant) if fits(constant, 1, true) => 1,
+                _ => 4,
+            };
+            let mode = match displacement_size {
+                0 => 0b00,
+                1 => 0b01,
+                _ => 0b10,
+            };
+            match index {
+                Some(index) => {
+                    let index_number = number(index);
+                    if index.full == "rsp" {
+                        return Err(format!("'{}' cannot be used as an index register", index.name));
+                    }
+                    if index_number & 8 != 0 {
+                        rex |= 0b010;
+                    }
+                    bytes.push(mode << 6 | reg_bits | 0b100);
+                    bytes.push((index_number & 7) << 3 | (base_number & 7));
+                }
+                // rsp and r12 as base can only be encoded with a SIB byte
+                None if base_number & 7 == 4 => {
+                    bytes.push(mode << 6 | reg_bits | 0b100);
+                    bytes.push(0b00_100_100);
+                }
+                None => bytes.push(mode << 6 | reg_bits | (base_number & 7)),
+            }
+        }
+        (None, Some(_)) => unreachable!("an index register always comes with a base"),
+    }
+
+    let mut fixup = None;
+    match displacement_size {
+        0 => {}
+        1 => bytes.push(constant as u8),
+        _ if symbolic => {
+            fixup = Some(Fixup { offset: bytes.len(), width: 4, terms: memory.displacement.clone(), kind: FixupKind::Absolute { signed: true } });
+            bytes.extend([0; 4]);
+        }
+        _ => {
+            if !fits(constant, 4, true) {
+                return Err(format!("displacement {} does not fit in 32 bits", constant));
+            }
+            bytes.extend_from_slice(&(constant as i32).to_le_bytes());
+        }
+    }
+    Ok(Addressing { rex, bytes, fixup })
+}
+
+// 16-bit addresses have no SIB byte, only a base of `bx` or `bp`, an index of `si` or `di`,
+// or one of each
+fn addressing16(reg_bits: u8, memory: &Memory) -> Result<Addressing, String> {
+    let (mut base, mut index) = (None, None);
+    for register in memory.base.iter().chain(memory.index.iter()) {
+        match register.name {
+            "bx" | "bp" if base.is_none() => base = Some(register.name),
+            "si" | "di" if index.is_none() => index = Some(register.name),
+            _ => return Err(format!("'{}' cannot be used here in a 16-bit address, only bx or bp plus si or di can", register.name)),
+        }
+    }
+    let rm = match (base, index) {
+        (Some("bx"), Some("si")) => 0b000,
+        (Some("bx"), Some("di")) => 0b001,
+        (Some("bp"), Some("si")) => 0b010,
+        (Some("bp"), Some("di")) => 0b011,
+        (None, Some("si")) => 0b100,
+        (None, Some("di")) => 0b101,
+        (Some("bp"), None) => 0b110,
+        (Some("bx"), None) => 0b111,
+        // mod 00 rm 110 is a plain [disp16], `[bp]` takes a zero disp8 instead
+        _ => 0b110,
+    };
+
+    let (constant, symbolic) = split_displacement(&memory.displacement);
+    let displacement_size = match (base, index, symbolic, constant) {
+        (None, None, ..) => 2,
+        (_, _, false, 0) if base != Some("bp") || index.is_some() => 0,
+        (_, _, false, constant) if fits(constant, 1, true) => 1,
+        _ => 2,
+    };
+    let mode = match (base, index, displacement_size) {
+        (None, None, _) | (_, _, 0) => 0b00,
+        (_, _, 1) => 0b01,
+        _ => 0b10,
+    };
+    let mut bytes = vec![mode << 6 | reg_bits | rm];
+    let mut fixup = None;
+    match displacement_size {
+        0 => {}
+        1 => bytes.push(constant as u8),
+        _ if symbolic => {
+            fixup = Some(Fixup { offset: bytes.len(), width: 2, terms: memory.displacement.clone(), kind: FixupKind::Absolute { signed: false } });
+            bytes.extend([0; 2]);
+        }
+        _ => {
+            if !fits(constant, 2, false) {
+                return Err(format!("displacement {} does not fit in 16 bits", constant));
+            }
+            bytes.extend_from_slice(&(constant as u16).to_le_bytes());
+        }
+    }
+    Ok(Addressing { rex: 0, bytes, fixup })
+}
+
+// Sum of the numeric terms of a displacement, and whether any term still needs a value
+fn split_displacement(terms: &[Term]) -> (i64, bool) {
+    let mut constant = 0i64;
+    let mut symbolic = false;
+    for term in terms {
+        match term.value {
+            Value::Number(number) if term.negative => constant = constant.wrapping_sub(number),
+            Value::Number(number) => constant = constant.wrapping_add(number),
+            _ => symbolic = true,
+        }
+    }
+    (constant, symbolic)
+}
+
+/// `[66] [REX] opcode ModRM [SIB] [displacement]` for an operation of `size` bytes.
+fn modrm(size: usize, opcode: &[u8], reg: u8, rm: Rm, force_rex: bool, bits: u32) -> Result<Encoded, String> {
+    let addressing = addressing(reg, &rm, bits)?;
+    let mut encoded = Encoded::default();
+    if operand_prefix(size, bits) {
+        encoded.bytes.push(0x66);
+    }
+    let rex = addressing.rex | if size == 8 { 0b1000 } else { 0 };
+    if rex != 0 || force_rex {
+        encoded.bytes.push(0x40 | rex);
+    }
+    encoded.bytes.extend_from_slice(opcode);
+    if let Some(fixup) = addressing.fixup {
+        encoded.fixups.push(Fixup { offset: encoded.bytes.len() + fixup.offset, ..fixup });
+    }
+    encoded.bytes.extend(addressing.bytes);
+    Ok(encoded)
+}
+
+/// `[66] [REX] opcode+reg`, the short forms of push, pop and mov with an immediate.
+fn short_form(size: usize, opcode: u8, register: &Register, wide: bool, bits: u32) -> Encoded {
+    let mut encoded = Encoded::default();
+    if operand_prefix(size, bits) {
+        encoded.bytes.push(0x66);
+    }
+    let reg = number(register);
+    let rex = if wide { 0b1000 } else { 0 } | if reg & 8 != 0 { 0b001 } else { 0 };
+    if rex != 0 || needs_rex(register) {
+        encoded.bytes.push(0x40 | rex);
+    }
+    encoded.bytes.push(opcode + (reg & 7));
+    encoded
+}
+
+fn check_high_byte(registers: &[&Register], rex: bool) -> Result<(), String> {
+    if rex {
+        if let Some(high) = registers.iter().find(|register| register.high_byte) {
+            return Err(format!("'{}' cannot be used in an instruction that needs a REX prefix", high.name));
+        }
+    }
+    Ok(())
+}
+
+// The `/digit` of the 80/81/83 group for an operation with an immediate
+fn group_digit(opcode: Opcode) -> u8 {
+    match opcode {
+        Opcode::Add => 0,
+        Opcode::Sub => 5,
+        Opcode::Xor => 6,
+        Opcode::Cmp => 7,
+        Opcode::Mov => unreachable!("mov has its own immediate forms"),
+    }
+}
+
+// Opcode of the `r/m, reg` form for 8-bit operands, the wider form is the next one and the
+// `reg, r/m` forms follow two later
+fn rm_reg_opcode(opcode: Opcode) -> u8 {
+    match opcode {
+        Opcode::Mov => 0x88,
+        Opcode::Add => 0x00,
+        Opcode::Sub => 0x28,
+        Opcode::Xor => 0x30,
+        Opcode::Cmp => 0x38,
+    }
+}
+
+fn binary(opcode: Opcode, dest: &Operand, src: &Operand, bits: u32) -> Result<Encoded, String> {
+    let wide = |size: usize| if size > 1 { 1 } else { 0 };
+    match (dest, src) {
+        (Operand::Register(dest), Operand::Register(src)) => {
+            if dest.size != src.size {
+                return Err(format!("'{}' and '{}' differ in size", dest.name, src.name));
+            }
+            let force_rex = needs_rex(dest) || needs_rex(src);
+            check_high_byte(&[dest, src], force_rex || number(dest) & 8 != 0 || number(src) & 8 != 0)?;
+            modrm(dest.size, &[rm_reg_opcode(opcode) + wide(dest.size)], number(src), Rm::Register(dest), force_rex, bits)
+        }
+        (Operand::Memory(memory), Operand::Register(src)) => {
+            check_memory_size(memory, src.size)?;
+            let encoded = modrm(src.size, &[rm_reg_opcode(opcode) + wide(src.size)], number(src), Rm::Memory(memory), needs_rex(src), bits)?;
+            check_high_byte(&[src], has_rex(&encoded, src.size, bits))?;
+            Ok(encoded)
+        }
+        (Operand::Register(dest), Operand::Memory(memory)) => {
+            check_memory_size(memory, dest.size)?;
+            let encoded = modrm(dest.size, &[rm_reg_opcode(opcode) + 2 + wide(dest.size)], number(dest), Rm::Memory(memory), needs_rex(dest), bits)?;
+            check_high_byte(&[dest], has_rex(&encoded, dest.size, bits))?;
+            Ok(encoded)
+        }
+        (Operand::Register(dest), Operand::Immediate(value)) if opcode == Opcode::Mov => mov_immediate(dest, value, bits),
+        (Operand::Register(dest), Operand::Immediate(value)) => {
+            let encoded = group_immediate(opcode, dest.size, Rm::Register(dest), value, needs_rex(dest), bits)?;
+            check_high_byte(&[dest], has_rex(&encoded, dest.size, bits))?;
+            Ok(encoded)
+        }
+        (Operand::Memory(memory), Operand::Immediate(value)) => {
+            let size = memory.size.map(|size| size.size())
+                .ok_or_else(|| format!("size of the memory operand of '{}' is not known, add byte/word/dword/qword", opcode.mnemonic()))?;
+            if size == 8 && bits != 64 {
+                return Err(format!("'{}' cannot work on 64 bits in {}-bit code", opcode.mnemonic(), bits));
+            }
+            if opcode == Opcode::Mov {
+                let mut encoded = modrm(size, &[0xc6 + wide(size)], 0, Rm::Memory(memory), false, bits)?;
+                encoded.immediate(value, size.min(4), size == 8)?;
+                Ok(encoded)
+            } else {
+                group_immediate(opcode, size, Rm::Memory(memory), value, false, bits)
+            }
+        }
+        (Operand::Memory(_), Operand::Memory(_)) => Err(format!("'{}' cannot take two memory operands", opcode.mnemonic())),
+        (Operand::Immediate(_), _) => Err(format!("the destination of '{}' cannot be an immediate", opcode.mnemonic())),
+    }
+}
+
+fn check_memory_size(memory: &Memory, size: usize) -> Result<(), String> {
+    match memory.size {
+        Some(data_type) if data_type.size() != size => {
+            Err(format!("a {}-bit memory operand cannot be used with a {}-bit register", data_type.size() * 8, size * 8))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Whether the encoding carries a REX prefix, which rules out `ah` ... `dh`
+fn has_rex(encoded: &Encoded, size: usize, bits: u32) -> bool {
+    let prefix = if operand_prefix(size, bits) { 1 } else { 0 };
+    bits == 64 && encoded.bytes.get(prefix).is_some_and(|byte| byte & 0xf0 == 0x40)
+}
+
+// 80 /digit ib, 83 /digit ib or 81 /digit iw/id
+fn group_immediate(opcode: Opcode, size: usize, rm: Rm, value: &Value, force_rex: bool, bits: u32) -> Result<Encoded, String> {
+    let digit = group_digit(opcode);
+    match value {
+        _ if size == 1 => {
+            let mut encoded = modrm(size, &[0x80], digit, rm, force_rex, bits)?;
+            encoded.immediate(value, 1, false)?;
+            Ok(encoded)
+        }
+        Value::Number(number) if fits(*number, 1, true) => {
+            let mut encoded = modrm(size, &[0x83], digit, rm, force_rex, bits)?;
+            encoded.immediate(value, 1, true)?;
+            Ok(encoded)
+        }
+        _ => {
+            let mut encoded = modrm(size, &[0x81], digit, rm, force_rex, bits)?;
+            encoded.immediate(value, size.min(4), size == 8)?;
+            Ok(encoded)
+        }
+    }
+}
+
+fn mov_immediate(dest: &'static Register, value: &Value, bits: u32) -> Result<Encoded, String> {
+    let mut encoded;
+    match (dest.size, value) {
+        // A 32-bit write clears the upper half, so values up to 2^32 - 1 need no REX.W
+        (8, Value::Number(number)) if fits(*number, 4, false) && *number >= 0 => {
+            encoded = short_form(4, 0xb8, dest, false, bits);
+            encoded.immediate(value, 4, false)?;
+        }
+        (8, Value::Number(number)) if fits(*number, 4, true) => {
+            encoded = modrm(8, &[0xc7], 0, Rm::Register(dest), false, bits)?;
+            encoded.immediate(value, 4, true)?;
+        }
+        // Addresses and large numbers take the full 64-bit immediate
+        (8, _) => {
+            encoded = short_form(8, 0xb8, dest, true, bits);
+            encoded.immediate(value, 8, false)?;
+        }
+        (1, _) => {
+            encoded = short_form(1, 0xb0, dest, false, bits);
+            check_high_byte(&[dest], has_rex(&encoded, 1, bits))?;
+            encoded.immediate(value, 1, false)?;
+        }
+        (size, _) => {
+            encoded = short_form(size, 0xb8, dest, false, bits);
+            encoded.immediate(value, size, false)?;
+        }
+    }
+    Ok(encoded)
+}
+
+// Whether push and pop take the register: 16-bit registers and those of the width of a
+// word, and in 16-bit code also the 32-bit ones
+fn stack_register(register: &Register, bits: u32) -> bool {
+    register.size == 2 || register.bits() == bits as usize || (bits == 16 && register.size == 4)
+}
+
+fn stack_widths(bits: u32) -> String {
+    match bits {
+        16 => "16-bit and 32-bit".to_string(),
+        bits => format!("{}-bit and 16-bit", bits),
+    }
+}
+
+fn push(operand: &Operand, bits: u32) -> Result<Encoded, String> {
+    match operand {
+        Operand::Register(register) if stack_register(register, bits) => Ok(short_form(register.size, 0x50, register, false, bits)),
+        Operand::Register(register) => Err(format!("'{}' cannot be pushed, only {} registers can", register.name, stack_widths(bits))),
+        Operand::Immediate(Value::Number(number)) if fits(*number, 1, true) => {
+            Ok(Encoded { bytes: vec![0x6a, *number as u8], fixups: Vec::new() })
+        }
+        // A word in 16-bit code, otherwise 32 bits the CPU sign-extends to a word
+        Operand::Immediate(value) if bits == 16 => {
+            let mut encoded = Encoded { bytes: vec![0x68], fixups: Vec::new() };
+            encoded.immediate(value, 2, false)?;
+            Ok(encoded)
+        }
+        Operand::Immediate(value) => {
+            let mut encoded = Encoded { bytes: vec![0x68], fixups: Vec::new() };
+            encoded.immediate(value, 4, true)?;
+            Ok(encoded)
+        }
+        Operand::Memory(memory) => {
+            modrm(stack_size(memory, bits)?, &[0xff], 6, Rm::Memory(memory), false, bits)
+        }
+    }
+}
+
+fn pop(operand: &Operand, bits: u32) -> Result<Encoded, String> {
+    match operand {
+        Operand::Register(register) if stack_register(register, bits) => Ok(short_form(register.size, 0x58, register, false, bits)),
+        Operand::Register(register) => Err(format!("'{}' cannot be popped, only {} registers can", register.name, stack_widths(bits))),
+        Operand::Immediate(_) => Err("an immediate cannot be popped".to_string()),
+        Operand::Memory(memory) => {
+            modrm(stack_size(memory, bits)?, &[0x8f], 0, Rm::Memory(memory), false, bits)
+        }
+    }
+}
+
+// Operand size to encode push and pop of memory with, they default to the size of a word
+// without any prefix
+fn stack_size(memory: &Memory, bits: u32) -> Result<usize, String> {
+    let word = if bits == 16 { 2 } else { 4 };
+    match memory.size.map(|size| size.size() * 8) {
+        None => Ok(word),
+        Some(size) if size == bits as usize => Ok(word),
+        Some(16) => Ok(2),
+        Some(32) if bits == 16 => Ok(4),
+        Some(size) => Err(format!("a {}-bit memory operand cannot be pushed or popped", size)),
+    }
+}
+
+// Registers of x86-64 that 32-bit code does not have
+fn check_registers(instruction: &Instruction, bits: u32) -> Result<(), String> {
+    let operands = match instruction {
+        Instruction::Binary(_, dest, src) => vec![dest, src],
+        Instruction::Push(operand) | Instruction::Pop(operand) => vec![operand],
+        _ => Vec::new(),
+    };
+    for operand in operands {
+        let registers = match operand {
+            Operand::Register(register) => vec![*register],
+            Operand::Memory(memory) => memory.base.iter().chain(memory.index.iter()).copied().collect(),
+            Operand::Immediate(_) => Vec::new(),
+        };
+        if let Some(register) = registers.iter().find(|register| bits != 64 && (register.size == 8 || register.rex)) {
+            return Err(format!("'{}' does not exist in {}-bit code", register.name, bits));
+        }
+    }
+    Ok(())
+}
+
+fn condition_code(condition: Condition) -> u8 {
+    match condition {
+        Condition::Equal => 0x4,
+        Condition::NotEqual => 0x5,
+        Condition::Less => 0xc,
+        Condition::GreaterEqual => 0xd,
+        Condition::LessEqual => 0xe,
+        Condition::Greater => 0xf,
+    }
+}
+
+// 16-bit code jumps within its 64K segment, with a 16-bit displacement
+fn relative(opcode: &[u8], target: &str, bits: u32) -> Encoded {
+    let width = if bits == 16 { 2 } else { 4 };
+    let mut encoded = Encoded { bytes: opcode.to_vec(), fixups: Vec::new() };
+    encoded.fixup(vec![Term { negative: false, value: Value::Symbol(target.to_string()) }], width, FixupKind::Relative);
+    encoded
+}
+
+/// Encodes one instruction for code running in `bits`-bit mode. Jumps and calls always take
+/// the widest displacement, so the size of an instruction never depends on where its target
+/// ends up.
+pub fn encode(instruction: &Instruction, bits: u32) -> Result<Encoded, String> {
+    check_registers(instruction, bits)?;
+    match instruction {
+        Instruction::Binary(opcode, dest, src) => binary(*opcode, dest, src, bits),
+        Instruction::Push(operand) => push(operand, bits),
+        Instruction::Pop(operand) => pop(operand, bits),
+        Instruction::Call(target) => Ok(relative(&[0xe8], target, bits)),
+        Instruction::Jump(None, target) => Ok(relative(&[0xe9], target, bits)),
+        Instruction::Jump(Some(condition), target) => Ok(relative(&[0x0f, 0x80 | condition_code(*condition)], target, bits)),
+        Instruction::Ret => Ok(Encoded { bytes: vec![0xc3], fixups: Vec::new() }),
+        Instruction::Syscall if bits != 64 => Err(format!("'syscall' does not exist in {}-bit code, use 'int 0x80'", bits)),
+        Instruction::Syscall => Ok(Encoded { bytes: vec![0x0f, 0x05], fixups: Vec::new() }),
+        Instruction::Int(vector) => Ok(Encoded { bytes: vec![0xcd, *vector], fixups: Vec::new() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::DataType;
+    use crate::registers;
+
+    fn reg(name: &str) -> Operand {
+        Operand::Register(registers::lookup(name).unwrap())
+    }
+
+    fn imm(number: i64) -> Operand {
+        Operand::Immediate(Value::Number(number))
+    }
+
+    // `size [base + index + displacement]`, a zero displacement is left out
+    fn mem(size: Option<DataType>, base: Option<&str>, index: Option<&str>, displacement: i64) -> Operand {
+        let displacement = if displacement == 0 { Vec::new() } else { vec![Term { negative: false, value: Value::Number(displacement) }] };
+        Operand::Memory(Memory {
+            size,
+            base: base.map(|name| registers::lookup(name).unwrap()),
+            index: index.map(|name| registers::lookup(name).unwrap()),
+            displacement,
+        })
+    }
+
+    fn binary(opcode: Opcode, dest: Operand, src: Operand) -> Instruction {
+        Instruction::Binary(opcode, dest, src)
+    }
+
+    fn check(rows: Vec<(Instruction, &[u8])>, bits: u32) {
+        for (instruction, expected) in rows {
+            let encoded = encode(&instruction, bits).unwrap_or_else(|message| panic!("{:?}: {}", instruction, message));
+            assert_eq!(encoded.bytes, expected, "{:?} in {}-bit code", instruction, bits);
+            assert!(encoded.fixups.is_empty(), "{:?}", instruction);
+        }
+    }
+
+    // The bytes NASM assembles each instruction to
+    #[test]
+    fn encodes_64_bit_instructions_as_nasm_does() {
+        use Opcode::*;
+        check(vec![
+            // REX.W, REX.R and REX.B
+            (binary(Mov, reg("rax"), reg("rbx")), &[0x48, 0x89, 0xd8]),
+            (binary(Mov, reg("r8"), reg("rax")), &[0x49, 0x89, 0xc0]),
+            (binary(Mov, reg("rax"), reg("r8")), &[0x4c, 0x89, 0xc0]),
+            (binary(Mov, reg("r12d"), reg("r13d")), &[0x45, 0x89, 0xec]),
+            (binary(Xor, reg("eax"), reg("eax")), &[0x31, 0xc0]),
+            (binary(Mov, reg("ax"), reg("bx")), &[0x66, 0x89, 0xd8]),
+            // sil needs an empty REX prefix to not be dh
+            (binary(Mov, reg("sil"), reg("al")), &[0x40, 0x88, 0xc6]),
+            (binary(Mov, reg("al"), mem(None, Some("rbx"), None, 0)), &[0x8a, 0x03]),
+            // rbp and r13 as base have no mod 00 form, they take a zero disp8
+            (binary(Mov, reg("rax"), mem(None, Some("rbp"), None, 0)), &[0x48, 0x8b, 0x45, 0x00]),
+            (binary(Mov, reg("rax"), mem(None, Some("r13"), None, 0)), &[0x49, 0x8b, 0x45, 0x00]),
+            (binary(Mov, reg("rax"), mem(None, Some("r13"), Some("rcx"), 0)), &[0x49, 0x8b, 0x44, 0x0d, 0x00]),
+            // rsp and r12 as base need a SIB byte
+            (binary(Mov, reg("rax"), mem(None, Some("rsp"), None, 0)), &[0x48, 0x8b, 0x04, 0x24]),
+            (binary(Mov, reg("rax"), mem(None, Some("r12"), None, 0)), &[0x49, 0x8b, 0x04, 0x24]),
+            (binary(Mov, reg("rax"), mem(None, Some("rsp"), None, 8)), &[0x48, 0x8b, 0x44, 0x24, 0x08]),
+            // Index registers, REX.X for r8 ... r15
+            (binary(Mov, reg("rax"), mem(None, Some("rbx"), Some("rcx"), 0)), &[0x48, 0x8b, 0x04, 0x0b]),
+            (binary(Mov, reg("rax"), mem(None, Some("rbx"), Some("r9"), 0)), &[0x4a, 0x8b, 0x04, 0x0b]),
+            // disp8 from -128 to 127, disp32 beyond
+            (binary(Mov, reg("rax"), mem(None, Some("rbx"), None, -128)), &[0x48, 0x8b, 0x43, 0x80]),
+            (binary(Mov, reg("rax"), mem(None, Some("rbx"), None, 0x80)), &[0x48, 0x8b, 0x83, 0x80, 0x00, 0x00, 0x00]),
+            // A plain address goes through a SIB byte, mod 00 rm 101 is rip-relative
+            (binary(Mov, reg("ebx"), mem(None, None, None, 0x1000)), &[0x8b, 0x1c, 0x25, 0x00, 0x10, 0x00, 0x00]),
+            // imm8 for -128 ... 127, imm32 beyond
+            (binary(Add, reg("rax"), imm(1)), &[0x48, 0x83, 0xc0, 0x01]),
+            (binary(Add, reg("rax"), imm(127)), &[0x48, 0x83, 0xc0, 0x7f]),
+            (binary(Add, reg("rax"), imm(-128)), &[0x48, 0x83, 0xc0, 0x80]),
+            (binary(Add, reg("rax"), imm(128)), &[0x48, 0x81, 0xc0, 0x80, 0x00, 0x00, 0x00]),
+            (binary(Cmp, reg("al"), imm(200)), &[0x80, 0xf8, 0xc8]),
+            (binary(Sub, mem(Some(DataType::U64), Some("rbx"), None, 0), imm(1)), &[0x48, 0x83, 0x2b, 0x01]),
+            (binary(Mov, mem(Some(DataType::U16), Some("rbx"), None, 0), imm(5)), &[0x66, 0xc7, 0x03, 0x05, 0x00]),
+            // mov picks the shortest of the 32-bit, sign-extended and 64-bit immediates
+            (binary(Mov, reg("rax"), imm(1)), &[0xb8, 0x01, 0x00, 0x00, 0x00]),
+            (binary(Mov, reg("r9"), imm(1)), &[0x41, 0xb9, 0x01, 0x00, 0x00, 0x00]),
+            (binary(Mov, reg("rax"), imm(-1)), &[0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]),
+            (binary(Mov, reg("rax"), imm(0x1_0000_0000)), &[0x48, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]),
+            (Instruction::Push(reg("rbx")), &[0x53]),
+            (Instruction::Push(reg("r12")), &[0x41, 0x54]),
+            (Instruction::Pop(reg("r15")), &[0x41, 0x5f]),
+            (Instruction::Push(imm(1)), &[0x6a, 0x01]),
+            (Instruction::Push(imm(0x1000)), &[0x68, 0x00, 0x10, 0x00, 0x00]),
+            (Instruction::Push(mem(None, Some("rbp"), None, 16)), &[0xff, 0x75, 0x10]),
+            (Instruction::Syscall, &[0x0f, 0x05]),
+            (Instruction::Int(0x80), &[0xcd, 0x80]),
+            (Instruction::Ret, &[0xc3]),
+        ], 64);
+    }
+
+    #[test]
+    fn encodes_32_bit_instructions_as_nasm_does() {
+        use Opcode::*;
+        check(vec![
+            (binary(Mov, reg("eax"), reg("ebx")), &[0x89, 0xd8]),
+            (binary(Mov, reg("ax"), reg("bx")), &[0x66, 0x89, 0xd8]),
+            (binary(Mov, reg("eax"), mem(None, Some("ebp"), None, 8)), &[0x8b, 0x45, 0x08]),
+            (binary(Mov, reg("eax"), mem(None, Some("ebp"), None, 0)), &[0x8b, 0x45, 0x00]),
+            (binary(Mov, reg("eax"), mem(None, Some("esp"), None, 0)), &[0x8b, 0x04, 0x24]),
+            (binary(Mov, reg("ebx"), mem(None, None, None, 0x1000)), &[0x8b, 0x1d, 0x00, 0x10, 0x00, 0x00]),
+            (binary(Add, reg("esp"), imm(8)), &[0x83, 0xc4, 0x08]),
+            (binary(Cmp, reg("ecx"), imm(1000)), &[0x81, 0xf9, 0xe8, 0x03, 0x00, 0x00]),
+            (binary(Mov, reg("eax"), imm(1)), &[0xb8, 0x01, 0x00, 0x00, 0x00]),
+            (Instruction::Push(reg("ebx")), &[0x53]),
+            (Instruction::Push(reg("bx")), &[0x66, 0x53]),
+            (Instruction::Push(mem(None, Some("ebp"), None, 8)), &[0xff, 0x75, 0x08]),
+            (Instruction::Int(0x80), &[0xcd, 0x80]),
+        ], 32);
+    }
+
+    #[test]
+    fn encodes_16_bit_instructions_as_nasm_does() {
+        use Opcode::*;
+        check(vec![
+            (binary(Mov, reg("ax"), reg("bx")), &[0x89, 0xd8]),
+            // 32-bit operands take the 66 prefix in 16-bit code
+            (binary(Mov, reg("eax"), reg("ebx")), &[0x66, 0x89, 0xd8]),
+            (binary(Mov, reg("ax"), mem(None, Some("bx"), Some("si"), 0)), &[0x8b, 0x00]),
+            (binary(Mov, reg("ax"), mem(None, Some("bp"), Some("di"), 4)), &[0x8b, 0x43, 0x04]),
+            // [bp] has no mod 00 form, it takes a zero disp8
+            (binary(Mov, reg("ax"), mem(None, Some("bp"), None, 0)), &[0x8b, 0x46, 0x00]),
+            (binary(Mov, reg("bx"), mem(None, None, None, 0x7c00)), &[0x8b, 0x1e, 0x00, 0x7c]),
+            (binary(Mov, reg("ax"), imm(0x7c0)), &[0xb8, 0xc0, 0x07]),
+            (binary(Add, reg("sp"), imm(2)), &[0x83, 0xc4, 0x02]),
+            (Instruction::Push(reg("ax")), &[0x50]),
+            (Instruction::Push(imm(0x1234)), &[0x68, 0x34, 0x12]),
+            (Instruction::Int(0x10), &[0xcd, 0x10]),
+        ], 16);
+    }
+
+    #[test]
+    fn rejects_what_cannot_be_encoded() {
+        let rows = [
+            binary(Opcode::Mov, reg("ah"), reg("sil")),
+            binary(Opcode::Mov, reg("rax"), mem(None, Some("rbx"), Some("rsp"), 0)),
+            binary(Opcode::Mov, reg("rax"), reg("ebx")),
+            binary(Opcode::Add, mem(None, Some("rbx"), None, 0), imm(1)),
+            binary(Opcode::Mov, reg("al"), imm(256)),
+            Instruction::Push(reg("eax")),
+        ];
+        for instruction in rows {
+            assert!(encode(&instruction, 64).is_err(), "{:?}", instruction);
+        }
+        for instruction in [binary(Opcode::Mov, reg("rax"), reg("rbx")), binary(Opcode::Mov, reg("r8d"), imm(1)), Instruction::Syscall] {
+            assert!(encode(&instruction, 32).is_err(), "{:?}", instruction);
+        }
+        assert!(encode(&binary(Opcode::Mov, reg("ax"), mem(None, Some("ax"), None, 0)), 16).is_err());
+    }
+
+    // Labels leave a field of the right width for the assembler to fill in
+    #[test]
+    fn leaves_fixups_for_labels() {
+        let label = Value::Symbol("label".to_string());
+        let encoded = encode(&Instruction::Jump(Some(Condition::NotEqual), "label".to_string()), 64).unwrap();
+        assert_eq!(encoded.bytes, [0x0f, 0x85, 0, 0, 0, 0]);
+        assert_eq!((encoded.fixups[0].offset, encoded.fixups[0].width, encoded.fixups[0].kind), (2, 4, FixupKind::Relative));
+
+        let encoded = encode(&binary(Opcode::Mov, reg("rax"), Operand::Immediate(label.clone())), 64).unwrap();
+        assert_eq!(encoded.bytes, [0x48, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!((encoded.fixups[0].offset, encoded.fixups[0].width), (2, 8));
+
+        let memory = Operand::Memory(Memory { size: None, base: Some(registers::lookup("rbx").unwrap()), index: None, displacement: vec![Term { negative: false, value: label }] });
+        let encoded = encode(&binary(Opcode::Mov, reg("eax"), memory), 64).unwrap();
+        assert_eq!(encoded.bytes, [0x8b, 0x83, 0, 0, 0, 0]);
+        assert_eq!((encoded.fixups[0].offset, encoded.fixups[0].width, encoded.fixups[0].kind), (2, 4, FixupKind::Absolute { signed: true }));
+
+        // 16-bit code jumps with a 16-bit displacement
+        let encoded = encode(&Instruction::Call("label".to_string()), 16).unwrap();
+        assert_eq!(encoded.bytes, [0xe8, 0, 0]);
+        assert_eq!((encoded.fixups[0].offset, encoded.fixups[0].width, encoded.fixups[0].kind), (1, 2, FixupKind::Relative));
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::ir::{Code, Data, EquValue, Function, Instruction, Item, Memory, Operand, Program, Term, Value};
+use crate::parser::DataType;
+use crate::tokenizer::Loc;
+
+/// The two syntaxes the GNU assembler reads x86 code in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    // `movq $1, 8(%rbp)`
+    Att,
+    // `.intel_syntax noprefix`, `mov qword ptr [rbp + 8], 1`
+    Intel,
+}
+
+struct Emitter {
+    dialect: Dialect,
+    // GAS has no textual macros, every `%define` is replaced by its operand where it is used
+    defines: HashMap<String, Operand>,
+    // Size of the operands of push and pop, and of anything else without a size of its own
+    word_size: usize,
+    // Source files of the `.loc` directives, numbered from 1 by `.file`
+    files: Vec<Rc<PathBuf>>,
+}
+
+/// Prints a program as source for the GNU assembler.
+pub fn emit(program: &Program, dialect: Dialect) -> String {
+    let defines = program.items.iter()
+        .filter_map(|item| match item {
+            Item::Define(name, operand) => Some((name.clone(), operand.clone())),
+            _ => None,
+        })
+        .collect();
+    let mut files: Vec<Rc<PathBuf>> = program.debug.iter().map(|debug| Rc::new(debug.source.clone())).collect();
+    for loc in lines(program) {
+        if !files.contains(&loc.file) {
+            files.push(loc.file.clone());
+        }
+    }
+    let emitter = Emitter { dialect, defines, word_size: program.target.word_size(), files };
+
+    let mut result = String::new();
+    if dialect == Dialect::Intel {
+        result.push_str(".intel_syntax noprefix\n");
+    }
+    for (index, file) in emitter.files.iter().enumerate() {
+        result.push_str(&format!(".file {} \"{}\"\n", index + 1, file.display()));
+    }
+    let mut in_text = true;
+    for item in &program.items {
+        match item {
+            Item::Define(..) => {}
+            Item::Extern(name) => result.push_str(&format!(".extern {}\n", name)),
+            Item::Global(name) => result.push_str(&format!(".globl {}\n", name)),
+            Item::Section(name) => {
+                in_text = name == "text";
+                result.push_str(&format!(".section .{}\n", name));
+            }
+            Item::Function(function) => result.push_str(&emitter.function(function)),
+            Item::Label(label) if in_text => result.push_str(&format!("{}:\n", label)),
+            Item::Label(label) => result.push_str(&format!("  {}:\n", label)),
+            Item::Instruction(instr) => result.push_str(&format!("  {}\n", emitter.instruction(instr))),
+            Item::Data(label, data) => result.push_str(&emitter.data(label.as_deref(), data)),
+            Item::Equ(name, value) => result.push_str(&format!("  .set {}, {}\n", name, equ(value))),
+            Item::Align { alignment, .. } => result.push_str(&format!("  .balign {}\n", emitter.value(alignment))),
+            // `.org` counts from the start of the section like `pad` does
+            Item::Pad(size) => result.push_str(&format!("  .org {}\n", emitter.value(size))),
+            // Only flat binaries have an origin, and GAS does not write those
+            Item::Org(_) => {}
+            Item::Line(loc) if in_text => result.push_str(&emitter.line(loc)),
+            Item::Line(_) => {}
+        }
+    }
+    result
+}
+
+// Every source position in the program, in and outside of functions
+fn lines(program: &Program) -> Vec<Loc> {
+    let mut lines = Vec::new();
+    for item in &program.items {
+        match item {
+            Item::Function(function) => lines.extend(function.blocks.iter().flat_map(|block| block.lines.iter().map(|(_, loc)| loc.clone()))),
+            Item::Line(loc) => lines.push(loc.clone()),
+            _ => {}
+        }
+    }
+    lines
+}
+
+fn data_directive(data_type: DataType) -> &'static str {
+    match data_type {
+        DataType::U8 => ".byte",
+        DataType::U16 => ".short",
+        DataType::U32 => ".long",
+        DataType::U64 => ".quad",
+    }
+}
+
+// AT&T suffix of an instruction on operands of this size
+fn suffix(size: usize) -> &'static str {
+    match size {
+        1 => "b",
+        2 => "w",
+        4 => "l",
+        _ => "q",
+    }
+}
+
+fn equ(value: &EquValue) -> String {
+    match value {
+        EquValue::Since(label) => format!(". - {}", label),
+        EquValue::Quotient(symbol, divisor) => format!("{} / {}", symbol, divisor),
+        EquValue::Symbol(symbol) => symbol.clone(),
+        EquValue::Number(number) => number.to_string(),
+    }
+}
+
+impl Emitter {
+    fn function(&self, function: &Function) -> String {
+        let mut result = format!("{}:\n", function.name);
+        for code in function.code() {
+            match code {
+                Code::Label(label) => result.push_str(&format!("{}:\n", label)),
+                Code::Instruction(instr) => result.push_str(&format!("  {}\n", self.instruction(&instr))),
+                Code::Line(loc) => result.push_str(&self.line(&loc)),
+            }
+        }
+        result
+    }
+
+    // GAS builds the line table of the debug information from these
+    fn line(&self, loc: &Loc) -> String {
+        let file = self.files.iter().position(|file| *file == loc.file).unwrap() + 1;
+        format!("  .loc {} {} {}\n", file, loc.line, loc.column)
+    }
+
+    fn data(&self, label: Option<&str>, data: &Data) -> String {
+        let mut result = label.map(|label| format!("  {}:\n", label)).unwrap_or_default();
+        match data {
+            Data::Values { data_type, values, repeat } => {
+                let values: Vec<String> = values.iter().map(|value| self.value(value)).collect();
+                let line = format!("  {} {}\n", data_directive(*data_type), values.join(", "));
+                match repeat {
+                    Some(count) => result.push_str(&format!("  .rept {}\n  {}  .endr\n", self.value(count), line)),
+                    None => result.push_str(&line),
+                }
+            }
+            Data::Bytes(bytes) if bytes.is_empty() => {}
+            Data::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+                result.push_str(&format!("  .byte {}\n", bytes.join(", ")));
+            }
+            Data::Reserve { data_type, count } => {
+                result.push_str(&format!("  .skip {} * {}\n", self.value(count), data_type.size()));
+            }
+            Data::Zero(count) => result.push_str(&format!("  .zero {}\n", count)),
+        }
+        result
+    }
+
+    /// The operand a `%define` stands for, or the operand itself.
+    fn substitute(&self, operand: &Operand) -> Operand {
+        match operand {
+            Operand::Immediate(Value::Symbol(name)) => match self.defines.get(name) {
+                Some(defined) => self.substitute(defined),
+                None => operand.clone(),
+            },
+            Operand::Immediate(value) => Operand::Immediate(self.substitute_value(value)),
+            Operand::Memory(memory) => {
+                let displacement = memory.displacement.iter()
+                    .map(|term| Term { negative: term.negative, value: self.substitute_value(&term.value) })
+                    .collect();
+                Operand::Memory(Memory { displacement, ..memory.clone() })
+            }
+            Operand::Register(_) => operand.clone(),
+        }
+    }
+
+    fn substitute_value(&self, value: &Value) -> Value {
+        match value {
+            Value::Symbol(name) => match self.defines.get(name).map(|defined| self.substitute(defined)) {
+                Some(Operand::Immediate(value)) => value,
+                _ => value.clone(),
+            },
+            Value::Or(left, right) => Value::Or(Box::new(self.substitute_value(left)), Box::new(self.substitute_value(right))),
+            Value::Number(_) => value.clone(),
+        }
+    }
+
+    fn value(&self, value: &Value) -> String {
+        self.substitute_value(value).to_string()
+    }
+
+    fn instruction(&self, instruction: &Instruction) -> String {
+        match instruction {
+            Instruction::Binary(opcode, dest, src) => {
+                let (dest, src) = (self.substitute(dest), self.substitute(src));
+                match self.dialect {
+                    Dialect::Att => format!("{}{} {}, {}", opcode.mnemonic(), self.size_suffix(&[&dest, &src]), self.operand(&src), self.operand(&dest)),
+                    Dialect::Intel => format!("{} {}, {}", opcode.mnemonic(), self.operand(&dest), self.operand(&src)),
+                }
+            }
+            Instruction::Push(value) => self.stack("push", &self.substitute(value)),
+            Instruction::Pop(value) => self.stack("pop", &self.substitute(value)),
+            Instruction::Call(target) => format!("call {}", target),
+            Instruction::Jump(None, target) => format!("jmp {}", target),
+            Instruction::Jump(Some(condition), target) => format!("j{} {}", condition.suffix(), target),
+            Instruction::Ret => "ret".to_string(),
+            Instruction::Syscall => "syscall".to_string(),
+            Instruction::Int(vector) if self.dialect == Dialect::Att => format!("int ${:#x}", vector),
+            Instruction::Int(vector) => format!("int {:#x}", vector),
+        }
+    }
+
+    fn stack(&self, mnemonic: &str, operand: &Operand) -> String {
+        match self.dialect {
+            // Only 16-bit operands are not pushed as a whole word
+            Dialect::Att => {
+                let size = operand.register().map(|register| register.size).unwrap_or(self.word_size);
+                format!("{}{} {}", mnemonic, suffix(size), self.operand(operand))
+            }
+            Dialect::Intel => format!("{} {}", mnemonic, self.operand(operand)),
+        }
+    }
+
+    // AT&T spells the operand size in the mnemonic, taken from a sized memory operand or a register
+    fn size_suffix(&self, operands: &[&Operand]) -> &'static str {
+        let size = operands.iter().find_map(|operand| match operand {
+            Operand::Memory(Memory { size: Some(size), .. }) => Some(size.size()),
+            _ => None,
+        });
+        let size = size.or_else(|| operands.iter().find_map(|operand| operand.register().map(|register| register.size)));
+        suffix(size.unwrap_or(self.word_size))
+    }
+
+    fn operand(&self, operand: &Operand) -> String {
+        match (self.dialect, operand) {
+            (Dialect::Att, Operand::Register(register)) => format!("%{}", register.name),
+            (Dialect::Att, Operand::Immediate(value)) => format!("${}", value),
+            (Dialect::Att, Operand::Memory(memory)) => {
+                let displacement = displacement(&memory.displacement);
+                let registers: Vec<String> = memory.base.iter().chain(memory.index.iter())
+                    .map(|register| format!("%{}", register.name))
+                    .collect();
+                match (registers.is_empty(), memory.base.is_none()) {
+                    (true, _) => displacement,
+                    // An index without a base still needs the comma in front of it
+                    (false, true) => format!("{}(,{})", displacement, registers.join(",")),
+                    (false, false) => format!("{}({})", displacement, registers.join(",")),
+                }
+            }
+            (Dialect::Intel, Operand::Register(register)) => register.name.to_string(),
+            // A bare symbol is a memory operand in Intel syntax, `offset` asks for its value
+            (Dialect::Intel, Operand::Immediate(value @ (Value::Symbol(_) | Value::Or(..)))) => format!("offset {}", value),
+            (Dialect::Intel, Operand::Immediate(value)) => value.to_string(),
+            (Dialect::Intel, Operand::Memory(memory)) => {
+                let mut address = String::new();
+                for register in memory.base.iter().chain(memory.index.iter()) {
+                    if !address.is_empty() {
+                        address.push_str(" + ");
+                    }
+                    address.push_str(register.name);
+                }
+                for term in &memory.displacement {
+                    match (address.is_empty(), term.negative) {
+                        (true, false) => {}
+                        (true, true) => address.push('-'),
+                        (false, false) => address.push_str(" + "),
+                        (false, true) => address.push_str(" - "),
+                    }
+                    address.push_str(&term.value.to_string());
+                }
+                match memory.size {
+                    Some(size) => format!("{} ptr [{}]", size.size_keyword(), address),
+                    None => format!("[{}]", address),
+                }
+            }
+        }
+    }
+}
+
+// `sym+8-x` in front of the registers of an AT&T address
+fn displacement(terms: &[Term]) -> String {
+    let mut result = String::new();
+    for term in terms {
+        if term.negative {
+            result.push('-');
+        } else if !result.is_empty() {
+            result.push('+');
+        }
+        result.push_str(&term.value.to_string());
+    }
+    result
+}
@@ -1,18 +1,74 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::parser::{Node, NodeExpr, NodeExprIdent, NodeExprLen, NodeExprNumber, NodeExprString, NodeFunc, NodeStmt, NodeStmtDefine, NodeStmtIf, NodeStmtWhile};
+use crate::clobbers;
+use crate::consts;
+use crate::ir::{Code, Condition, Data, DebugFunction, DebugInfo, EquValue, Function, Instruction, Item, Memory, Opcode, Operand, Parameter, Program, Term, Value};
+use crate::layout::{self, StructLayout};
+use crate::parser::{DataType, Node, NodeExpr, NodeExprIdent, NodeExprLen, NodeExprMemory, NodeExprNumber, NodeExprSizeof, NodeExprString, NodeExtern, NodeFunc, NodeStmt, NodeStmtAlign, NodeStmtData, NodeStmtDefine, NodeStmtIf, NodeStmtInstance, NodeStmtReserve, NodeStmtWhile};
+use crate::registers::{self, Register};
+use crate::target::Target;
+use crate::tokenizer::Loc;
 
 static LABEL_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 pub struct Generator {
     node: Node,
+    target: Target,
+    layouts: HashMap<String, StructLayout>,
+    constants: HashMap<String, i64>,
+    data_types: HashMap<String, DataType>,
+    // Name of the section currently being emitted, `align` needs it to pick `align` or `alignb`
+    section: RefCell<String>,
+    // String literals used as operands, emitted once each into `.rodata` as `__str_<index>`
+    strings: RefCell<Vec<String>>,
+    // Bytes on the stack below the last 16-byte aligned rsp, used to pad calls to alignment
+    stack_depth: RefCell<i64>,
+    // Whether the code of every statement is marked with its source position
+    lines: bool,
+    // The file being compiled when `-g` asks for debug information
+    debug: Option<PathBuf>,
 }
 
 impl Generator {
-    pub fn new(node: Node) -> Generator {
+    pub fn new(node: Node, target: Target) -> Generator {
+        let layouts = layout::compute_layouts(&node.structs);
+        // The resolver has reported any constant without a value
+        let (constants, _) = consts::collect_constants(&node.enums, &node.consts);
+        let data_types = layout::data_label_types(&node.stmt);
         Generator {
             node,
+            target,
+            layouts,
+            constants,
+            data_types,
+            section: RefCell::new(String::new()),
+            strings: RefCell::new(Vec::new()),
+            stack_depth: RefCell::new(0),
+            lines: false,
+            debug: None,
+        }
+    }
+
+    /// Marks the code of every statement with its position in the source.
+    pub fn set_lines(&mut self) {
+        self.lines = true;
+    }
+
+    /// Marks the code with source positions and describes the functions for debuggers,
+    /// `source` being the file the compilation started from.
+    pub fn set_debug(&mut self, source: PathBuf) {
+        self.lines = true;
+        self.debug = Some(source);
+    }
+
+    // The marker for the instructions of a statement, when they are marked
+    fn line(&self, loc: Option<&Loc>) -> Vec<Code> {
+        match loc {
+            Some(loc) if self.lines => vec![Code::Line(loc.clone())],
+            _ => Vec::new(),
         }
     }
 
@@ -21,253 +77,409 @@ impl Generator {
         format!("label_{}", count)
     }
 
-    fn generate_if_statement(&self, if_stmt: &NodeStmtIf) -> String {
-        let mut result = String::new();
-        let unique_label = self.unique_label();
-        match &if_stmt.condition {
-            NodeExpr::Equal(equal_expr) => {
-                let left = self.generate_expr(&equal_expr.left);
-                let right = self.generate_expr(&equal_expr.right);
-                result.push_str(&format!("  cmp {}, {}\n", left, right));
-                result.push_str(&format!("  jne .if_true_{}\n", unique_label));
-            }
-            NodeExpr::Lesser(lesser_expr) => {
-                let left = self.generate_expr(&lesser_expr.left);
-                let right = self.generate_expr(&lesser_expr.right);
-                result.push_str(&format!("  cmp {}, {}\n", left, right));
-                result.push_str(&format!("  jge .if_true_{}\n", unique_label));
-            }
-            NodeExpr::Greater(greater_expr) => {
-                let left = self.generate_expr(&greater_expr.left);
-                let right = self.generate_expr(&greater_expr.right);
-                result.push_str(&format!("  cmp {}, {}\n", left, right));
-                result.push_str(&format!("  jle .if_true_{}\n", unique_label));
-            }
-            NodeExpr::NotEqual(not_equal_expr) => {
-                let left = self.generate_expr(&not_equal_expr.left);
-                let right = self.generate_expr(&not_equal_expr.right);
-                result.push_str(&format!("  cmp {}, {}\n", left, right));
-                result.push_str(&format!("  je .if_true_{}\n", unique_label));
-            }
-            _ => panic!("Unsupported if statement condition"),
-        }
+    /// Compares the two sides of a condition and jumps to `target` when it does not hold.
+    fn generate_condition(&self, condition: &NodeExpr, params: &HashMap<String, Operand>, target: &str) -> Option<Vec<Code>> {
+        let (left, right, holds) = match condition {
+            NodeExpr::Equal(equal_expr) => (&equal_expr.left, &equal_expr.right, Condition::Equal),
+            NodeExpr::Lesser(lesser_expr) => (&lesser_expr.left, &lesser_expr.right, Condition::Less),
+            NodeExpr::Greater(greater_expr) => (&greater_expr.left, &greater_expr.right, Condition::Greater),
+            NodeExpr::NotEqual(not_equal_expr) => (&not_equal_expr.left, &not_equal_expr.right, Condition::NotEqual),
+            _ => return None,
+        };
+        Some(vec![
+            Code::Instruction(Instruction::Binary(Opcode::Cmp, self.operand(left, params), self.operand(right, params))),
+            Code::Instruction(Instruction::Jump(Some(holds.negate()), target.to_string())),
+        ])
+    }
+
+    fn generate_if_statement(&self, if_stmt: &NodeStmtIf, params: &HashMap<String, Operand>) -> Vec<Code> {
+        let end = format!(".if_true_{}", self.unique_label());
+        let mut code = self.generate_condition(&if_stmt.condition, params, &end)
+            .unwrap_or_else(|| panic!("Unsupported if statement condition"));
 
         for stmt in &if_stmt.body {
-            result.push_str(&self.generate_statement(stmt));
+            code.extend(self.generate_code(stmt, params));
         }
 
-        result.push_str(&format!(".if_true_{}:\n", unique_label));
-
-        result
+        code.push(Code::Label(end));
+        code
     }
 
-
-    fn generate_while(&self, while_stmt: &NodeStmtWhile) -> String {
-        let mut result = String::new();
+    fn generate_while(&self, while_stmt: &NodeStmtWhile, params: &HashMap<String, Operand>) -> Vec<Code> {
         let unique_label = self.unique_label();
-        result.push_str(&format!(".while_{}:\n", unique_label));
-
-        match &while_stmt.condition {
-            NodeExpr::Equal(equal_expr) => {
-                let left = self.generate_expr(&equal_expr.left);
-                let right = self.generate_expr(&equal_expr.right);
-                result.push_str(&format!("  cmp {}, {}\n", left, right));
-                result.push_str(&format!("  jne .while_end_{}\n", unique_label));
-            }
-            NodeExpr::Lesser(lesser_expr) => {
-                let left = self.generate_expr(&lesser_expr.left);
-                let right = self.generate_expr(&lesser_expr.right);
-                result.push_str(&format!("  cmp {}, {}\n", left, right));
-                result.push_str(&format!("  jge .while_end_{}\n", unique_label));
-            }
-            NodeExpr::Greater(greater_expr) => {
-                let left = self.generate_expr(&greater_expr.left);
-                let right = self.generate_expr(&greater_expr.right);
-                result.push_str(&format!("  cmp {}, {}\n", left, right));
-                result.push_str(&format!("  jle .while_end_{}\n", unique_label));
-            }
-            NodeExpr::NotEqual(not_equal_expr) => {
-                let left = self.generate_expr(&not_equal_expr.left);
-                let right = self.generate_expr(&not_equal_expr.right);
-                result.push_str(&format!("  cmp {}, {}\n", left, right));
-                result.push_str(&format!("  je .while_end_{}\n", unique_label));
-            }
-            _ => panic!("Unsupported while statement condition"),
-        }
+        let start = format!(".while_{}", unique_label);
+        let end = format!(".while_end_{}", unique_label);
+
+        let mut code = vec![Code::Label(start.clone())];
+        code.extend(self.generate_condition(&while_stmt.condition, params, &end)
+            .unwrap_or_else(|| panic!("Unsupported while statement condition")));
 
         for stmt in &while_stmt.body {
-            result.push_str(&self.generate_statement(stmt));
+            code.extend(self.generate_code(stmt, params));
         }
 
-        result.push_str(&format!("  jmp .while_{}\n", unique_label));
-        result.push_str(&format!(".while_end_{}:\n", unique_label));
-
-        result
+        code.extend(self.line(Some(&while_stmt.loc)));
+        code.push(Code::Instruction(Instruction::Jump(None, start)));
+        code.push(Code::Label(end));
+        code
     }
 
-
-
-    fn generate_statement(&self, stmt: &NodeStmt) -> String {
-        match stmt {
-            NodeStmt::Mov(mov) => {
-                format!("  mov {}, {}\n", self.generate_expr_ident(&mov.ident), self.generate_expr(&mov.expr))
+    fn generate_statement(&self, stmt: &NodeStmt) -> Vec<Item> {
+        let items = match stmt {
+            NodeStmt::Global(global) => {
+                vec![Item::Global(self.generate_expr_ident(&global.ident))]
             }
-            NodeStmt::Add(add) => {
-                format!("  add {}, {}\n", self.generate_expr_ident(&add.ident), self.generate_expr(&add.expr))
+            NodeStmt::Section(section) => {
+                let name = self.generate_expr_ident(&section.name);
+                *self.section.borrow_mut() = name.clone();
+                vec![Item::Section(name)]
             }
-            NodeStmt::Global(global) => {
-                format!("global {}\n", self.generate_expr_ident(&global.ident))
+            NodeStmt::Assign(assign) => {
+                let bytes = match &assign.expr {
+                    NodeExpr::String(string) => string_bytes(&string.value),
+                    expr => string_bytes(&self.value(expr).to_string()),
+                };
+                let name = self.generate_expr_ident(&assign.ident);
+                vec![
+                    Item::Data(Some(name.clone()), Data::Bytes(bytes)),
+                    Item::Equ(format!("{}_len", name), EquValue::Since(name.clone())),
+                    Item::Equ(format!("{}_size", name), EquValue::Symbol(format!("{}_len", name))),
+                ]
             }
-            NodeStmt::Syscall(_syscall) => {
-                "  syscall\n".to_string()
+            NodeStmt::Data(data) => {
+                self.generate_data(data)
             }
-            NodeStmt::Call(call) => {
-                let mut result = String::new();
+            NodeStmt::Reserve(reserve) => {
+                self.generate_reserve(reserve)
+            }
+            NodeStmt::Align(align) => {
+                vec![self.generate_align(align)]
+            }
+            NodeStmt::Instance(instance) => {
+                self.generate_instance(instance)
+            }
+            // Put in front by `generate`
+            NodeStmt::Org(_) => Vec::new(),
+            NodeStmt::Pad(pad) => {
+                vec![Item::Pad(self.value(&pad.expr))]
+            }
+            NodeStmt::BootSignature => {
+                // The BIOS only boots a sector that ends in 0x55 0xAA
+                vec![Item::Pad(Value::Number(510)), Item::Data(None, Data::Bytes(vec![0x55, 0xaa]))]
+            }
+            _ => {
+                // Instructions outside of any function, which come with their line marker
+                return self.generate_code(stmt, &HashMap::new()).into_iter().map(|code| match code {
+                    Code::Label(label) => Item::Label(label),
+                    Code::Instruction(instruction) => Item::Instruction(instruction),
+                    Code::Line(loc) => Item::Line(loc),
+                }).collect();
+            }
+        };
+        // Data is marked as well, so that problems with it can be traced back to the statement
+        match stmt.loc() {
+            Some(loc) if self.lines => [vec![Item::Line(loc.clone())], items].concat(),
+            _ => items,
+        }
+    }
 
-                let stack_space = call.arguments.len() * 8; 
+    /// `name_size` and `name_len` of a label holding elements of `data_type`.
+    fn generate_sizes(&self, name: &str, data_type: DataType) -> [Item; 2] {
+        [
+            Item::Equ(format!("{}_size", name), EquValue::Since(name.to_string())),
+            Item::Equ(format!("{}_len", name), EquValue::Quotient(format!("{}_size", name), data_type.size())),
+        ]
+    }
 
-                let reversed_args: Vec<_> = call.arguments.iter().rev().collect();
+    fn generate_data(&self, data: &NodeStmtData) -> Vec<Item> {
+        let name = self.generate_expr_ident(&data.ident);
+        let data_type = data.data_type;
 
-                for arg in reversed_args.iter() {
-                    result.push_str(&format!("  push {}\n", self.generate_expr(arg)));
-                }
+        let contents = if data.values.is_empty() {
+            // A declaration without initialiser is a single zeroed element, `.bss` only reserves it
+            if self.section.borrow().as_str() == "bss" {
+                Data::Reserve { data_type, count: Value::Number(1) }
+            } else {
+                Data::Values { data_type, values: vec![Value::Number(0)], repeat: None }
+            }
+        } else {
+            let values = data.values.iter().map(|value| self.value(value)).collect();
+            let repeat = data.repeat.as_ref().map(|count| self.value(count));
+            Data::Values { data_type, values, repeat }
+        };
+
+        let mut items = vec![Item::Data(Some(name.clone()), contents)];
+        items.extend(self.generate_sizes(&name, data_type));
+        items
+    }
 
-                result.push_str(&format!("  call {}\n", self.generate_expr_ident(&call.name)));
+    fn generate_reserve(&self, reserve: &NodeStmtReserve) -> Vec<Item> {
+        let name = self.generate_expr_ident(&reserve.ident);
+        let contents = Data::Reserve { data_type: reserve.data_type, count: self.value(&reserve.count) };
+        let mut items = vec![Item::Data(Some(name.clone()), contents)];
+        items.extend(self.generate_sizes(&name, reserve.data_type));
+        items
+    }
 
-                if stack_space > 0 {
-                    result.push_str(&format!("  add rsp, {}\n", stack_space));
-                }
+    fn generate_instance(&self, instance: &NodeStmtInstance) -> Vec<Item> {
+        let name = self.generate_expr_ident(&instance.ident);
+        let layout = self.layouts.get(&instance.struct_name.name)
+            .unwrap_or_else(|| panic!("Unknown type '{}' for '{}'", instance.struct_name.name, name));
+        let mut items = Vec::new();
 
-                result
-            }
-            NodeStmt::Section(section) => {
-                format!("section .{}\n", self.generate_expr_ident(&section.name))
+        for init in &instance.fields {
+            if layout.field(&init.ident.name).is_none() {
+                panic!("Struct '{}' has no field '{}'", layout.name, init.ident.name);
             }
-            NodeStmt::Assign(assign) => {
-                let newstring = self.string_to_hex(self.generate_expr(&assign.expr));
-                let mut result = String::new();
-                result.push_str(&format!("  {} db {}\n", self.generate_expr_ident(&assign.ident), newstring));
-
-                result.push_str(&format!("  {}_len equ $ - {}\n", self.generate_expr_ident(&assign.ident), self.generate_expr_ident(&assign.ident)));
+        }
 
-                result
-            }
-            NodeStmt::If(if_stmt) => {
-                self.generate_if_statement(if_stmt, )
-            }
-            NodeStmt::While(while_stmt) => {
-                self.generate_while(while_stmt)
+        if self.section.borrow().as_str() == "bss" {
+            if !instance.fields.is_empty() {
+                panic!("'{}' is in .bss and cannot have initial values", name);
             }
-            NodeStmt::Push(push) => {
-                format!("  push {}\n", self.generate_expr(&push.expr))
+            let count = Value::Number(layout.size as i64);
+            items.push(Item::Data(Some(name.clone()), Data::Reserve { data_type: DataType::U8, count }));
+        } else {
+            items.push(Item::Label(name.clone()));
+            let mut offset = 0;
+            for field in &layout.fields {
+                if field.offset > offset {
+                    items.push(Item::Data(None, Data::Zero(field.offset - offset)));
+                }
+                let init = instance.fields.iter().find(|init| init.ident.name == field.name);
+                match (field.data_type, init) {
+                    (Some(data_type), Some(init)) => {
+                        let values = vec![self.value(&init.expr)];
+                        items.push(Item::Data(None, Data::Values { data_type, values, repeat: None }));
+                    }
+                    (None, Some(_)) => panic!("Field '{}.{}' is not a scalar and cannot be initialised", layout.name, field.name),
+                    (_, None) => {
+                        items.push(Item::Data(None, Data::Zero(field.size)));
+                    }
+                }
+                offset = field.offset + field.size;
             }
-            NodeStmt::Pop(pop) => {
-                format!("  pop {}\n", self.generate_expr_ident(&pop.ident))
+            if layout.size > offset {
+                items.push(Item::Data(None, Data::Zero(layout.size - offset)));
             }
-            NodeStmt::Xor(xor) => {
-                format!("  xor {}, {}\n", self.generate_expr_ident(&xor.ident), self.generate_expr(&xor.expr))
-            }
-            _ => "".to_string(),
         }
+
+        items.push(Item::Equ(format!("{}_size", name), EquValue::Since(name.clone())));
+        items.push(Item::Equ(format!("{}_len", name), EquValue::Number(1)));
+        items
     }
 
-    fn string_to_hex(&self, string: String) -> String {
-        let bytes = string.as_bytes();
-        let mut hex_representation = String::new();
-        let mut skip_next = false;
+    fn generate_align(&self, align: &NodeStmtAlign) -> Item {
+        let bss = self.section.borrow().as_str() == "bss";
+        Item::Align { alignment: self.value(&align.expr), bss }
+    }
 
-        for (i, &byte) in bytes.iter().enumerate() {
-            if skip_next {
-                skip_next = false;
-                continue;
-            }
+    fn adjust_stack_depth(&self, bytes: i64) {
+        *self.stack_depth.borrow_mut() += bytes;
+    }
 
-            if byte == b'\\' && i + 1 < bytes.len() && bytes[i + 1] == b'n' {
-                hex_representation.push_str("0x0a, ");
-                skip_next = true; 
-            } else {
-                let hex = format!("0x{:02x}, ", byte);
-                hex_representation.push_str(&hex);
+    fn generate_function(&self, func: &NodeFunc) -> Function {
+        let name = self.generate_expr_ident(&func.name);
+        let mut code = self.line(Some(&func.name.loc));
+        let word = self.target.word_size();
+        // `_start` begins with an aligned rsp, every other function right after a call pushed
+        // its return address onto an aligned one
+        *self.stack_depth.borrow_mut() = if func.name.name == "_start" { 0 } else { word as i64 };
+
+        if func.naked {
+            for stmt in &func.body {
+                code.extend(self.generate_code(stmt, &HashMap::new()));
             }
+            return Function::new(name, code);
         }
 
-        hex_representation.trim_end_matches(", ").to_string()
-    }
+        let rbp = Operand::Register(self.target.register("rbp"));
+        let rsp = Operand::Register(self.target.register("rsp"));
 
-    fn generate_function(&self, func: &NodeFunc) -> String {
-        let mut result = format!("{}:\n", self.generate_expr_ident(&func.name));
-        result.push_str("  push rbp\n");
-        result.push_str("  mov rbp, rsp\n");
+        // A leaf without parameters never addresses anything relative to rbp
+        let frame = !is_leaf(func);
+        if frame {
+            code.push(Code::Instruction(Instruction::Push(rbp.clone())));
+            code.push(Code::Instruction(Instruction::Binary(Opcode::Mov, rbp.clone(), rsp.clone())));
+            self.adjust_stack_depth(word as i64);
+        }
+
+        // Callee-saved registers sit right below the saved rbp and are reloaded from there,
+        // so the epilogue does not depend on the body leaving rsp where it found it
+        let saved = clobbers::saved(func, self.target);
+        for name in &saved {
+            code.push(Code::Instruction(Instruction::Push(Operand::Register(register(name)))));
+            self.adjust_stack_depth(word as i64);
+        }
 
-        let mut arg_stack_map: HashMap<String, String> = HashMap::new();
+        let mut params: HashMap<String, Operand> = HashMap::new();
         for (index, arg) in func.arguments.iter().enumerate() {
-            let arg_name = self.generate_expr_ident(arg);
-            let stack_offset = (index + 2) * 8; 
-            let stack_offset_str = format!("[rbp + {}]", stack_offset);
-            arg_stack_map.insert(arg_name, stack_offset_str);
+            let arg_name = self.generate_expr_ident(&arg.ident);
+            let stack_offset = (index + 2) * word;
+            params.insert(arg_name, Operand::Memory(self.frame_slot(Some(self.target.word_type()), false, stack_offset)));
         }
 
         for stmt in &func.body {
-            result.push_str(&self.generate_statement_with_arg_map(stmt, &arg_stack_map));
+            code.extend(self.generate_code(stmt, &params));
         }
 
-        result.push_str("  mov rsp, rbp\n");
-        result.push_str("  pop rbp\n");
-        result.push_str("  ret\n");
-        result
+        if func.noreturn {
+            return Function::new(name, code);
+        }
+        code.extend(self.line(Some(&func.end)));
+        if frame {
+            for (index, name) in saved.iter().enumerate() {
+                let slot = Operand::Memory(self.frame_slot(None, true, (index + 1) * word));
+                code.push(Code::Instruction(Instruction::Binary(Opcode::Mov, Operand::Register(register(name)), slot)));
+            }
+            code.push(Code::Instruction(Instruction::Binary(Opcode::Mov, rsp, rbp.clone())));
+            code.push(Code::Instruction(Instruction::Pop(rbp)));
+        } else {
+            for name in saved.iter().rev() {
+                code.push(Code::Instruction(Instruction::Pop(Operand::Register(register(name)))));
+            }
+        }
+        code.push(Code::Instruction(Instruction::Ret));
+        Function::new(name, code)
+    }
+
+    /// Lowers a statement that turns into instructions, with the parameters of the enclosing
+    /// function mapped to their stack slots.
+    fn generate_code(&self, stmt: &NodeStmt, params: &HashMap<String, Operand>) -> Vec<Code> {
+        let mut code = self.line(stmt.loc());
+        code.extend(self.generate_instructions(stmt, params));
+        code
     }
 
-    fn generate_statement_with_arg_map(&self, stmt: &NodeStmt, arg_register_map: &std::collections::HashMap<String, String>) -> String {
+    fn generate_instructions(&self, stmt: &NodeStmt, params: &HashMap<String, Operand>) -> Vec<Code> {
         match stmt {
             NodeStmt::Mov(mov) => {
-                let target = self.generate_expr_ident(&mov.ident);
-                let target_mapped = arg_register_map.get(&target).unwrap_or(&target);
-                let value = self.generate_expr_with_arg_map(&mov.expr, arg_register_map);
-                format!("  mov {}, {}\n", target_mapped, value)
+                let target = self.operand(&mov.dest, params);
+                let value = self.operand(&mov.expr, params);
+                vec![Code::Instruction(Instruction::Binary(Opcode::Mov, target, value))]
             },
             NodeStmt::Add(add) => {
-                let target = self.generate_expr_ident(&add.ident);
-                let target_mapped = arg_register_map.get(&target).unwrap_or(&target);
-                let value = self.generate_expr_with_arg_map(&add.expr, arg_register_map);
-                format!("  add {}, {}\n", target_mapped, value)
+                if let (NodeExpr::Ident(dest), Some(bytes)) = (&add.dest, consts::eval_expr(&add.expr, &self.constants)) {
+                    if dest.name == self.target.register("rsp").name {
+                        self.adjust_stack_depth(-bytes);
+                    }
+                }
+                let target = self.operand(&add.dest, params);
+                let value = self.operand(&add.expr, params);
+                vec![Code::Instruction(Instruction::Binary(Opcode::Add, target, value))]
             },
-            _ => self.generate_statement(stmt),
-        }
-    }
+            NodeStmt::Xor(xor) => {
+                let target = self.operand(&xor.dest, params);
+                let value = self.operand(&xor.expr, params);
+                vec![Code::Instruction(Instruction::Binary(Opcode::Xor, target, value))]
+            },
+            NodeStmt::Push(push) => {
+                self.adjust_stack_depth(self.target.word_size() as i64);
+                vec![Code::Instruction(Instruction::Push(self.operand(&push.expr, params)))]
+            },
+            NodeStmt::Pop(pop) => {
+                self.adjust_stack_depth(-(self.target.word_size() as i64));
+                vec![Code::Instruction(Instruction::Pop(self.operand_ident(&pop.ident, params)))]
+            },
+            NodeStmt::Syscall(_syscall) => {
+                vec![Code::Instruction(Instruction::Syscall)]
+            },
+            NodeStmt::Int(int) => {
+                let vector = consts::eval_expr(&int.vector, &self.constants)
+                    .and_then(|vector| u8::try_from(vector).ok())
+                    .unwrap_or_else(|| panic!("{}: Interrupt vector is not a constant from 0 to 255", int.loc));
+                vec![Code::Instruction(Instruction::Int(vector))]
+            },
+            NodeStmt::Call(call) => {
+                let mut code = Vec::new();
+                let rsp = Operand::Register(self.target.register("rsp"));
+
+                // Pad so that rsp is aligned as the target wants once the arguments are pushed
+                let alignment = self.target.stack_alignment();
+                let arguments_size = (call.arguments.len() * self.target.word_size()) as i64;
+                let padding = (alignment - (*self.stack_depth.borrow() + arguments_size).rem_euclid(alignment)) % alignment;
+                if padding > 0 {
+                    code.push(Code::Instruction(Instruction::Binary(Opcode::Sub, rsp.clone(), Operand::Immediate(Value::Number(padding)))));
+                }
+                let stack_space = arguments_size + padding;
 
+                for arg in call.arguments.iter().rev() {
+                    code.push(Code::Instruction(Instruction::Push(self.operand(arg, params))));
+                }
 
-    fn generate_expr_with_arg_map(&self, expr: &NodeExpr, arg_register_map: &std::collections::HashMap<String, String>) -> String {
-        match expr {
-            NodeExpr::Ident(ident) => {
-                let name = self.generate_expr_ident(ident);
-                arg_register_map.get(&name).unwrap_or(&name).clone()
+                code.push(Code::Instruction(Instruction::Call(self.generate_expr_ident(&call.name))));
+
+                if stack_space > 0 {
+                    code.push(Code::Instruction(Instruction::Binary(Opcode::Add, rsp, Operand::Immediate(Value::Number(stack_space)))));
+                }
+
+                code
             },
-            NodeExpr::Number(number) => self.generate_expr_number(number),
-            NodeExpr::String(string) => self.generate_string(string),
-            _ => "".to_string(),
+            // The resolver makes sure the body of an `if` or `while` leaves the stack as deep as
+            // it found it, whether it runs or not
+            NodeStmt::If(if_stmt) => self.generate_if_statement(if_stmt, params),
+            NodeStmt::While(while_stmt) => self.generate_while(while_stmt, params),
+            NodeStmt::Global(_) | NodeStmt::Section(_) | NodeStmt::Assign(_) | NodeStmt::Data(_)
+            | NodeStmt::Reserve(_) | NodeStmt::Align(_) | NodeStmt::Instance(_) => {
+                panic!("Sections, labels and data cannot be declared inside a function")
+            },
+            _ => Vec::new(),
         }
     }
 
-    fn generate_define(&self, define: &NodeStmtDefine) -> String {
-        format!("%define {} {}\n", self.generate_expr_ident(&define.ident), self.generate_expr(&define.expr))
+    fn generate_define(&self, define: &NodeStmtDefine) -> Item {
+        Item::Define(self.generate_expr_ident(&define.ident), self.operand(&define.expr, &HashMap::new()))
     }
 
+    fn generate_extern(&self, prototype: &NodeExtern) -> Item {
+        Item::Extern(self.generate_expr_ident(&prototype.name))
+    }
 
-    pub fn generate(&self) -> String {
-        let mut result = String::new();
+
+    pub fn generate(&self) -> Program {
+        let mut program = Program { items: Vec::new(), target: self.target, debug: None };
+        if let Some(source) = &self.debug {
+            let functions = self.node.functions.iter().map(|func| self.debug_function(func)).collect();
+            program.debug = Some(DebugInfo { source: source.clone(), functions });
+        }
+
+        // `org` holds for the whole file, wherever it is written
+        for stmt in &self.node.stmt {
+            if let NodeStmt::Org(org) = stmt {
+                program.items.push(Item::Org(self.value(&org.expr)));
+            }
+        }
 
         for define in &self.node.defines {
-            result.push_str(&self.generate_define(define));
+            program.items.push(self.generate_define(define));
+        }
+
+        for prototype in &self.node.externs {
+            program.items.push(self.generate_extern(prototype));
         }
 
         for func in &self.node.functions {
-            result.push_str(&self.generate_function(func));
+            program.items.push(Item::Function(self.generate_function(func)));
         }
 
+        *self.stack_depth.borrow_mut() = 0;
         for stmt in &self.node.stmt {
-            result.push_str(&self.generate_statement(stmt));
+            program.items.extend(self.generate_statement(stmt));
         }
-        result
+
+        program.items.extend(self.generate_string_pool());
+        program
+    }
+
+    // The parameters of a function where `generate_function` put them
+    fn debug_function(&self, func: &NodeFunc) -> DebugFunction {
+        let word = self.target.word_size() as i64;
+        let parameters = func.arguments.iter().enumerate().map(|(index, arg)| Parameter {
+            name: self.generate_expr_ident(&arg.ident),
+            param_type: arg.param_type,
+            offset: if func.naked { None } else { Some((index as i64 + 2) * word) },
+        }).collect();
+        DebugFunction { name: self.generate_expr_ident(&func.name), loc: func.name.loc.clone(), parameters }
     }
 
     fn generate_expr_ident(&self, ident: &NodeExprIdent) -> String {
@@ -275,25 +487,187 @@ impl Generator {
     }
 
     fn generate_string(&self, string: &NodeExprString) -> String {
-        format!("{}", string.value)
+        let mut strings = self.strings.borrow_mut();
+        let index = match strings.iter().position(|interned| *interned == string.value) {
+            Some(index) => index,
+            None => {
+                strings.push(string.value.clone());
+                strings.len() - 1
+            }
+        };
+        format!("__str_{}", index)
+    }
+
+    fn generate_string_pool(&self) -> Vec<Item> {
+        let strings = self.strings.borrow();
+        if strings.is_empty() {
+            return Vec::new();
+        }
+
+        let mut items = vec![Item::Section("rodata".to_string())];
+        for (index, string) in strings.iter().enumerate() {
+            let label = format!("__str_{}", index);
+            items.push(Item::Data(Some(label.clone()), Data::Bytes(string_bytes(string))));
+            items.push(Item::Equ(format!("{}_len", label), EquValue::Since(label)));
+        }
+        items
+    }
+
+    fn generate_length(&self, string: &NodeExprLen) -> Value {
+        Value::Symbol(format!("{}_len", self.value(&string.ident)))
+    }
+
+    fn generate_sizeof(&self, sizeof: &NodeExprSizeof) -> Value {
+        if let NodeExpr::Ident(ident) = sizeof.ident.as_ref() {
+            if let Some(layout) = self.layouts.get(&ident.name) {
+                return Value::Number(layout.size as i64);
+            }
+        }
+        Value::Symbol(format!("{}_size", self.value(&sizeof.ident)))
+    }
+
+    fn generate_memory(&self, memory: &NodeExprMemory) -> Memory {
+        let mut address = Memory { size: None, base: None, index: None, displacement: Vec::new() };
+        match registers::lookup(&memory.base.name) {
+            Some(base) => address.base = Some(base),
+            None => address.displacement.push(Term { negative: false, value: Value::Symbol(self.generate_expr_ident(&memory.base)) }),
+        }
+
+        if let Some(offset) = &memory.offset {
+            match self.operand(offset, &HashMap::new()) {
+                Operand::Register(register) if memory.negative => {
+                    panic!("Register '{}' cannot be subtracted in an address", register.name)
+                }
+                Operand::Register(register) if address.base.is_none() => address.base = Some(register),
+                Operand::Register(register) => address.index = Some(register),
+                Operand::Immediate(value) => address.displacement.push(Term { negative: memory.negative, value }),
+                Operand::Memory(_) => panic!("Addresses cannot be nested"),
+            }
+        }
+
+        address.size = match &memory.field {
+            Some(field) => {
+                let (offset, field_layout) = layout::resolve_field(&self.layouts, &field.name)
+                    .unwrap_or_else(|| panic!("Unknown struct field '{}'", field.name));
+                if offset > 0 {
+                    address.displacement.push(Term { negative: false, value: Value::Number(offset as i64) });
+                }
+                field_layout.data_type
+            }
+            None => self.data_types.get(&memory.base.name).copied(),
+        };
+        address
     }
 
-    fn generate_length(&self, string: &NodeExprLen) -> String {
-        format!("{}_len", self.generate_expr(&string.ident))
+    fn operand_ident(&self, ident: &NodeExprIdent, params: &HashMap<String, Operand>) -> Operand {
+        if let Some(param) = params.get(&ident.name) {
+            return param.clone();
+        }
+        match registers::lookup(&ident.name) {
+            Some(register) => Operand::Register(register),
+            None => Operand::Immediate(self.value_ident(ident)),
+        }
+    }
+
+    fn operand(&self, expr: &NodeExpr, params: &HashMap<String, Operand>) -> Operand {
+        match expr {
+            NodeExpr::Ident(ident) => self.operand_ident(ident, params),
+            NodeExpr::Memory(memory) => Operand::Memory(self.generate_memory(memory)),
+            _ => Operand::Immediate(self.value(expr)),
+        }
+    }
+
+    fn value_ident(&self, ident: &NodeExprIdent) -> Value {
+        if let Some(value) = self.constants.get(&ident.name) {
+            return Value::Number(*value);
+        }
+        match layout::resolve_field(&self.layouts, &ident.name) {
+            Some((offset, _)) => Value::Number(offset as i64),
+            None => Value::Symbol(self.generate_expr_ident(ident)),
+        }
     }
 
-    fn generate_expr(&self, expr: &NodeExpr) -> String {
+    fn value(&self, expr: &NodeExpr) -> Value {
         match expr {
-            NodeExpr::Ident(ident) => self.generate_expr_ident(ident),
+            NodeExpr::Ident(ident) => self.value_ident(ident),
+            NodeExpr::BitOr(bit_or) => match consts::eval_expr(expr, &self.constants) {
+                Some(value) => Value::Number(value),
+                None => Value::Or(Box::new(self.value(&bit_or.left)), Box::new(self.value(&bit_or.right))),
+            },
             NodeExpr::Number(number) => self.generate_expr_number(number),
-            NodeExpr::String(string) => self.generate_string(string),
+            NodeExpr::String(string) => Value::Symbol(self.generate_string(string)),
             NodeExpr::Len(string) => self.generate_length(string),
-            _ => "".to_string(),
+            NodeExpr::Sizeof(sizeof) => self.generate_sizeof(sizeof),
+            NodeExpr::Memory(_) => panic!("A memory operand cannot be used as a value"),
+            _ => panic!("A comparison can only be used as a condition"),
         }
     }
 
-    fn generate_expr_number(&self, number: &NodeExprNumber) -> String {
-        number.value.to_string()
+    /// `[rbp + offset]` or `[rbp - offset]`, a parameter or saved register in the frame.
+    fn frame_slot(&self, size: Option<DataType>, below: bool, offset: usize) -> Memory {
+        Memory {
+            size,
+            base: Some(self.target.register("rbp")),
+            index: None,
+            displacement: vec![Term { negative: below, value: Value::Number(offset as i64) }],
+        }
+    }
+
+    fn generate_expr_number(&self, number: &NodeExprNumber) -> Value {
+        Value::Number(number.value)
     }
     
-}
\ No newline at end of file
+}
+
+fn register(name: &str) -> &'static Register {
+    registers::lookup(name).unwrap_or_else(|| panic!("Unknown register '{}'", name))
+}
+
+/// The bytes of a string literal with its escape sequences resolved.
+fn string_bytes(string: &str) -> Vec<u8> {
+    let bytes = string.as_bytes();
+    let mut result = Vec::new();
+    let mut skip_next = false;
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+
+        let escaped = match bytes.get(i + 1) {
+            Some(b'n') => Some(0x0a),
+            Some(b't') => Some(0x09),
+            Some(b'r') => Some(0x0d),
+            Some(b'0') => Some(0x00),
+            Some(b'\\') => Some(b'\\'),
+            _ => None,
+        };
+        if let (b'\\', Some(escaped)) = (byte, escaped) {
+            result.push(escaped);
+            skip_next = true; 
+        } else {
+            result.push(byte);
+        }
+    }
+
+    result
+}
+
+// Whether a statement moves rsp, which a function without a frame could not undo
+fn moves_stack(stmt: &NodeStmt) -> bool {
+    match stmt {
+        NodeStmt::Call(_) | NodeStmt::Push(_) | NodeStmt::Pop(_) => true,
+        NodeStmt::If(if_stmt) => if_stmt.body.iter().any(moves_stack),
+        NodeStmt::While(while_stmt) => while_stmt.body.iter().any(moves_stack),
+        _ => false,
+    }
+}
+
+/// A function that calls nothing, takes no parameters from the stack and leaves rsp and rbp
+/// alone can run without a frame of its own.
+fn is_leaf(func: &NodeFunc) -> bool {
+    func.arguments.is_empty()
+        && !func.body.iter().any(moves_stack)
+        && !clobbers::written(func).iter().any(|register| *register == "rsp" || *register == "rbp")
+}
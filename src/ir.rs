@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::parser::{DataType, ParamType};
+use crate::registers::Register;
+use crate::target::Target;
+use crate::tokenizer::Loc;
+
+/// A value the assembler knows, used for immediates, displacements and data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(i64),
+    // A label or an assembler constant such as `star_len`
+    Symbol(String),
+    // `a | b` of values that only the assembler can combine
+    Or(Box<Value>, Box<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Symbol(symbol) => write!(f, "{}", symbol),
+            Value::Or(left, right) => write!(f, "({} | {})", left, right),
+        }
+    }
+}
+
+/// One term of a memory displacement, added or subtracted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Term {
+    pub negative: bool,
+    pub value: Value,
+}
+
+/// `size [base + index + displacement...]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memory {
+    pub size: Option<DataType>,
+    pub base: Option<&'static Register>,
+    pub index: Option<&'static Register>,
+    pub displacement: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Register(&'static Register),
+    Immediate(Value),
+    Memory(Memory),
+}
+
+impl Operand {
+    pub fn register(&self) -> Option<&'static Register> {
+        match self {
+            Operand::Register(register) => Some(register),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opcode {
+    Mov,
+    Add,
+    Sub,
+    Xor,
+    Cmp,
+}
+
+impl Opcode {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Mov => "mov",
+            Opcode::Add => "add",
+            Opcode::Sub => "sub",
+            Opcode::Xor => "xor",
+            Opcode::Cmp => "cmp",
+        }
+    }
+}
+
+/// Condition of a conditional jump, on the flags of a signed `cmp`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    GreaterEqual,
+    Greater,
+    LessEqual,
+}
+
+impl Condition {
+    /// Suffix of the `j<cc>` mnemonic.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Condition::Equal => "e",
+            Condition::NotEqual => "ne",
+            Condition::Less => "l",
+            Condition::GreaterEqual => "ge",
+            Condition::Greater => "g",
+            Condition::LessEqual => "le",
+        }
+    }
+
+    /// The condition that holds exactly when this one does not.
+    pub fn negate(&self) -> Condition {
+        match self {
+            Condition::Equal => Condition::NotEqual,
+            Condition::NotEqual => Condition::Equal,
+            Condition::Less => Condition::GreaterEqual,
+            Condition::GreaterEqual => Condition::Less,
+            Condition::Greater => Condition::LessEqual,
+            Condition::LessEqual => Condition::Greater,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Binary(Opcode, Operand, Operand),
+    Push(Operand),
+    Pop(Operand),
+    Call(String),
+    // `jmp` without a condition
+    Jump(Option<Condition>, String),
+    Ret,
+    Syscall,
+    // `int n`, a software interrupt
+    Int(u8),
+}
+
+impl Instruction {
+    /// Whether control never falls through to the next instruction.
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Instruction::Jump(None, _) | Instruction::Ret)
+    }
+}
+
+/// The flat form of a function body: instructions with the labels between them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Code {
+    Label(String),
+    Instruction(Instruction),
+    // The instructions that follow were generated for the statement at this position, only
+    // present when compiling with `-g`
+    Line(Loc),
+}
+
+/// A run of instructions that is only entered at the top, optionally through a label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub label: Option<String>,
+    pub instructions: Vec<Instruction>,
+    // Source positions by the index of the first instruction generated for them
+    pub lines: Vec<(usize, Loc)>,
+}
+
+/// Control flow graph of a function: the successors of every block, by block index.
+#[derive(Debug)]
+pub struct Cfg {
+    pub successors: Vec<Vec<usize>>,
+}
+
+impl Cfg {
+    /// Blocks control can reach from the entry block.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.successors.len()];
+        let mut pending = vec![0];
+        while let Some(block) = pending.pop() {
+            if block < reachable.len() && !reachable[block] {
+                reachable[block] = true;
+                pending.extend(&self.successors[block]);
+            }
+        }
+        reachable
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub blocks: Vec<Block>,
+}
+
+impl Function {
+    /// Splits a flat body into blocks: a block starts at every label and after every jump
+    /// or return. A source position belongs to the instruction that follows it.
+    pub fn new(name: String, code: Vec<Code>) -> Function {
+        let mut blocks = vec![Block { label: None, instructions: Vec::new(), lines: Vec::new() }];
+        let mut line = None;
+        for item in code {
+            let current = blocks.last().unwrap();
+            let ends_block = current.instructions.last().is_some_and(|last| matches!(last, Instruction::Jump(..) | Instruction::Ret));
+            match item {
+                Code::Label(label) => {
+                    // The entry block never gets a label, a label at the very top of the body
+                    // starts a block of its own
+                    if current.label.is_none() && current.instructions.is_empty() && blocks.len() > 1 {
+                        blocks.last_mut().unwrap().label = Some(label);
+                    } else {
+                        blocks.push(Block { label: Some(label), instructions: Vec::new(), lines: Vec::new() });
+                    }
+                }
+                Code::Instruction(instruction) => {
+                    if ends_block {
+                        blocks.push(Block { label: None, instructions: Vec::new(), lines: Vec::new() });
+                    }
+                    let block = blocks.last_mut().unwrap();
+                    if let Some(loc) = line.take() {
+                        block.lines.push((block.instructions.len(), loc));
+                    }
+                    block.instructions.push(instruction);
+                }
+                Code::Line(loc) => line = Some(loc),
+            }
+        }
+        Function { name, blocks }
+    }
+
+    /// The body as labels, source positions and instructions in layout order.
+    pub fn code(&self) -> Vec<Code> {
+        let mut code = Vec::new();
+        for block in &self.blocks {
+            if let Some(label) = &block.label {
+                code.push(Code::Label(label.clone()));
+            }
+            let mut lines = block.lines.iter().peekable();
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                if let Some((_, loc)) = lines.next_if(|(line_index, _)| *line_index == index) {
+                    code.push(Code::Line(loc.clone()));
+                }
+                code.push(Code::Instruction(instruction.clone()));
+            }
+        }
+        code
+    }
+
+    pub fn cfg(&self) -> Cfg {
+        let labels: HashMap<&str, usize> = self.blocks.iter().enumerate()
+            .filter_map(|(index, block)| block.label.as_deref().map(|label| (label, index)))
+            .collect();
+
+        let mut successors = vec![Vec::new(); self.blocks.len()];
+        for (index, block) in self.blocks.iter().enumerate() {
+            let falls_through = !block.instructions.last().is_some_and(Instruction::is_terminator);
+            if let Some(Instruction::Jump(_, target)) = block.instructions.last() {
+                if let Some(target) = labels.get(target.as_str()) {
+                    successors[index].push(*target);
+                }
+            }
+            if falls_through && index + 1 < self.blocks.len() {
+                successors[index].push(index + 1);
+            }
+        }
+        Cfg { successors }
+    }
+}
+
+/// `name_size equ ...` and friends, which NASM computes from the layout of the section.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EquValue {
+    // `$ - label`: bytes emitted since the label
+    Since(String),
+    // `symbol / n`
+    Quotient(String, usize),
+    Symbol(String),
+    Number(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    // `dX v, ...`, optionally `times n dX v, ...`
+    Values { data_type: DataType, values: Vec<Value>, repeat: Option<Value> },
+    Bytes(Vec<u8>),
+    // `resX n`
+    Reserve { data_type: DataType, count: Value },
+    // `times n db 0`
+    Zero(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Define(String, Operand),
+    Extern(String),
+    Global(String),
+    Section(String),
+    Function(Function),
+    Label(String),
+    // Code outside of any function
+    Instruction(Instruction),
+    Data(Option<String>, Data),
+    Equ(String, EquValue),
+    // `align` in sections with contents, `alignb` in `.bss`
+    Align { alignment: Value, bss: bool },
+    // `times n - ($ - $$) db 0`, zeroes up to `n` bytes from the start of the section
+    Pad(Value),
+    // Address a flat binary is loaded at
+    Org(Value),
+    // Source position of the code outside of functions that follows, see `Code::Line`
+    Line(Loc),
+}
+
+/// A parameter in the stack frame of its function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub param_type: Option<ParamType>,
+    // Distance from the frame pointer, `None` in a naked function that has no frame
+    pub offset: Option<i64>,
+}
+
+/// What `-g` keeps about a function for debuggers besides its line positions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugFunction {
+    pub name: String,
+    pub loc: Loc,
+    pub parameters: Vec<Parameter>,
+}
+
+/// The source a program was compiled from and its functions, for debug information.
+#[derive(Debug)]
+pub struct DebugInfo {
+    pub source: PathBuf,
+    pub functions: Vec<DebugFunction>,
+}
+
+/// Everything the generator lowers a program into, in output order.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub items: Vec<Item>,
+    pub target: Target,
+    // Only there when compiling with `-g`
+    pub debug: Option<DebugInfo>,
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use crate::parser::{DataType, NodeStmt, NodeStruct};
+
+#[derive(Debug, Clone)]
+pub struct FieldLayout {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+    // Set for scalar fields only, arrays and nested structs have no single operand size
+    pub data_type: Option<DataType>,
+    // Name of the struct type for nested struct fields
+    pub struct_name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructLayout {
+    pub name: String,
+    pub size: usize,
+    pub align: usize,
+    pub fields: Vec<FieldLayout>,
+}
+
+impl StructLayout {
+    pub fn field(&self, name: &str) -> Option<&FieldLayout> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
+}
+
+/// Lays out every struct with natural alignment (each field aligned to its own size,
+/// the struct padded to its largest alignment). Packed structs use an alignment of one.
+/// Structs may only use struct types that are declared before them. The resolver reports
+/// repeated structs and unknown field types; the first declaration is kept and such fields
+/// are left out.
+pub fn compute_layouts(structs: &[NodeStruct]) -> HashMap<String, StructLayout> {
+    let mut layouts: HashMap<String, StructLayout> = HashMap::new();
+
+    for structure in structs {
+        if layouts.contains_key(&structure.name.name) {
+            continue;
+        }
+
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        let mut struct_align = 1;
+
+        for field in &structure.fields {
+            let count = field.count.unwrap_or(1);
+            let (element_size, element_align, data_type, struct_name) = match DataType::from_name(&field.type_name.name) {
+                Some(data_type) => (data_type.size(), data_type.size(), Some(data_type), None),
+                None => match layouts.get(&field.type_name.name) {
+                    Some(layout) => (layout.size, layout.align, None, Some(layout.name.clone())),
+                    None => continue,
+                },
+            };
+
+            let align = if structure.packed { 1 } else { element_align };
+            offset = align_up(offset, align);
+            struct_align = struct_align.max(align);
+
+            fields.push(FieldLayout {
+                name: field.ident.name.clone(),
+                offset,
+                size: element_size * count,
+                data_type: if field.count.is_some() { None } else { data_type },
+                struct_name: if field.count.is_some() { None } else { struct_name },
+            });
+            offset += element_size * count;
+        }
+
+        let layout = StructLayout {
+            name: structure.name.name.clone(),
+            size: align_up(offset, struct_align),
+            align: struct_align,
+            fields,
+        };
+        layouts.insert(layout.name.clone(), layout);
+    }
+
+    layouts
+}
+
+/// Resolves a dotted path such as `Point.x` or `Rect.origin.y` to the offset of the
+/// final field from the start of the outermost struct.
+pub fn resolve_field<'a>(layouts: &'a HashMap<String, StructLayout>, path: &str) -> Option<(usize, &'a FieldLayout)> {
+    let mut parts = path.split('.');
+    let mut layout = layouts.get(parts.next()?)?;
+    let mut offset = 0;
+    let mut field = layout.field(parts.next()?)?;
+    offset += field.offset;
+
+    for part in parts {
+        layout = layouts.get(field.struct_name.as_ref()?)?;
+        field = layout.field(part)?;
+        offset += field.offset;
+    }
+
+    Some((offset, field))
+}
+
+/// Element type of every typed data label, which is also the size of a memory operand
+/// that addresses it without naming a struct field.
+pub fn data_label_types(stmts: &[NodeStmt]) -> HashMap<String, DataType> {
+    let mut types = HashMap::new();
+    for stmt in stmts {
+        match stmt {
+            NodeStmt::Data(data) => {
+                types.insert(data.ident.name.clone(), data.data_type);
+            }
+            NodeStmt::Reserve(reserve) => {
+                types.insert(reserve.ident.name.clone(), reserve.data_type);
+            }
+            _ => {}
+        }
+    }
+    types
+}
@@ -0,0 +1,194 @@
+use crate::assembler::{Object, RelocationKind, Section, Target};
+use crate::elf;
+use crate::target;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// Address the first segment is loaded at, the same as `ld` uses for static executables.
+fn base_address(target: target::Target) -> u64 {
+    match target {
+        target::Target::X86_64 => 0x400000,
+        target::Target::X86 | target::Target::X86_16 => 0x8048000,
+    }
+}
+
+/// A part of the file that is mapped into memory, `memory_size` beyond `file_size` is zeroed.
+#[derive(Debug)]
+pub struct Segment {
+    pub offset: u64,
+    pub address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// An object with every section placed at its address and every relocation applied.
+#[derive(Debug)]
+pub struct Executable {
+    pub object: Object,
+    pub addresses: Vec<u64>,
+    // Position of every section in the file
+    pub offsets: Vec<u64>,
+    pub segments: Vec<Segment>,
+    pub entry: u64,
+}
+
+fn align(value: u64, alignment: u64) -> u64 {
+    value.next_multiple_of(alignment.max(1))
+}
+
+// The segment a section is loaded with: code, read-only data or writable data
+fn segment_index(section: &Section) -> usize {
+    if section.is_code() {
+        0
+    } else if section.is_writable() {
+        2
+    } else {
+        1
+    }
+}
+
+fn apply(object: &mut Object, addresses: &[u64]) -> Result<(), String> {
+    for index in 0..object.sections.len() {
+        let relocations = std::mem::take(&mut object.sections[index].relocations);
+        for relocation in &relocations {
+            let target = match &relocation.target {
+                Target::Section(section) => addresses[*section],
+                Target::Symbol(name) => return Err(format!("undefined reference to '{}'", name)),
+            };
+            let value = target.wrapping_add_signed(relocation.addend);
+            let place = addresses[index] + relocation.offset;
+            let bytes = match relocation.kind {
+                RelocationKind::Absolute64 => value.to_le_bytes().to_vec(),
+                RelocationKind::Absolute32 => {
+                    let value = u32::try_from(value).map_err(|_| format!("address {:#x} does not fit in 32 bits", value))?;
+                    value.to_le_bytes().to_vec()
+                }
+                RelocationKind::Absolute32Signed => {
+                    let value = i32::try_from(value as i64).map_err(|_| format!("address {:#x} does not fit in 32 bits", value))?;
+                    value.to_le_bytes().to_vec()
+                }
+                RelocationKind::Relative32 => {
+                    let distance = value.wrapping_sub(place) as i64;
+                    let distance = i32::try_from(distance).map_err(|_| format!("{:#x} is out of reach of a 32-bit displacement", value))?;
+                    distance.to_le_bytes().to_vec()
+                }
+                RelocationKind::Absolute16 => {
+                    let value = u16::try_from(value).map_err(|_| format!("address {:#x} does not fit in 16 bits", value))?;
+                    value.to_le_bytes().to_vec()
+                }
+                // 16-bit code wraps around within its segment
+                RelocationKind::Relative16 => (value.wrapping_sub(place) as u16).to_le_bytes().to_vec(),
+            };
+            let start = relocation.offset as usize;
+            object.sections[index].data[start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+    }
+    Ok(())
+}
+
+/// Lays out the sections of a single object in a static executable and resolves every
+/// relocation. Code, read-only data and writable data each get a segment of their own,
+/// `.bss` goes at the end of the writable one. Debug information follows the segments in
+/// the file and is never loaded.
+pub fn link(mut object: Object, entry: &str) -> Result<Executable, String> {
+    let mut groups: [Vec<usize>; 3] = Default::default();
+    for (index, section) in object.sections.iter().enumerate() {
+        if !section.is_debug() {
+            groups[segment_index(section)].push(index);
+        }
+    }
+    // The sections that take up room in the file come before `.bss`
+    groups[2].sort_by_key(|index| object.sections[*index].bss);
+
+    // The ELF and program headers are loaded with the code segment, which is always there
+    let segment_count = 1 + groups[1..].iter().filter(|group| !group.is_empty()).count() as u64;
+    let base = base_address(object.target);
+    let mut offset = elf::header_size(object.target) + segment_count * elf::program_header_size(object.target);
+    let mut addresses = vec![0; object.sections.len()];
+    let mut offsets = vec![0; object.sections.len()];
+    let mut segments = Vec::new();
+
+    for (group_index, group) in groups.iter().enumerate() {
+        if group_index > 0 {
+            if group.is_empty() {
+                continue;
+            }
+            offset = align(offset, PAGE_SIZE);
+        }
+        let start = if group_index == 0 { 0 } else { offset };
+        // Addresses follow the file offsets, `.bss` only moves the address on
+        let mut address = base + offset;
+        for index in group {
+            let section = &object.sections[*index];
+            address = align(address, section.alignment);
+            if !section.bss {
+                offset = address - base;
+            }
+            addresses[*index] = address;
+            offsets[*index] = offset;
+            address += section.size;
+            if !section.bss {
+                offset += section.size;
+            }
+        }
+        segments.push(Segment {
+            offset: start,
+            address: base + start,
+            file_size: offset - start,
+            memory_size: address - (base + start),
+            writable: group_index == 2,
+            executable: group_index == 0,
+        });
+    }
+
+    // Offsets within the debug sections are measured from their start, they keep address 0
+    for (index, section) in object.sections.iter().enumerate() {
+        if section.is_debug() {
+            offsets[index] = offset;
+            offset += section.size;
+        }
+    }
+
+    let entry = object.symbols.iter()
+        .find(|symbol| symbol.name == entry && symbol.section.is_some())
+        .map(|symbol| addresses[symbol.section.unwrap()] + symbol.offset)
+        .ok_or_else(|| format!("entry point '{}' is not defined", entry))?;
+
+    apply(&mut object, &addresses)?;
+    Ok(Executable { object, addresses, offsets, segments, entry })
+}
+
+/// Lays out the sections one after the other from the `org` address, or 0, and resolves
+/// every relocation, the way NASM's `-f bin` does. `.text` comes first and `.bss` last, which
+/// only takes up addresses. The result is the memory image itself, without any headers.
+pub fn flat(mut object: Object) -> Result<Vec<u8>, String> {
+    let mut order: Vec<usize> = (0..object.sections.len()).collect();
+    order.sort_by_key(|index| (!object.sections[*index].is_code(), object.sections[*index].bss));
+
+    let origin = object.origin.unwrap_or(0);
+    let mut addresses = vec![0; object.sections.len()];
+    let mut address = origin;
+    for (position, index) in order.iter().enumerate() {
+        let section = &object.sections[*index];
+        // The first section starts right at the origin
+        if position > 0 {
+            address = align(address, section.alignment);
+        }
+        addresses[*index] = address;
+        address += section.size;
+    }
+
+    apply(&mut object, &addresses)?;
+    let mut image = Vec::new();
+    for index in order {
+        let section = &object.sections[index];
+        if section.bss {
+            break;
+        }
+        image.resize((addresses[index] - origin) as usize, 0);
+        image.extend_from_slice(&section.data);
+    }
+    Ok(image)
+}
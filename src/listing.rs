@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::assembler::{Object, Placed};
+use crate::ir::{Code, Item, Program};
+use crate::nasm;
+use crate::stdlib;
+use crate::target::Target;
+use crate::tokenizer::Loc;
+
+// Encoded bytes shown on a line of the listing, longer runs continue on the lines below
+const BYTES_PER_LINE: usize = 10;
+// Lines of bytes shown for a single item
+const MAX_LINES: usize = 4;
+
+/// The lines of the files a program was compiled from, read as the listing needs them.
+pub struct Sources {
+    target: Target,
+    files: HashMap<PathBuf, Vec<String>>,
+}
+
+impl Sources {
+    pub fn new(target: Target) -> Sources {
+        Sources { target, files: HashMap::new() }
+    }
+
+    fn line(&mut self, loc: &Loc) -> String {
+        let target = self.target;
+        let lines = self.files.entry(loc.file.to_path_buf()).or_insert_with(|| {
+            // `<name>` is a bundled library, which has no file on disk
+            let name = loc.file.to_str().and_then(|file| file.strip_prefix('<')).and_then(|file| file.strip_suffix('>'));
+            let contents = match name {
+                Some(name) => stdlib::bundled(name, target).map(str::to_string),
+                None => fs::read_to_string(&*loc.file).ok(),
+            };
+            contents.unwrap_or_default().lines().map(str::to_string).collect()
+        });
+        lines.get(loc.line - 1).map(|line| line.trim().to_string()).unwrap_or_default()
+    }
+
+    /// A statement as a comment above the code generated for it.
+    pub fn comment(&mut self, loc: &Loc) -> String {
+        format!("; {}:{}: {}\n", loc.file.display(), loc.line, self.line(loc))
+    }
+}
+
+// The bytes of a placed item in hex, with the fields a linker still fills in in brackets as
+// NASM lists them
+fn hex(object: &Object, placed: &Placed) -> Vec<String> {
+    let section = &object.sections[placed.section];
+    if section.bss {
+        return if placed.size > 0 { vec![format!("<res {:X}>", placed.size)] } else { Vec::new() };
+    }
+    let (start, end) = (placed.offset as usize, (placed.offset + placed.size) as usize);
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut count = 0;
+    let mut index = start;
+    while index < end {
+        let relocation = section.relocations.iter().find(|relocation| relocation.offset as usize == index);
+        let width = relocation.map(|relocation| relocation.kind.width()).unwrap_or(1);
+        if count > 0 && count + width > BYTES_PER_LINE {
+            chunks.push(std::mem::take(&mut chunk));
+            count = 0;
+        }
+        let bytes: String = section.data[index..index + width].iter().map(|byte| format!("{:02X}", byte)).collect();
+        match relocation {
+            Some(_) => chunk.push_str(&format!("[{}]", bytes)),
+            None => chunk.push_str(&bytes),
+        }
+        count += width;
+        index += width;
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    // Padding and repeated data would fill pages, their size says enough
+    if chunks.len() > MAX_LINES {
+        chunks.truncate(MAX_LINES - 1);
+        chunks.push(format!("<{:X} bytes in all>", placed.size));
+    }
+    chunks
+}
+
+// Source text with the offset and bytes of the item in front of its first line
+fn columns(text: &str, offset: Option<u64>, bytes: &[String]) -> String {
+    let mut lines = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let prefix = match (index, offset, bytes.first()) {
+            (0, Some(offset), Some(first)) => format!("{:08X} {:<width$}", offset, first, width = BYTES_PER_LINE * 2),
+            _ => String::new(),
+        };
+        lines.push(format!("{:<width$} {}", prefix, line, width = 8 + 1 + BYTES_PER_LINE * 2));
+    }
+    for more in bytes.iter().skip(1) {
+        lines.push(format!("{:8} {}", "", more));
+    }
+    lines.iter().map(|line| format!("{}\n", line.trim_end())).collect()
+}
+
+fn placed_columns(object: &Object, text: &str, placed: &Placed) -> String {
+    columns(text, Some(placed.offset), &hex(object, placed))
+}
+
+/// Writes every statement of the program as a comment followed by the instructions generated
+/// for it, in NASM syntax, with the section offset and encoded bytes of every instruction and
+/// item in front of it. `placed` comes from `assembler::assemble`.
+pub fn write(program: &Program, object: &Object, placed: &[Placed], sources: &mut Sources) -> String {
+    let mut placed = placed.iter();
+    let mut result = String::new();
+    let mut in_text = true;
+    for item in &program.items {
+        match item {
+            Item::Function(function) => {
+                result.push_str(&columns(&format!("{}:", function.name), None, &[]));
+                for code in function.code() {
+                    match code {
+                        Code::Label(label) => result.push_str(&columns(&format!("{}:", label), None, &[])),
+                        Code::Line(loc) => result.push_str(&columns(&sources.comment(&loc), None, &[])),
+                        Code::Instruction(instruction) => {
+                            let text = format!("  {}", nasm::instruction(&instruction));
+                            result.push_str(&placed_columns(object, &text, placed.next().expect("every instruction is placed")));
+                        }
+                    }
+                }
+            }
+            Item::Line(loc) => {
+                placed.next();
+                result.push_str(&columns(&sources.comment(loc), None, &[]));
+            }
+            item => {
+                let text = nasm::emit_item(item, &mut in_text);
+                result.push_str(&placed_columns(object, &text, placed.next().expect("every item is placed")));
+            }
+        }
+    }
+    result
+}
@@ -1,72 +1,370 @@
 #![allow(nonstandard_style)]
 use std::fs;
 use std::env;
-use std::io::Write;
-use std::process::Command;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::{SystemTime, UNIX_EPOCH};
+mod cli;
 mod tokenizer;
 mod parser;
 mod generator;
+mod layout;
+mod consts;
+mod stdlib;
+mod modules;
+mod registers;
+mod clobbers;
+mod operands;
+mod ir;
+mod nasm;
+mod gas;
+mod encoder;
+mod assembler;
+mod elf;
+mod linker;
+mod target;
+mod peephole;
+mod dwarf;
+mod listing;
+mod diagnostic;
+mod resolver;
+
+use cli::{Emit, Options, Syntax};
+
+/// Where the intermediate files of a compilation go: a directory of its own that is removed
+/// once the compilation is done, or next to the output with `--keep-temps`.
+struct Temps {
+    // Created on first use, see `create_dir`
+    dir: Option<PathBuf>,
+    // The input name, the intermediate files are named after it
+    stem: PathBuf,
+    // `--keep-temps` names the files after the output instead and leaves them
+    kept: Option<PathBuf>,
+}
+
+impl Temps {
+    fn new(options: &Options) -> Temps {
+        let stem = PathBuf::from(options.input.file_stem().unwrap_or(options.input.as_os_str()));
+        Temps {
+            dir: None,
+            kept: if options.keep_temps { options.output_path() } else { None },
+            stem,
+        }
+    }
+
+    // A new directory only the user can enter. The name cannot be guessed in advance and a
+    // directory that already exists, whoever made it, is never used
+    fn create_dir() -> io::Result<PathBuf> {
+        let mut builder = fs::DirBuilder::new();
+        builder.mode(0o700);
+        let mut attempt = 0u64;
+        loop {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+            let suffix = random_suffix(nanos ^ ((process::id() as u64) << 32) ^ attempt);
+            let dir = env::temp_dir().join(format!("asmpp-{}", suffix));
+            match builder.create(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// The path of an intermediate file with the given extension, `""` for none.
+    fn path(&mut self, extension: &str) -> Result<PathBuf, String> {
+        if let Some(output) = &self.kept {
+            return Ok(PathBuf::from(format!("{}.{}", output.display(), extension)));
+        }
+        if self.dir.is_none() {
+            let dir = Temps::create_dir()
+                .map_err(|error| format!("error: cannot create a directory in {}: {}", env::temp_dir().display(), error))?;
+            self.dir = Some(dir);
+        }
+        Ok(self.dir.as_ref().expect("created above").join(self.stem.with_extension(extension)))
+    }
+}
+
+impl Drop for Temps {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+// Twelve letters and digits mixed from the seed, so that close seeds give unrelated names
+fn random_suffix(seed: u64) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+    // splitmix64
+    let mut state = seed.wrapping_add(0x9e3779b97f4a7c15);
+    state = (state ^ (state >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    state = (state ^ (state >> 27)).wrapping_mul(0x94d049bb133111eb);
+    state ^= state >> 31;
+    (0..12).map(|_| {
+        let letter = ALPHABET[(state % ALPHABET.len() as u64) as usize] as char;
+        state /= ALPHABET.len() as u64;
+        letter
+    }).collect()
+}
+
+fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), String> {
+    fs::write(path, contents).map_err(|error| format!("error: cannot write {}: {}", path.display(), error))
+}
+
+// Tokens and the syntax tree are printed unless `-o` names a file for them
+fn write_dump(options: &Options, text: String) -> Result<i32, String> {
+    match &options.output {
+        Some(path) => write(path, text)?,
+        None => print!("{}", text),
+    }
+    Ok(0)
+}
+
+// Runs an assembler or the linker and passes on what it prints, every message through
+// `message`. The compilation stops when the tool fails
+fn run_tool(command: &mut Command, message: impl Fn(&str) -> String) -> Result<(), String> {
+    let name = command.get_program().to_string_lossy().to_string();
+    let output = command.output().map_err(|error| format!("error: cannot run {}: {}", name, error))?;
+
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        eprintln!("{}", message(line));
+    }
+    if !output.status.success() {
+        return Err(format!("error: {} failed ({})", name, output.status));
+    }
+    Ok(())
+}
+
+// NASM reports problems as `file.asm:123: error: message`, which is turned into the statement
+// that line was generated for, `file.asmpp:14:5: error: message`
+fn source_message(message: &str, asm_file: &Path, positions: &[Option<tokenizer::Loc>]) -> String {
+    let prefix = format!("{}:", asm_file.display());
+    let loc = message.strip_prefix(&prefix)
+        .and_then(|rest| rest.split_once(':'))
+        .and_then(|(line, rest)| Some((line.parse::<usize>().ok()?, rest)))
+        .and_then(|(line, rest)| Some((positions.get(line.checked_sub(1)?)?.as_ref()?, rest)));
+    match loc {
+        Some((loc, rest)) => format!("{}:{}", loc, rest),
+        None => message.to_string(),
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    // Check for minimum arguments needed after including -f and -o options
-    if args.len() < 6 {
-        println!("Usage: {} -f <format> -o <output file name> <input file name>", args[0]);
-        return;
-    }
-    
-    // Parsing command line arguments for -f and -o options
-    let format = match args.iter().position(|x| x == "-f") {
-        Some(index) => args.get(index + 1).expect("Format not specified after -f").to_string(),
-        None => {
-            println!("Format not specified. Use -f option.");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match cli::parse(&args) {
+        Ok(cli::Command::Compile(options)) => options,
+        Ok(cli::Command::Help) => {
+            print!("{}", cli::USAGE);
             return;
-        },
-    };
-
-    let output_file_name = match args.iter().position(|x| x == "-o") {
-        Some(index) => args.get(index + 1).expect("Output file name not specified after -o").to_string(),
-        None => {
-            println!("Output file name not specified. Use -o option.");
+        }
+        Ok(cli::Command::Version) => {
+            println!("asmpp {}", env!("CARGO_PKG_VERSION"));
             return;
-        },
+        }
+        Err(message) => {
+            eprintln!("error: {}", message);
+            eprintln!("Run with --help to see the options.");
+            process::exit(2);
+        }
     };
 
-    let input_file_name = args.last().expect("No input file name provided");
+    let mut temps = Temps::new(&options);
+    let status = compile(&options, &mut temps).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        1
+    });
+    // `process::exit` skips destructors, the intermediate files go first
+    drop(temps);
+    process::exit(status);
+}
 
-    let contents = fs::read_to_string(input_file_name).expect("Something went wrong reading the file");
-    let mut tokenizer = tokenizer::Tokenizer::new(contents);
+/// Compiles the input as far as `--emit` asks and returns the exit status, which is the one of
+/// the program itself with `--run`.
+fn compile(options: &Options, temps: &mut Temps) -> Result<i32, String> {
+    let input = &options.input;
+    let flat = options.flat();
+    let format = &options.format;
+    let mut target = target::Target::from_format(format);
+
+    let contents = fs::read_to_string(input).map_err(|error| format!("error: cannot read {}: {}", input.display(), error))?;
+    let mut tokenizer = tokenizer::Tokenizer::new(contents, input);
     let tokens = tokenizer.tokenize();
+    if options.emit == Emit::Tokens {
+        return write_dump(options, tokens.iter().map(|token| format!("{}: {}\n", token.loc, token)).collect());
+    }
     let mut parser = parser::Parser::new(tokens);
+    for dir in &options.include_dirs {
+        parser.add_include_dir(dir.clone());
+    }
+    if let Some(dir) = &options.stdlib_dir {
+        parser.set_stdlib_dir(dir.clone());
+    }
+    parser.set_target(target);
     let node = parser.parse_prog();
-    let generator = generator::Generator::new(node);
-    let result = generator.generate();
-
-    // Use the specified output file name for the assembly file
-    let asm_file_name = format!("{}.asm", output_file_name);
-    let mut file = fs::File::create(&asm_file_name).expect("Unable to create file");
-    file.write_all(result.as_bytes()).expect("Unable to write data");
-
-    let output = Command::new("nasm")
-        .arg("-f")
-        .arg(&format)
-        .arg(&asm_file_name)
-        .arg("-o")
-        .arg(format!("{}.o", output_file_name))
-        .output()
-        .expect("Failed to execute command");
-
-    println!("{}", String::from_utf8_lossy(&output.stdout));
-    println!("{}", String::from_utf8_lossy(&output.stderr));
-
-    // Use the specified output file name for the linker output
-    let output = Command::new("ld")
-        .arg("-o")
-        .arg(&output_file_name)
-        .arg(format!("{}.o", output_file_name))
-        .output()
-        .expect("Failed to execute command");
-
-    println!("{}", String::from_utf8_lossy(&output.stdout));
-    println!("{}", String::from_utf8_lossy(&output.stderr));
+    if options.emit == Emit::Ast {
+        return write_dump(options, format!("{:#?}\n", node));
+    }
+
+    // `bits` picks the width of a flat binary, any other format already has one
+    if let Some(bits) = &node.bits {
+        let chosen = target::Target::from_bits(bits.bits).expect("The parser only accepts 16, 32 and 64 bits");
+        if !flat && chosen != target {
+            return Err(format!("{}: error: 'bits {}' does not match -f {}, which is {}-bit", bits.loc, bits.bits, format, target.bits()));
+        }
+        target = chosen;
+    }
+
+    let mut resolver = resolver::Resolver::new(&node);
+    resolver.set_warn_clobbers(options.warn_clobbers);
+    resolver.set_target(target);
+    resolver.set_flat(flat);
+    let diagnostics = resolver.resolve();
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+        return Ok(1);
+    }
+
+    let listing = options.emit == Emit::Listing;
+    let mut generator = generator::Generator::new(node, target);
+    if options.debug {
+        generator.set_debug(input.clone());
+    } else if options.emit != Emit::Asm && !matches!(options.syntax, Some(Syntax::Gas(_))) {
+        // Errors of the built-in assembler and NASM's messages are traced back to the
+        // statements through the line markers
+        generator.set_lines();
+    }
+    let mut program = generator.generate();
+    if options.optimise {
+        peephole::optimise(&mut program);
+    }
+
+    // An executable that is only built to be run goes with the intermediate files
+    let output = match options.output_path() {
+        Some(_) if options.run && options.output.is_none() && !options.keep_temps => temps.path("")?,
+        Some(output) => output,
+        None => unreachable!("only tokens and the syntax tree go to standard output"),
+    };
+    let object_file = if options.emit == Emit::Obj { output.clone() } else { temps.path("o")? };
+
+    match options.syntax {
+        // The generated source is what -S asks for, whichever assembler would read it
+        None | Some(Syntax::Nasm) if options.emit == Emit::Asm => {
+            write(&output, nasm::emit(&program))?;
+            return Ok(0);
+        }
+        Some(Syntax::Gas(dialect)) if options.emit == Emit::Asm => {
+            write(&output, gas::emit(&program, dialect))?;
+            return Ok(0);
+        }
+        Some(Syntax::Nasm) => {
+            // NASM lists the source it is given, which has the statements as comments
+            let mut sources = listing::Sources::new(target);
+            let (result, positions) = nasm::emit_with_map(&program, |loc| {
+                if listing {
+                    sources.comment(loc)
+                } else if options.debug {
+                    nasm::line(loc)
+                } else {
+                    String::new()
+                }
+            });
+            let asm_file = temps.path("asm")?;
+            write(&asm_file, result)?;
+
+            let mut nasm = Command::new("nasm");
+            if options.debug {
+                nasm.arg("-g").arg("-F").arg("dwarf");
+            }
+            if listing {
+                nasm.arg("-l").arg(&output);
+            }
+            run_tool(nasm
+                .arg("-f")
+                .arg(format)
+                .arg(&asm_file)
+                .arg("-o")
+                .arg(if flat && !listing { &output } else { &object_file }),
+                |message| source_message(message, &asm_file, &positions))?;
+            if flat || listing || options.emit == Emit::Obj {
+                return Ok(0);
+            }
+        }
+        Some(Syntax::Gas(dialect)) => {
+            if format != "elf64" && format != "elf32" {
+                return Err(format!("error: the GNU assembler backend only supports elf64 and elf32, use --asm-syntax nasm for '{}'", format));
+            }
+            let asm_file = temps.path("s")?;
+            write(&asm_file, gas::emit(&program, dialect))?;
+
+            run_tool(Command::new("as")
+                .arg(format!("--{}", target.bits()))
+                .arg(&asm_file)
+                .arg("-o")
+                .arg(&object_file),
+                str::to_string)?;
+            if options.emit == Emit::Obj {
+                return Ok(0);
+            }
+        }
+        None => {
+            if format != "elf64" && format != "elf32" && !flat {
+                return Err(format!("error: the built-in assembler only supports elf64, elf32 and bin, use --assembler nasm for '{}'", format));
+            }
+            let (object, placed) = assembler::assemble(&program).map_err(|error| match error.loc {
+                Some(loc) => format!("{}: error: {}", loc, error.message),
+                None => format!("{}: error: {}", input.display(), error.message),
+            })?;
+            if listing {
+                let mut sources = listing::Sources::new(target);
+                write(&output, listing::write(&program, &object, &placed, &mut sources))?;
+                return Ok(0);
+            }
+            if flat {
+                let image = linker::flat(object).map_err(|message| format!("{}: error: {}", input.display(), message))?;
+                write(&output, image)?;
+                return Ok(0);
+            }
+            if options.emit == Emit::Obj || options.use_ld {
+                write(&object_file, elf::write_object(&object))?;
+            }
+            if options.emit == Emit::Obj {
+                return Ok(0);
+            }
+            if !options.use_ld {
+                let executable = linker::link(object, &options.entry).map_err(|message| format!("{}: error: {}", input.display(), message))?;
+                write(&output, elf::write_executable(&executable))?;
+                fs::set_permissions(&output, fs::Permissions::from_mode(0o755))
+                    .map_err(|error| format!("error: cannot make {} executable: {}", output.display(), error))?;
+            }
+        }
+    }
+
+    if options.use_ld {
+        run_tool(Command::new("ld")
+            .arg("-m")
+            .arg(target.ld_emulation())
+            .arg("-e")
+            .arg(&options.entry)
+            .arg("-o")
+            .arg(&output)
+            .arg(&object_file),
+            str::to_string)?;
+    }
+
+    if !options.run {
+        return Ok(0);
+    }
+    // A bare name would be looked up on PATH
+    let program_path = Path::new(".").join(&output);
+    let status = Command::new(&program_path)
+        .status()
+        .map_err(|error| format!("error: cannot run {}: {}", output.display(), error))?;
+    // A program killed by a signal exits with 128 and the signal number, as a shell reports it
+    Ok(status.code().or(status.signal().map(|signal| 128 + signal)).unwrap_or(1))
 }
@@ -0,0 +1,201 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Node, NodeExpr, NodeExprIdent, NodeStmt};
+
+/// Symbol name of a module member: exported members are `module.name`, private members
+/// `module..name`, which cannot be written in source and so cannot collide with anything.
+pub fn mangled_name(module: &str, name: &str, public: bool) -> String {
+    if public {
+        format!("{}.{}", module, name)
+    } else {
+        format!("{}..{}", module, name)
+    }
+}
+
+// Functions and data labels declared by each module, with their `pub` flag
+struct Modules {
+    members: HashMap<String, HashMap<String, bool>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Modules {
+    /// Resolves a name used inside `context` (`None` outside of any module). Unqualified names
+    /// find members of the current module first; `module.name` finds exported members of any
+    /// module and private members of the current one. A member the module does not have is
+    /// left to the resolver to report as undefined.
+    fn resolve(&mut self, ident: &NodeExprIdent, context: Option<&str>) -> Option<String> {
+        let name = ident.name.as_str();
+        if let Some(module) = context {
+            if let Some(public) = self.members.get(module).and_then(|members| members.get(name)) {
+                return Some(mangled_name(module, name, *public));
+            }
+        }
+
+        let (module, member) = name.split_once('.')?;
+        let members = self.members.get(module)?;
+        match members.get(member) {
+            Some(true) => Some(mangled_name(module, member, true)),
+            Some(false) if context == Some(module) => Some(mangled_name(module, member, false)),
+            Some(false) => {
+                let message = format!("'{}' is private to module '{}', declare it with 'pub' to use it from outside", member, module);
+                self.diagnostics.push(Diagnostic::error(&ident.loc, message));
+                // Still renamed, so that the name is not reported as undefined as well
+                Some(mangled_name(module, member, false))
+            }
+            None => None,
+        }
+    }
+
+    fn rename_ident(&mut self, ident: &mut NodeExprIdent, context: Option<&str>, locals: &HashSet<String>) {
+        if locals.contains(&ident.name) {
+            return;
+        }
+        if let Some(mangled) = self.resolve(ident, context) {
+            ident.name = mangled;
+        }
+    }
+
+    fn rename_expr(&mut self, expr: &mut NodeExpr, context: Option<&str>, locals: &HashSet<String>) {
+        match expr {
+            NodeExpr::Ident(ident) => self.rename_ident(ident, context, locals),
+            NodeExpr::Memory(memory) => self.rename_ident(&mut memory.base, context, locals),
+            NodeExpr::Len(len) => self.rename_expr(&mut len.ident, context, locals),
+            NodeExpr::Sizeof(sizeof) => self.rename_expr(&mut sizeof.ident, context, locals),
+            NodeExpr::Equal(cmp) => {
+                self.rename_expr(&mut cmp.left, context, locals);
+                self.rename_expr(&mut cmp.right, context, locals);
+            }
+            NodeExpr::Lesser(cmp) => {
+                self.rename_expr(&mut cmp.left, context, locals);
+                self.rename_expr(&mut cmp.right, context, locals);
+            }
+            NodeExpr::Greater(cmp) => {
+                self.rename_expr(&mut cmp.left, context, locals);
+                self.rename_expr(&mut cmp.right, context, locals);
+            }
+            NodeExpr::NotEqual(cmp) => {
+                self.rename_expr(&mut cmp.left, context, locals);
+                self.rename_expr(&mut cmp.right, context, locals);
+            }
+            NodeExpr::BitOr(bit_or) => {
+                self.rename_expr(&mut bit_or.left, context, locals);
+                self.rename_expr(&mut bit_or.right, context, locals);
+            }
+            NodeExpr::Number(_) | NodeExpr::String(_) => {}
+        }
+    }
+
+    fn rename_stmt(&mut self, stmt: &mut NodeStmt, context: Option<&str>, locals: &HashSet<String>) {
+        match stmt {
+            NodeStmt::Mov(mov) => {
+                self.rename_expr(&mut mov.dest, context, locals);
+                self.rename_expr(&mut mov.expr, context, locals);
+            }
+            NodeStmt::Add(add) => {
+                self.rename_expr(&mut add.dest, context, locals);
+                self.rename_expr(&mut add.expr, context, locals);
+            }
+            NodeStmt::Xor(xor) => {
+                self.rename_expr(&mut xor.dest, context, locals);
+                self.rename_expr(&mut xor.expr, context, locals);
+            }
+            NodeStmt::Push(push) => self.rename_expr(&mut push.expr, context, locals),
+            NodeStmt::Int(int) => self.rename_expr(&mut int.vector, context, locals),
+            NodeStmt::Pop(pop) => self.rename_ident(&mut pop.ident, context, locals),
+            NodeStmt::Global(global) => self.rename_ident(&mut global.ident, context, locals),
+            NodeStmt::Call(call) => {
+                self.rename_ident(&mut call.name, context, locals);
+                for argument in &mut call.arguments {
+                    self.rename_expr(argument, context, locals);
+                }
+            }
+            NodeStmt::If(if_stmt) => {
+                self.rename_expr(&mut if_stmt.condition, context, locals);
+                for stmt in &mut if_stmt.body {
+                    self.rename_stmt(stmt, context, locals);
+                }
+            }
+            NodeStmt::While(while_stmt) => {
+                self.rename_expr(&mut while_stmt.condition, context, locals);
+                for stmt in &mut while_stmt.body {
+                    self.rename_stmt(stmt, context, locals);
+                }
+            }
+            NodeStmt::Data(data) => {
+                for value in &mut data.values {
+                    self.rename_expr(value, context, locals);
+                }
+            }
+            NodeStmt::Instance(instance) => {
+                for field in &mut instance.fields {
+                    self.rename_expr(&mut field.expr, context, locals);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn data_label(stmt: &mut NodeStmt) -> Option<&mut NodeExprIdent> {
+    match stmt {
+        NodeStmt::Assign(assign) => Some(&mut assign.ident),
+        NodeStmt::Data(data) => Some(&mut data.ident),
+        NodeStmt::Reserve(reserve) => Some(&mut reserve.ident),
+        NodeStmt::Instance(instance) => Some(&mut instance.ident),
+        _ => None,
+    }
+}
+
+/// Gives every function and data label declared inside a `module` its mangled symbol name
+/// and rewrites all references to them. `stmt_scopes` holds the module and `pub` flag of
+/// each top-level statement in `node.stmt`. Returns the names that cannot be used where they
+/// are, for the resolver to report.
+pub fn mangle(node: &mut Node, stmt_scopes: &[(Option<String>, bool)]) -> Vec<Diagnostic> {
+    let mut modules = Modules { members: HashMap::new(), diagnostics: Vec::new() };
+
+    // The first declaration of a member counts
+    let mut declare = |module: &str, ident: &NodeExprIdent, public: bool| {
+        let members = modules.members.entry(module.to_string()).or_default();
+        if members.contains_key(&ident.name) {
+            let message = format!("'{}' is declared more than once in module '{}'", ident.name, module);
+            modules.diagnostics.push(Diagnostic::error(&ident.loc, message));
+        } else {
+            members.insert(ident.name.clone(), public);
+        }
+    };
+    for func in &node.functions {
+        if let Some(module) = &func.module {
+            declare(module, &func.name, func.public);
+        }
+    }
+    for (stmt, (module, public)) in node.stmt.iter_mut().zip(stmt_scopes) {
+        if let (Some(module), Some(label)) = (module, data_label(stmt)) {
+            declare(module, label, *public);
+        }
+    }
+
+    if modules.members.is_empty() {
+        return modules.diagnostics;
+    }
+
+    for func in &mut node.functions {
+        let context = func.module.as_deref();
+        let locals: HashSet<String> = func.arguments.iter().map(|argument| argument.ident.name.clone()).collect();
+        for stmt in &mut func.body {
+            modules.rename_stmt(stmt, context, &locals);
+        }
+        if let Some(module) = context {
+            func.name.name = mangled_name(module, &func.name.name, func.public);
+        }
+    }
+
+    let no_locals = HashSet::new();
+    for (stmt, (module, public)) in node.stmt.iter_mut().zip(stmt_scopes) {
+        modules.rename_stmt(stmt, module.as_deref(), &no_locals);
+        if let (Some(module), Some(label)) = (module, data_label(stmt)) {
+            label.name = mangled_name(module, &label.name, *public);
+        }
+    }
+    modules.diagnostics
+}
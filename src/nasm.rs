@@ -0,0 +1,182 @@
+use crate::ir::{Code, Data, EquValue, Function, Instruction, Item, Memory, Operand, Program};
+use crate::tokenizer::Loc;
+
+/// Prints a program as NASM source.
+pub fn emit(program: &Program) -> String {
+    emit_with_lines(program, line)
+}
+
+/// Prints a program as NASM source, with the source positions in its code printed by `line`.
+pub fn emit_with_lines(program: &Program, line: impl FnMut(&Loc) -> String) -> String {
+    emit_with_map(program, line).0
+}
+
+/// Prints a program as NASM source like `emit_with_lines`, along with the source position of
+/// every line of it, which tells where the code NASM reports a message for came from.
+pub fn emit_with_map(program: &Program, line: impl FnMut(&Loc) -> String) -> (String, Vec<Option<Loc>>) {
+    // Flat binaries start out in 16-bit mode whatever the target, so the width is always given
+    let mut writer = Writer::new(line);
+    writer.push(&format!("bits {}\n", program.target.bits()));
+    let mut in_text = true;
+    for item in &program.items {
+        match item {
+            Item::Function(function) => emit_function(function, &mut writer),
+            Item::Line(loc) => writer.line(loc),
+            item => writer.push(&emit_item(item, &mut in_text)),
+        }
+    }
+    (writer.text, writer.positions)
+}
+
+// NASM source as it is printed, with the source position each of its lines belongs to
+struct Writer<F: FnMut(&Loc) -> String> {
+    text: String,
+    positions: Vec<Option<Loc>>,
+    current: Option<Loc>,
+    print_line: F,
+}
+
+impl<F: FnMut(&Loc) -> String> Writer<F> {
+    fn new(print_line: F) -> Writer<F> {
+        Writer { text: String::new(), positions: Vec::new(), current: None, print_line }
+    }
+
+    fn push(&mut self, text: &str) {
+        self.text.push_str(text);
+        let lines = text.matches('\n').count();
+        self.positions.extend(std::iter::repeat_n(self.current.clone(), lines));
+    }
+
+    // The lines that follow come from `loc`
+    fn line(&mut self, loc: &Loc) {
+        self.current = Some(loc.clone());
+        let text = (self.print_line)(loc);
+        self.push(&text);
+    }
+}
+
+/// An item as NASM source, with its source positions as `%line`. Labels in data sections are
+/// indented like the data they name, `in_text` follows the sections to tell them apart.
+pub fn emit_item(item: &Item, in_text: &mut bool) -> String {
+    match item {
+        Item::Define(name, value) => format!("%define {} {}\n", name, operand(value)),
+        Item::Extern(name) => format!("extern {}\n", name),
+        Item::Global(name) => format!("global {}\n", name),
+        Item::Section(name) => {
+            *in_text = name == "text";
+            format!("section .{}\n", name)
+        }
+        Item::Label(label) if *in_text => format!("{}:\n", label),
+        Item::Label(label) => format!("  {}:\n", label),
+        Item::Instruction(instr) => format!("  {}\n", instruction(instr)),
+        Item::Data(label, data) => emit_data(label.as_deref(), data),
+        Item::Equ(name, value) => format!("  {} equ {}\n", name, equ(value)),
+        Item::Align { alignment, bss: true } => format!("  alignb {}\n", alignment),
+        Item::Align { alignment, bss: false } => format!("  align {}\n", alignment),
+        Item::Pad(size) => format!("  times {} - ($ - $$) db 0\n", size),
+        Item::Org(origin) => format!("org {}\n", origin),
+        Item::Function(function) => {
+            let mut writer = Writer::new(line);
+            emit_function(function, &mut writer);
+            writer.text
+        }
+        Item::Line(loc) => line(loc),
+    }
+}
+
+fn emit_function<F: FnMut(&Loc) -> String>(function: &Function, writer: &mut Writer<F>) {
+    writer.push(&format!("{}:\n", function.name));
+    for code in function.code() {
+        match code {
+            Code::Label(label) => writer.push(&format!("{}:\n", label)),
+            Code::Instruction(instr) => writer.push(&format!("  {}\n", instruction(&instr))),
+            Code::Line(loc) => writer.line(&loc),
+        }
+    }
+}
+
+/// Every line up to the next `%line` counts as this line of the source, which is what NASM
+/// puts in its debug information and messages.
+pub fn line(loc: &Loc) -> String {
+    format!("%line {}+0 {}\n", loc.line, loc.file.display())
+}
+
+fn emit_data(label: Option<&str>, data: &Data) -> String {
+    let directive = match data {
+        Data::Values { data_type, values, repeat } => {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            match repeat {
+                Some(count) => format!("times {} {} {}", count, data_type.define_directive(), values.join(", ")),
+                None => format!("{} {}", data_type.define_directive(), values.join(", ")),
+            }
+        }
+        // An empty string only defines its label
+        Data::Bytes(bytes) if bytes.is_empty() => {
+            return label.map(|label| format!("  {}:\n", label)).unwrap_or_default();
+        }
+        Data::Bytes(bytes) => {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+            format!("db {}", bytes.join(", "))
+        }
+        Data::Reserve { data_type, count } => format!("{} {}", data_type.reserve_directive(), count),
+        Data::Zero(count) => format!("times {} db 0", count),
+    };
+    match label {
+        Some(label) => format!("  {} {}\n", label, directive),
+        None => format!("  {}\n", directive),
+    }
+}
+
+fn equ(value: &EquValue) -> String {
+    match value {
+        EquValue::Since(label) => format!("$ - {}", label),
+        EquValue::Quotient(symbol, divisor) => format!("{} / {}", symbol, divisor),
+        EquValue::Symbol(symbol) => symbol.clone(),
+        EquValue::Number(number) => number.to_string(),
+    }
+}
+
+pub fn instruction(instruction: &Instruction) -> String {
+    match instruction {
+        Instruction::Binary(opcode, dest, src) => format!("{} {}, {}", opcode.mnemonic(), operand(dest), operand(src)),
+        Instruction::Push(value) => format!("push {}", operand(value)),
+        Instruction::Pop(value) => format!("pop {}", operand(value)),
+        Instruction::Call(target) => format!("call {}", target),
+        Instruction::Jump(None, target) => format!("jmp {}", target),
+        Instruction::Jump(Some(condition), target) => format!("j{} {}", condition.suffix(), target),
+        Instruction::Ret => "ret".to_string(),
+        Instruction::Syscall => "syscall".to_string(),
+        Instruction::Int(vector) => format!("int {:#x}", vector),
+    }
+}
+
+fn operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(register) => register.name.to_string(),
+        Operand::Immediate(value) => value.to_string(),
+        Operand::Memory(memory) => match memory.size {
+            Some(size) => format!("{} [{}]", size.size_keyword(), address(memory)),
+            None => format!("[{}]", address(memory)),
+        },
+    }
+}
+
+fn address(memory: &Memory) -> String {
+    let mut result = String::new();
+    for register in memory.base.iter().chain(memory.index.iter()) {
+        if !result.is_empty() {
+            result.push_str(" + ");
+        }
+        result.push_str(register.name);
+    }
+    for term in &memory.displacement {
+        match (result.is_empty(), term.negative) {
+            (true, false) => {}
+            (true, true) => result.push('-'),
+            (false, false) => result.push_str(" + "),
+            (false, true) => result.push_str(" - "),
+        }
+        result.push_str(&term.value.to_string());
+    }
+    result
+}
@@ -0,0 +1,139 @@
+use crate::registers::Register;
+
+/// An instruction operand, as far as the x86-64 encoding rules are concerned.
+pub enum Operand {
+    Register(&'static Register),
+    // Size in bytes when known, and whether the address uses a register that needs a REX prefix
+    Memory { size: Option<usize>, rex: bool },
+    Immediate(Option<i64>),
+    // The address of a label used as an immediate, with a description for messages
+    Address(String),
+    // Only known to NASM, e.g. a `define`
+    Unknown,
+}
+
+impl Operand {
+    fn size(&self) -> Option<usize> {
+        match self {
+            Operand::Register(register) => Some(register.size),
+            Operand::Memory { size, .. } => *size,
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Operand::Register(register) => format!("the {}-bit register '{}'", register.bits(), register.name),
+            Operand::Memory { size: Some(1), .. } => "an 8-bit memory operand".to_string(),
+            Operand::Memory { size: Some(size), .. } => format!("a {}-bit memory operand", size * 8),
+            Operand::Memory { size: None, .. } => "a memory operand".to_string(),
+            Operand::Immediate(_) => "a number".to_string(),
+            Operand::Address(what) => what.clone(),
+            Operand::Unknown => "an operand".to_string(),
+        }
+    }
+
+    fn needs_rex(&self) -> bool {
+        match self {
+            Operand::Register(register) => register.rex,
+            Operand::Memory { rex, .. } => *rex,
+            _ => false,
+        }
+    }
+
+    fn high_byte(&self) -> Option<&'static str> {
+        match self {
+            Operand::Register(register) if register.high_byte => Some(register.name),
+            _ => None,
+        }
+    }
+}
+
+// Whether a value can be encoded in an immediate of `size` bytes, either signed or unsigned
+fn fits(value: i64, size: usize) -> bool {
+    if size >= 8 {
+        return true;
+    }
+    let bits = size as u32 * 8;
+    value >= -(1 << (bits - 1)) && value < (1 << bits)
+}
+
+fn fits_imm32(value: i64) -> bool {
+    i32::try_from(value).is_ok()
+}
+
+/// Checks a two operand instruction (`mov`, `add`, `xor`, `cmp`) against the encodings x86-64
+/// has for it, in code whose words are `word_size` bytes. Operands that are only known to NASM
+/// are not checked.
+pub fn check_binary(mnemonic: &str, dest: &Operand, src: &Operand, word_size: usize) -> Result<(), String> {
+    if let Operand::Immediate(_) | Operand::Address(_) = dest {
+        return Err(format!("the first operand of '{}' must be a register or memory operand, not {}", mnemonic, dest.describe()));
+    }
+    if let (Operand::Memory { .. }, Operand::Memory { .. }) = (dest, src) {
+        return Err(format!("'{}' cannot have two memory operands, load one of them into a register first", mnemonic));
+    }
+
+    for (high, other) in [(dest, src), (src, dest)] {
+        if let (Some(name), true) = (high.high_byte(), other.needs_rex()) {
+            return Err(format!("'{}' cannot be used together with {}, which needs a REX prefix", name, other.describe()));
+        }
+    }
+
+    if let (Some(dest_size), Some(src_size)) = (dest.size(), src.size()) {
+        if dest_size != src_size {
+            return Err(format!("operand size mismatch in '{}': {} and {}", mnemonic, dest.describe(), src.describe()));
+        }
+    }
+
+    match (dest, src) {
+        (Operand::Memory { size: None, .. }, Operand::Immediate(_) | Operand::Address(_)) => {
+            Err(format!("the size of the memory operand of '{}' is unknown, use a typed data label or a struct field", mnemonic))
+        }
+        (_, Operand::Immediate(Some(value))) => match dest.size() {
+            // Only `mov` to a register has a 64-bit immediate, everything else sign-extends 32 bits
+            Some(8) if mnemonic != "mov" || !matches!(dest, Operand::Register(_)) => {
+                if fits_imm32(*value) {
+                    Ok(())
+                } else {
+                    Err(format!("'{}' only takes a sign-extended 32-bit immediate, load {} into a register first", mnemonic, value))
+                }
+            }
+            Some(size) if !fits(*value, size) => Err(format!("value {} does not fit in {}", value, dest.describe())),
+            _ => Ok(()),
+        },
+        (_, Operand::Address(what)) if mnemonic != "mov" => {
+            Err(format!("'{}' cannot take the address of {} as an immediate, load it into a register with mov first", mnemonic, what))
+        }
+        // Addresses take 32 bits, only those of 16-bit code fit in 16
+        (_, Operand::Address(what)) => match dest.size() {
+            Some(size) if size < word_size.min(4) => Err(format!("the address of {} does not fit in {}", what, dest.describe())),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// `push` and `pop` only move values the size of a word (`word_size` bytes) or 16-bit ones,
+/// 16-bit code can also move 32-bit ones.
+pub fn check_stack(mnemonic: &str, operand: &Operand, word_size: usize) -> Result<(), String> {
+    let other_size = if word_size == 2 { 4 } else { 2 };
+    let widths = format!("{}-bit or {}-bit", word_size * 8, other_size * 8);
+    match operand {
+        Operand::Register(register) if register.size != word_size && register.size != other_size => {
+            Err(format!("'{}' needs a {} operand, not {}", mnemonic, widths, operand.describe()))
+        }
+        Operand::Memory { size: Some(size), .. } if *size != word_size && *size != other_size => {
+            Err(format!("'{}' needs a {} operand, not {}", mnemonic, widths, operand.describe()))
+        }
+        Operand::Immediate(_) | Operand::Address(_) if mnemonic == "pop" => {
+            Err(format!("'pop' needs a register or memory operand, not {}", operand.describe()))
+        }
+        Operand::Immediate(Some(value)) if word_size == 2 && !fits(*value, 2) => {
+            Err(format!("'push' only takes a 16-bit immediate in 16-bit code, not {}", value))
+        }
+        Operand::Immediate(Some(value)) if !fits_imm32(*value) => {
+            Err(format!("'push' only takes a sign-extended 32-bit immediate, load {} into a register first", value))
+        }
+        _ => Ok(()),
+    }
+}
@@ -1,13 +1,21 @@
-use crate::tokenizer::{self, Token, TokenType};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic::Diagnostic;
+use crate::modules;
+use crate::stdlib;
+use crate::target::Target;
+use crate::tokenizer::{self, Loc, Token, TokenType};
 
 #[derive(Debug)]
 pub struct NodeExprIdent {
     pub name: String,
+    pub loc: Loc,
 }
 
 #[derive(Debug)]
 pub struct NodeExprNumber {
-    pub value: i32,
+    pub value: i64,
 }
 
 #[derive(Debug)]
@@ -39,11 +47,31 @@ pub struct NodeExprNotEqual {
     pub right: Box<NodeExpr>,
 }
 
+#[derive(Debug)]
+pub struct NodeExprBitOr {
+    pub left: Box<NodeExpr>,
+    pub right: Box<NodeExpr>,
+}
+
 #[derive(Debug)]
 pub struct NodeExprLen {
     pub ident: Box<NodeExpr>,
 }
 
+#[derive(Debug)]
+pub struct NodeExprSizeof {
+    pub ident: Box<NodeExpr>,
+}
+
+/// `[base + offset]`, optionally typed by a struct field as in `[rdi].Point.x`.
+#[derive(Debug)]
+pub struct NodeExprMemory {
+    pub base: NodeExprIdent,
+    pub offset: Option<Box<NodeExpr>>,
+    pub negative: bool,
+    pub field: Option<NodeExprIdent>,
+}
+
 #[derive(Debug)]
 pub enum NodeExpr {
     Ident(NodeExprIdent),
@@ -53,7 +81,10 @@ pub enum NodeExpr {
     Lesser(NodeExprLesser),
     Greater(NodeExprGreater),
     NotEqual(NodeExprNotEqual),
+    BitOr(NodeExprBitOr),
     Len(NodeExprLen),
+    Sizeof(NodeExprSizeof),
+    Memory(NodeExprMemory),
 }
 
 impl From<NodeExprIdent> for NodeExpr {
@@ -76,14 +107,16 @@ impl From<NodeExprString> for NodeExpr {
 
 #[derive(Debug)]
 pub struct NodeStmtMov {
-    pub ident: NodeExprIdent,
+    pub dest: NodeExpr,
     pub expr: NodeExpr,
+    pub loc: Loc,
 }
 
 #[derive(Debug)]
 pub struct NodeStmtAdd {
-    pub ident: NodeExprIdent,
+    pub dest: NodeExpr,
     pub expr: NodeExpr,
+    pub loc: Loc,
 }
 
 #[derive(Debug)]
@@ -91,15 +124,75 @@ pub struct NodeStmtGlobal {
     pub ident: NodeExprIdent,
 }
 
+/// Type annotation of a parameter: `ptr` for addresses, `u8` ... `u64` for plain numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamType {
+    Ptr,
+    Int(DataType),
+}
+
+impl ParamType {
+    pub fn from_name(name: &str) -> Option<ParamType> {
+        match name {
+            "ptr" => Some(ParamType::Ptr),
+            _ => DataType::from_name(name).map(ParamType::Int),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParamType::Ptr => "ptr",
+            ParamType::Int(DataType::U8) => "u8",
+            ParamType::Int(DataType::U16) => "u16",
+            ParamType::Int(DataType::U32) => "u32",
+            ParamType::Int(DataType::U64) => "u64",
+        }
+    }
+}
+
+/// `name` or `name: type` in a parameter list.
+#[derive(Debug)]
+pub struct NodeParam {
+    pub ident: NodeExprIdent,
+    pub param_type: Option<ParamType>,
+}
+
 #[derive(Debug)]
 pub struct NodeFunc {
     pub name: NodeExprIdent,     
-    pub arguments: Vec<NodeExprIdent>, 
+    pub arguments: Vec<NodeParam>, 
+    // Registers listed after `uses`, saved instead of the ones found in the body
+    pub uses: Option<Vec<NodeExprIdent>>,
     pub body: Vec<NodeStmt>,     
+    // `naked fn` has no prologue or epilogue, `noreturn fn` no epilogue
+    pub naked: bool,
+    pub noreturn: bool,
+    // Module the function was declared in and whether it is exported with `pub`
+    pub module: Option<String>,
+    pub public: bool,
+    // The closing brace, where the epilogue is placed in debug line information
+    pub end: Loc,
+}
+
+/// `extern fn name(a, b)` - prototype of a function defined outside of the program.
+#[derive(Debug)]
+pub struct NodeExtern {
+    pub name: NodeExprIdent,
+    pub arguments: Vec<NodeParam>,
+    pub noreturn: bool,
 }
 
 #[derive(Debug)]
-pub struct NodeStmtSyscall {}
+pub struct NodeStmtSyscall {
+    pub loc: Loc,
+}
+
+/// `int 0x80` - software interrupt, how 32-bit Linux is asked for a system call.
+#[derive(Debug)]
+pub struct NodeStmtInt {
+    pub vector: NodeExpr,
+    pub loc: Loc,
+}
 
 #[derive(Debug)]
 pub struct NodeStmtCall {
@@ -122,23 +215,27 @@ pub struct NodeStmtAssign {
 pub struct NodeStmtIf {
     pub condition: NodeExpr,
     pub body: Vec<NodeStmt>,
+    pub loc: Loc,
 }
 
 #[derive(Debug)]
 pub struct NodeStmtWhile {
     pub condition: NodeExpr,
     pub body: Vec<NodeStmt>,
+    pub loc: Loc,
 }
 
 #[derive(Debug)]
 pub struct NodeStmtXor {
-    pub ident: NodeExprIdent,
+    pub dest: NodeExpr,
     pub expr: NodeExpr,
+    pub loc: Loc,
 }
 
 #[derive(Debug)]
 pub struct NodeStmtPush {
     pub expr: NodeExpr,
+    pub loc: Loc,
 }
 
 #[derive(Debug)]
@@ -152,6 +249,170 @@ pub struct NodeStmtDefine {
     pub expr: NodeExpr,
 }
 
+/// Element type of a data declaration such as `counter: u64 = 0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataType {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl DataType {
+    pub fn from_name(name: &str) -> Option<DataType> {
+        match name {
+            "u8" => Some(DataType::U8),
+            "u16" => Some(DataType::U16),
+            "u32" => Some(DataType::U32),
+            "u64" => Some(DataType::U64),
+            _ => None,
+        }
+    }
+
+    /// Maps the NASM reservation directives (`resb` ... `resq`) onto their element type.
+    pub fn from_reserve(name: &str) -> Option<DataType> {
+        match name {
+            "resb" => Some(DataType::U8),
+            "resw" => Some(DataType::U16),
+            "resd" => Some(DataType::U32),
+            "resq" => Some(DataType::U64),
+            _ => None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            DataType::U8 => 1,
+            DataType::U16 => 2,
+            DataType::U32 => 4,
+            DataType::U64 => 8,
+        }
+    }
+
+    pub fn define_directive(&self) -> &'static str {
+        match self {
+            DataType::U8 => "db",
+            DataType::U16 => "dw",
+            DataType::U32 => "dd",
+            DataType::U64 => "dq",
+        }
+    }
+
+    pub fn reserve_directive(&self) -> &'static str {
+        match self {
+            DataType::U8 => "resb",
+            DataType::U16 => "resw",
+            DataType::U32 => "resd",
+            DataType::U64 => "resq",
+        }
+    }
+
+    /// Size specifier used for memory operands of this type.
+    pub fn size_keyword(&self) -> &'static str {
+        match self {
+            DataType::U8 => "byte",
+            DataType::U16 => "word",
+            DataType::U32 => "dword",
+            DataType::U64 => "qword",
+        }
+    }
+}
+
+/// `name: u32 = [1, 2, 3] * 4` - `values` empty means an uninitialised element.
+#[derive(Debug)]
+pub struct NodeStmtData {
+    pub ident: NodeExprIdent,
+    pub data_type: DataType,
+    pub values: Vec<NodeExpr>,
+    pub repeat: Option<NodeExpr>,
+}
+
+/// `name: resb 4096`
+#[derive(Debug)]
+pub struct NodeStmtReserve {
+    pub ident: NodeExprIdent,
+    pub data_type: DataType,
+    pub count: NodeExpr,
+}
+
+#[derive(Debug)]
+pub struct NodeStmtAlign {
+    pub expr: NodeExpr,
+}
+
+/// `bits 16` - the width of the code, which picks the target for the whole program.
+#[derive(Debug)]
+pub struct NodeStmtBits {
+    pub bits: u32,
+    pub loc: Loc,
+}
+
+/// `org 0x7c00` - the address a flat binary is loaded at.
+#[derive(Debug)]
+pub struct NodeStmtOrg {
+    pub expr: NodeExpr,
+    pub loc: Loc,
+}
+
+/// `pad 510` - zeroes up to the given size of the section.
+#[derive(Debug)]
+pub struct NodeStmtPad {
+    pub expr: NodeExpr,
+    pub loc: Loc,
+}
+
+/// A field of a struct declaration; `type_name` is a data type or another struct,
+/// `count` is set for arrays such as `zero: u8[8]`.
+#[derive(Debug)]
+pub struct NodeStructField {
+    pub ident: NodeExprIdent,
+    pub type_name: NodeExprIdent,
+    pub count: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct NodeStruct {
+    pub name: NodeExprIdent,
+    pub packed: bool,
+    pub fields: Vec<NodeStructField>,
+}
+
+#[derive(Debug)]
+pub struct NodeStmtFieldInit {
+    pub ident: NodeExprIdent,
+    pub expr: NodeExpr,
+}
+
+/// A member of an `enum` or `const` block, `value` is `None` when it is auto-numbered.
+#[derive(Debug)]
+pub struct NodeConstant {
+    pub ident: NodeExprIdent,
+    pub value: Option<NodeExpr>,
+}
+
+/// `enum Name { A, B = 5, C }` defines `Name.A`, `Name.B` and `Name.C`. Flag enums number
+/// their members with successive powers of two.
+#[derive(Debug)]
+pub struct NodeEnum {
+    pub name: NodeExprIdent,
+    pub flags: bool,
+    pub members: Vec<NodeConstant>,
+}
+
+/// `const { A = 1, B = A | 2 }` defines unqualified constants.
+#[derive(Debug)]
+pub struct NodeConstBlock {
+    pub members: Vec<NodeConstant>,
+}
+
+/// `origin: Point = { x: 1, y: 2 }` - fields that are not listed are zeroed.
+#[derive(Debug)]
+pub struct NodeStmtInstance {
+    pub ident: NodeExprIdent,
+    pub struct_name: NodeExprIdent,
+    pub fields: Vec<NodeStmtFieldInit>,
+}
+
 #[derive(Debug)]
 pub enum NodeStmt {
     Mov(NodeStmtMov),
@@ -159,6 +420,7 @@ pub enum NodeStmt {
     Global(NodeStmtGlobal),
     Func(NodeFunc),
     Syscall(NodeStmtSyscall),
+    Int(NodeStmtInt),
     Call(NodeStmtCall),
     Section(NodeStmtSection),
     Assign(NodeStmtAssign),
@@ -168,6 +430,44 @@ pub enum NodeStmt {
     Push(NodeStmtPush),
     Pop(NodeStmtPop),
     Define(NodeStmtDefine),
+    Data(NodeStmtData),
+    Reserve(NodeStmtReserve),
+    Align(NodeStmtAlign),
+    Bits(NodeStmtBits),
+    Org(NodeStmtOrg),
+    Pad(NodeStmtPad),
+    // `boot_signature`, pads the section to 510 bytes and ends it with 0x55 0xAA
+    BootSignature,
+    Struct(NodeStruct),
+    Instance(NodeStmtInstance),
+    Enum(NodeEnum),
+    Const(NodeConstBlock),
+    Extern(NodeExtern),
+}
+
+impl NodeStmt {
+    /// Where a statement that turns into instructions starts, for diagnostics about the
+    /// statement as a whole and for debug line information.
+    pub fn loc(&self) -> Option<&Loc> {
+        match self {
+            NodeStmt::Mov(mov) => Some(&mov.loc),
+            NodeStmt::Add(add) => Some(&add.loc),
+            NodeStmt::Xor(xor) => Some(&xor.loc),
+            NodeStmt::Push(push) => Some(&push.loc),
+            NodeStmt::Syscall(syscall) => Some(&syscall.loc),
+            NodeStmt::Int(int) => Some(&int.loc),
+            NodeStmt::Pop(pop) => Some(&pop.ident.loc),
+            NodeStmt::Call(call) => Some(&call.name.loc),
+            NodeStmt::If(if_stmt) => Some(&if_stmt.loc),
+            NodeStmt::While(while_stmt) => Some(&while_stmt.loc),
+            NodeStmt::Assign(assign) => Some(&assign.ident.loc),
+            NodeStmt::Data(data) => Some(&data.ident.loc),
+            NodeStmt::Reserve(reserve) => Some(&reserve.ident.loc),
+            NodeStmt::Instance(instance) => Some(&instance.ident.loc),
+            NodeStmt::Pad(pad) => Some(&pad.loc),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -175,11 +475,28 @@ pub struct Node {
     pub functions: Vec<NodeFunc>,
     pub stmt: Vec<NodeStmt>,
     pub defines: Vec<NodeStmtDefine>,
+    pub structs: Vec<NodeStruct>,
+    pub enums: Vec<NodeEnum>,
+    pub consts: Vec<NodeConstBlock>,
+    pub externs: Vec<NodeExtern>,
+    pub bits: Option<NodeStmtBits>,
+    // Problems found while mangling module members, reported along with the resolver's
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
     index: usize,
+    // Searched in order for includes that are not found next to the including file
+    include_dirs: Vec<PathBuf>,
+    // Replaces the bundled libraries with `<dir>/<name>.asmpp` when set
+    stdlib_dir: Option<PathBuf>,
+    // Picks the libraries written for the target
+    target: Target,
+    // Canonical paths of every file read so far, each file is only included once
+    included: HashSet<PathBuf>,
+    // Files currently being parsed, outermost first, as (canonical path, path as written)
+    include_stack: Vec<(PathBuf, PathBuf)>,
 }
 
 impl Parser {
@@ -187,15 +504,42 @@ impl Parser {
         Parser {
             tokens,
             index: 0,
+            include_dirs: Vec::new(),
+            stdlib_dir: None,
+            target: Target::default(),
+            included: HashSet::new(),
+            include_stack: Vec::new(),
         }
     }
 
+    pub fn add_include_dir(&mut self, dir: PathBuf) {
+        self.include_dirs.push(dir);
+    }
+
+    pub fn set_stdlib_dir(&mut self, dir: PathBuf) {
+        self.stdlib_dir = Some(dir);
+    }
+
+    pub fn set_target(&mut self, target: Target) {
+        self.target = target;
+    }
+
+    // `--stdlib <dir>`, or the directory in it with the libraries of the target
+    fn library_dir(&self) -> Option<PathBuf> {
+        let dir = self.stdlib_dir.as_ref()?;
+        Some(match self.target.library_dir() {
+            Some(target_dir) => dir.join(target_dir),
+            None => dir.clone(),
+        })
+    }
+
     fn operator_precedence(&self, token_type: &tokenizer::TokenType) -> i32 {
         match token_type {
             tokenizer::TokenType::Equal => 1,
             tokenizer::TokenType::Lesser => 1,
             tokenizer::TokenType::Greater => 1,
             tokenizer::TokenType::NotEqual => 1,
+            tokenizer::TokenType::Pipe => 2,
             _ => 0,
         }
     }
@@ -209,7 +553,7 @@ impl Parser {
                 break;
             }
 
-            let op_type = op_token.token_type.clone();
+            let op_type = op_token.token_type;
             self.consume(); 
             let mut right_expr = self.parse_primary_expression();
             while let Some(next_op_token) = self.peek(0) {
@@ -226,6 +570,7 @@ impl Parser {
                 tokenizer::TokenType::Lesser => NodeExpr::Lesser(NodeExprLesser { left: Box::new(left_expr), right: Box::new(right_expr) }),
                 tokenizer::TokenType::Greater => NodeExpr::Greater(NodeExprGreater { left: Box::new(left_expr), right: Box::new(right_expr) }),
                 tokenizer::TokenType::NotEqual => NodeExpr::NotEqual(NodeExprNotEqual { left: Box::new(left_expr), right: Box::new(right_expr) }),
+                tokenizer::TokenType::Pipe => NodeExpr::BitOr(NodeExprBitOr { left: Box::new(left_expr), right: Box::new(right_expr) }),
                 _ => panic!("Unexpected operator {:?}", op_type),
             };
         }
@@ -237,8 +582,8 @@ impl Parser {
     fn parse_primary_expression(&mut self) -> NodeExpr {
         let token = self.consume().expect("Expected a primary expression token");
         match token.token_type {
-            tokenizer::TokenType::Identifier => NodeExpr::Ident(NodeExprIdent { name: token.value.clone().unwrap() }),
-            tokenizer::TokenType::Number => NodeExpr::Number(NodeExprNumber { value: token.value.clone().unwrap().parse().unwrap() }),
+            tokenizer::TokenType::Identifier => NodeExpr::Ident(NodeExprIdent { name: token.value.clone().unwrap(), loc: token.loc.clone() }),
+            tokenizer::TokenType::Number => NodeExpr::Number(NodeExprNumber { value: parse_number(token.value.as_ref().unwrap()) }),
             tokenizer::TokenType::StringLit => NodeExpr::String(NodeExprString { value: token.value.clone().unwrap() }),
             tokenizer::TokenType::Len => {
                 let _ = self.expect_token(tokenizer::TokenType::Lparen, "Expected '(' after 'len'");
@@ -249,10 +594,53 @@ impl Parser {
 
                 NodeExpr::Len(NodeExprLen { ident: Box::new(expr) })
             }
+            tokenizer::TokenType::Sizeof => {
+                let _ = self.expect_token(tokenizer::TokenType::Lparen, "Expected '(' after 'sizeof'");
+
+                let expr = self.parse_expression();
+
+                let _ = self.expect_token(tokenizer::TokenType::Rparen, "Expected ')' after sizeof expression");
+
+                NodeExpr::Sizeof(NodeExprSizeof { ident: Box::new(expr) })
+            }
+            tokenizer::TokenType::Minus => {
+                let token = self.expect_token(tokenizer::TokenType::Number, "Expected a number after '-'").unwrap();
+                NodeExpr::Number(NodeExprNumber { value: -parse_number(token.value.as_ref().unwrap()) })
+            }
+            tokenizer::TokenType::BracketL => self.parse_memory(),
             _ => panic!("Unexpected token type in primary expression: {:?}", token.token_type),
         }
     }
 
+    fn parse_memory(&mut self) -> NodeExpr {
+        let base = match self.consume() {
+            Some(Token { token_type: TokenType::Identifier, value: Some(name), loc }) => NodeExprIdent { name: name.clone(), loc: loc.clone() },
+            _ => panic!("Expected a register or label as memory operand base."),
+        };
+
+        let mut offset = None;
+        let mut negative = false;
+        if let Some(token) = self.peek(0) {
+            if token.token_type == TokenType::Plus || token.token_type == TokenType::Minus {
+                negative = token.token_type == TokenType::Minus;
+                self.consume();
+                offset = Some(Box::new(self.parse_primary_expression()));
+            }
+        }
+        let _ = self.expect_token(tokenizer::TokenType::BracketR, "Expected ']' to close the memory operand");
+
+        let mut field = None;
+        if let Some(Token { token_type: TokenType::Dot, .. }) = self.peek(0) {
+            self.consume();
+            field = match self.consume() {
+                Some(Token { token_type: TokenType::Identifier, value: Some(name), loc }) => Some(NodeExprIdent { name: name.clone(), loc: loc.clone() }),
+                _ => panic!("Expected 'Struct.field' after '.' in a memory operand."),
+            };
+        }
+
+        NodeExpr::Memory(NodeExprMemory { base, offset, negative, field })
+    }
+
     fn expect_token(&mut self, expected_type: tokenizer::TokenType, error_msg: &str) -> Option<&Token> {
         let token = self.consume().expect(error_msg);
         if token.token_type != expected_type {
@@ -266,7 +654,7 @@ impl Parser {
         if let Some(op_token) = self.peek(0) {
             let precedence = self.operator_precedence(&op_token.token_type);
             if precedence > 0 {
-                self.parse_binary_expression(primary_expr, precedence)
+                self.parse_binary_expression(primary_expr, 1)
             } else {
                 primary_expr
             }
@@ -277,28 +665,28 @@ impl Parser {
 
 
     fn parse_mov(&mut self) -> NodeStmt {
-        self.consume();
-        let ident_expr = self.parse_expression();
+        let loc = self.consume().unwrap().loc.clone();
+        let dest = self.parse_expression();
         let _ = self.consume().unwrap();
         
-        if let NodeExpr::Ident(ident) = ident_expr {
+        if let NodeExpr::Ident(_) | NodeExpr::Memory(_) = dest {
             let expr = self.parse_expression();
-            NodeStmt::Mov(NodeStmtMov { ident: ident, expr })
+            NodeStmt::Mov(NodeStmtMov { dest, expr, loc })
         } else {
-            panic!("Expected an identifier in the 'mov' statement.");
+            panic!("Expected an identifier or memory operand in the 'mov' statement.");
         }
     }
 
     fn parse_add(&mut self) -> NodeStmt {
-        self.consume();
-        let ident_expr = self.parse_expression();
+        let loc = self.consume().unwrap().loc.clone();
+        let dest = self.parse_expression();
         let _ = self.consume().unwrap();
         
-        if let NodeExpr::Ident(ident) = ident_expr {
+        if let NodeExpr::Ident(_) | NodeExpr::Memory(_) = dest {
             let expr = self.parse_expression();
-            NodeStmt::Add(NodeStmtAdd { ident: ident, expr })
+            NodeStmt::Add(NodeStmtAdd { dest, expr, loc })
         } else {
-            panic!("Expected an identifier in the 'add' statement.");
+            panic!("Expected an identifier or memory operand in the 'add' statement.");
         }
     }
 
@@ -307,7 +695,7 @@ impl Parser {
         let ident_expr = self.parse_expression();
         
         if let NodeExpr::Ident(ident) = ident_expr {
-            NodeStmt::Global(NodeStmtGlobal { ident: ident })
+            NodeStmt::Global(NodeStmtGlobal { ident })
         } else {
             panic!("Expected an identifier in the 'global' statement.");
         }
@@ -321,6 +709,7 @@ impl Parser {
                 | tokenizer::TokenType::Add
                 | tokenizer::TokenType::Global 
                 | tokenizer::TokenType::Syscall 
+                | tokenizer::TokenType::Int 
                 | tokenizer::TokenType::Call 
                 | tokenizer::TokenType::Section 
                 | tokenizer::TokenType::If 
@@ -338,13 +727,74 @@ impl Parser {
 
     }
 
+    // Whether the tokens ahead are `naked` and `noreturn` modifiers followed by `fn`
+    fn at_function_modifier(&self) -> bool {
+        let mut offset = 0;
+        while let Some(Token { token_type: TokenType::Identifier, value: Some(value), .. }) = self.peek(offset) {
+            if value != "naked" && value != "noreturn" {
+                return false;
+            }
+            offset += 1;
+        }
+        offset > 0 && matches!(self.peek(offset), Some(Token { token_type: TokenType::Function, .. }))
+    }
+
+    // Consumes `naked` and `noreturn` and returns which of them were given
+    fn parse_function_modifiers(&mut self) -> (bool, bool) {
+        let (mut naked, mut noreturn) = (false, false);
+        while let Some(Token { token_type: TokenType::Identifier, value: Some(value), .. }) = self.peek(0) {
+            match value.as_str() {
+                "naked" => naked = true,
+                "noreturn" => noreturn = true,
+                _ => break,
+            }
+            self.consume();
+        }
+        (naked, noreturn)
+    }
+
     fn parse_function(&mut self) -> NodeStmt {
+        let (naked, noreturn) = self.parse_function_modifiers();
         self.consume(); // Consume the "func" keyword
         let name = match self.parse_expression() {
             NodeExpr::Ident(ident) => ident,
             _ => panic!("Expected an identifier for the function name."),
         };
 
+        let arguments = self.parse_params();
+        let uses = self.parse_uses();
+
+        let opening_curly = self.consume().unwrap(); 
+
+        if opening_curly.token_type != tokenizer::TokenType::CurlyL {
+            panic!("Expected a curlyL token to open the function body.");
+        }
+
+        let mut body = Vec::new();
+        body.append(&mut self.parse_scoped_statement());
+
+        let closing_curly= self.consume().unwrap();
+        if closing_curly.token_type != tokenizer::TokenType::CurlyR {
+            panic!("Expected a curlyR token to close the function body.");
+        }
+
+            
+
+        NodeStmt::Func(NodeFunc {
+            name,
+            arguments,
+            uses,
+            naked,
+            noreturn,
+            body,
+            module: None,
+            public: false,
+            end: closing_curly.loc.clone(),
+        })
+    }
+
+    // `(a, b: ptr, c: u64)` - parameter names with optional type annotations
+    fn parse_params(&mut self) -> Vec<NodeParam> {
         let opening_bracket = self.consume().unwrap(); 
         if opening_bracket.token_type != tokenizer::TokenType::Lparen {
             panic!("Expected a lparen token to open the function arguments.");
@@ -354,11 +804,22 @@ impl Parser {
         while let Some(token) = self.peek(0) {
             match token.token_type {
                 tokenizer::TokenType::Identifier => {
-                    arguments.push(match self.parse_expression() {
+                    let ident = match self.parse_expression() {
                         NodeExpr::Ident(ident) => ident,
                         _ => panic!("Expected an identifier for an argument."),
-                    });
-                    
+                    };
+                    let mut param_type = None;
+                    if let Some(Token { token_type: TokenType::Colon, .. }) = self.peek(0) {
+                        self.consume();
+                        param_type = match self.consume() {
+                            Some(Token { token_type: TokenType::Identifier, value: Some(name), .. }) => match ParamType::from_name(name) {
+                                Some(param_type) => Some(param_type),
+                                None => panic!("{}: Unknown parameter type '{}', expected ptr, u8, u16, u32 or u64", ident.loc, name),
+                            },
+                            _ => panic!("{}: Expected a type after ':' for parameter '{}'", ident.loc, ident.name),
+                        };
+                    }
+                    arguments.push(NodeParam { ident, param_type });
                 }
                 tokenizer::TokenType::Comma => {
                     let _ = self.consume().unwrap();
@@ -370,33 +831,59 @@ impl Parser {
         if closing_bracket.token_type != tokenizer::TokenType::Rparen {
             panic!("Expected a rparen token to close the function arguments.");
         }
+        arguments
+    }
 
-        let opening_curly = self.consume().unwrap(); 
-
-        if opening_curly.token_type != tokenizer::TokenType::CurlyL {
-            panic!("Expected a curlyL token to open the function body.");
+    // `uses rbx, r12` between the parameters and the body of a function
+    fn parse_uses(&mut self) -> Option<Vec<NodeExprIdent>> {
+        match self.peek(0) {
+            Some(Token { token_type: TokenType::Identifier, value: Some(value), .. }) if value == "uses" => {}
+            _ => return None,
         }
-
-        let mut body = Vec::new();
-        body.append(&mut self.parse_scoped_statement());
-
-        let closing_curly= self.consume().unwrap();
-        if closing_curly.token_type != tokenizer::TokenType::CurlyR {
-            panic!("Expected a curlyR token to close the function body.");
+        let loc = self.consume().unwrap().loc.clone();
+        let mut registers = Vec::new();
+        loop {
+            match self.parse_expression() {
+                NodeExpr::Ident(ident) => registers.push(ident),
+                _ => panic!("{}: Expected a register name after 'uses'", loc),
+            }
+            match self.peek(0) {
+                Some(Token { token_type: TokenType::Comma, .. }) => {
+                    self.consume();
+                }
+                _ => break,
+            }
         }
+        Some(registers)
+    }
 
-            
-
-        NodeStmt::Func(NodeFunc {
-            name,
-            arguments,
-            body,
-        })
+    fn parse_extern(&mut self) -> NodeStmt {
+        let loc = self.consume().expect("Expected 'extern'").loc.clone();
+        let (naked, noreturn) = self.parse_function_modifiers();
+        if naked {
+            panic!("{}: An extern function cannot be naked", loc);
+        }
+        match self.consume() {
+            Some(Token { token_type: TokenType::Function, .. }) => {}
+            _ => panic!("{}: Expected 'fn' after 'extern'", loc),
+        }
+        let name = match self.parse_expression() {
+            NodeExpr::Ident(ident) => ident,
+            _ => panic!("{}: Expected a function name after 'extern fn'", loc),
+        };
+        let arguments = self.parse_params();
+        NodeStmt::Extern(NodeExtern { name, arguments, noreturn })
     }
 
     fn parse_syscall(&mut self) -> NodeStmt {
-        self.consume();
-        NodeStmt::Syscall(NodeStmtSyscall {})
+        let loc = self.consume().unwrap().loc.clone();
+        NodeStmt::Syscall(NodeStmtSyscall { loc })
+    }
+
+    fn parse_int(&mut self) -> NodeStmt {
+        let loc = self.consume().unwrap().loc.clone();
+        let vector = self.parse_expression();
+        NodeStmt::Int(NodeStmtInt { vector, loc })
     }
 
 
@@ -412,7 +899,9 @@ impl Parser {
         while let Some(token) = self.peek(0) {
             match token.token_type {
                 tokenizer::TokenType::Number
-                | tokenizer::TokenType::Identifier => {
+                | tokenizer::TokenType::Identifier
+                | tokenizer::TokenType::Minus
+                | tokenizer::TokenType::BracketL => {
                     arguments.push(self.parse_expression());
                     if let Some(token) = self.peek(0) {
                         match token.token_type {
@@ -423,8 +912,22 @@ impl Parser {
                         }
                     }
                 }
-                tokenizer::TokenType::Len => {
-                    arguments.push(self.parse_expression());
+                tokenizer::TokenType::Len
+                | tokenizer::TokenType::Sizeof
+                | tokenizer::TokenType::StringLit => {
+                    let is_bare_len = token.token_type == TokenType::Len
+                        && !matches!(self.peek(1), Some(Token { token_type: TokenType::Lparen, .. }));
+                    if is_bare_len {
+                        // A bare `len` is the length of the closest string literal before it
+                        self.consume();
+                        let literal = arguments.iter().rev().find_map(|arg| match arg {
+                            NodeExpr::String(string) => Some(string.value.clone()),
+                            _ => None,
+                        }).unwrap_or_else(|| panic!("'len' without an argument needs a string literal before it in the call to '{}'.", name.name));
+                        arguments.push(NodeExpr::Len(NodeExprLen { ident: Box::new(NodeExpr::String(NodeExprString { value: literal })) }));
+                    } else {
+                        arguments.push(self.parse_expression());
+                    }
                     if let Some(token) = self.peek(0) {
                         match token.token_type {
                             tokenizer::TokenType::Comma => {
@@ -444,6 +947,9 @@ impl Parser {
 
     fn parse_section(&mut self) -> NodeStmt {
         self.consume();
+        if let Some(Token { token_type: TokenType::Dot, .. }) = self.peek(0) {
+            self.consume();
+        }
         let name = match self.parse_expression() {
             NodeExpr::Ident(ident) => ident,
             _ => panic!("Expected an identifier for the section name."),
@@ -456,13 +962,233 @@ impl Parser {
             NodeExpr::Ident(ident) => ident,
             _ => panic!("Expected an identifier for the assignment."),
         };
-        let _ = self.consume().unwrap(); 
+        let _ = self.expect_token(tokenizer::TokenType::Colon, "Expected ':' after the data label.");
+
+        let (type_name, type_loc) = match self.peek(0) {
+            Some(Token { token_type: TokenType::Identifier, value: Some(name), loc }) => (name.clone(), loc.clone()),
+            _ => {
+                let expr = self.parse_expression();
+                return NodeStmt::Assign(NodeStmtAssign { ident, expr });
+            }
+        };
+
+        if let Some(data_type) = DataType::from_reserve(&type_name) {
+            self.consume();
+            let count = self.parse_expression();
+            return NodeStmt::Reserve(NodeStmtReserve { ident, data_type, count });
+        }
+
+        self.consume();
+        let data_type = match DataType::from_name(&type_name) {
+            Some(data_type) => data_type,
+            None => {
+                // Only data types take a plain value, a struct is initialised with `{ ... }`
+                if let (Some(Token { token_type: TokenType::Assign, .. }), Some(next)) = (self.peek(0), self.peek(1)) {
+                    if next.token_type != TokenType::CurlyL {
+                        panic!("{}: Unknown type '{}' for '{}'.", type_loc, type_name, ident.name);
+                    }
+                }
+                // Anything else names a struct, the resolver checks it exists
+                let struct_name = NodeExprIdent { name: type_name, loc: type_loc };
+                let fields = self.parse_field_inits();
+                return NodeStmt::Instance(NodeStmtInstance { ident, struct_name, fields });
+            }
+        };
+
+        let mut values = Vec::new();
+        let mut repeat = None;
+        if let Some(Token { token_type: TokenType::Assign, .. }) = self.peek(0) {
+            self.consume();
+            if let Some(Token { token_type: TokenType::BracketL, .. }) = self.peek(0) {
+                values = self.parse_data_array();
+                if let Some(Token { token_type: TokenType::Star, .. }) = self.peek(0) {
+                    self.consume();
+                    repeat = Some(self.parse_expression());
+                }
+            } else {
+                values.push(self.parse_expression());
+            }
+        }
+
+        NodeStmt::Data(NodeStmtData { ident, data_type, values, repeat })
+    }
+
+    fn parse_data_array(&mut self) -> Vec<NodeExpr> {
+        let _ = self.expect_token(tokenizer::TokenType::BracketL, "Expected '[' to open the array.");
+        let mut values = Vec::new();
+        while let Some(token) = self.peek(0) {
+            match token.token_type {
+                tokenizer::TokenType::BracketR => break,
+                tokenizer::TokenType::Comma => {
+                    self.consume();
+                }
+                _ => values.push(self.parse_expression()),
+            }
+        }
+        let _ = self.expect_token(tokenizer::TokenType::BracketR, "Expected ']' to close the array.");
+        values
+    }
+
+    fn parse_field_inits(&mut self) -> Vec<NodeStmtFieldInit> {
+        let mut fields = Vec::new();
+        if let Some(Token { token_type: TokenType::Assign, .. }) = self.peek(0) {
+            self.consume();
+            let _ = self.expect_token(tokenizer::TokenType::CurlyL, "Expected '{' to open the struct initialiser.");
+            while let Some(token) = self.peek(0) {
+                match token.token_type {
+                    tokenizer::TokenType::CurlyR => break,
+                    tokenizer::TokenType::Comma => {
+                        self.consume();
+                    }
+                    _ => {
+                        let ident = match self.parse_expression() {
+                            NodeExpr::Ident(ident) => ident,
+                            _ => panic!("Expected a field name in the struct initialiser."),
+                        };
+                        let _ = self.expect_token(tokenizer::TokenType::Colon, "Expected ':' after the field name.");
+                        let expr = self.parse_expression();
+                        fields.push(NodeStmtFieldInit { ident, expr });
+                    }
+                }
+            }
+            let _ = self.expect_token(tokenizer::TokenType::CurlyR, "Expected '}' to close the struct initialiser.");
+        }
+        fields
+    }
+
+    fn parse_struct(&mut self) -> NodeStmt {
+        let mut packed = false;
+        if let Some(Token { token_type: TokenType::Packed, .. }) = self.peek(0) {
+            self.consume();
+            packed = true;
+        }
+        let _ = self.expect_token(tokenizer::TokenType::Struct, "Expected 'struct'.");
+        let name = match self.parse_expression() {
+            NodeExpr::Ident(ident) => ident,
+            _ => panic!("Expected an identifier for the struct name."),
+        };
+        let _ = self.expect_token(tokenizer::TokenType::CurlyL, "Expected a curlyL token to open the struct body.");
+
+        let mut fields = Vec::new();
+        while let Some(token) = self.peek(0) {
+            match token.token_type {
+                tokenizer::TokenType::CurlyR => break,
+                tokenizer::TokenType::Comma => {
+                    self.consume();
+                }
+                _ => {
+                    let ident = match self.parse_expression() {
+                        NodeExpr::Ident(ident) => ident,
+                        _ => panic!("Expected a field name in struct '{}'.", name.name),
+                    };
+                    let _ = self.expect_token(tokenizer::TokenType::Colon, "Expected ':' after the field name.");
+                    let type_name = match self.parse_expression() {
+                        NodeExpr::Ident(ident) => ident,
+                        _ => panic!("Expected a type for field '{}'.", ident.name),
+                    };
+                    let mut count = None;
+                    if let Some(Token { token_type: TokenType::BracketL, .. }) = self.peek(0) {
+                        self.consume();
+                        count = match self.parse_expression() {
+                            NodeExpr::Number(number) => Some(number.value as usize),
+                            _ => panic!("Expected a number for the length of field '{}'.", ident.name),
+                        };
+                        let _ = self.expect_token(tokenizer::TokenType::BracketR, "Expected ']' after the array length.");
+                    }
+                    fields.push(NodeStructField { ident, type_name, count });
+                }
+            }
+        }
+        let _ = self.expect_token(tokenizer::TokenType::CurlyR, "Expected a curlyR token to close the struct body.");
+
+        NodeStmt::Struct(NodeStruct { name, packed, fields })
+    }
+
+    fn parse_constants(&mut self, owner: &str) -> Vec<NodeConstant> {
+        let _ = self.expect_token(tokenizer::TokenType::CurlyL, "Expected a curlyL token to open the constant block.");
+        let mut members = Vec::new();
+        while let Some(token) = self.peek(0) {
+            match token.token_type {
+                tokenizer::TokenType::CurlyR => break,
+                tokenizer::TokenType::Comma => {
+                    self.consume();
+                }
+                _ => {
+                    let ident = match self.parse_primary_expression() {
+                        NodeExpr::Ident(ident) => ident,
+                        _ => panic!("Expected a constant name in '{}'.", owner),
+                    };
+                    let mut value = None;
+                    if let Some(Token { token_type: TokenType::Assign, .. }) = self.peek(0) {
+                        self.consume();
+                        value = Some(self.parse_expression());
+                    }
+                    members.push(NodeConstant { ident, value });
+                }
+            }
+        }
+        let _ = self.expect_token(tokenizer::TokenType::CurlyR, "Expected a curlyR token to close the constant block.");
+        members
+    }
+
+    fn parse_enum(&mut self) -> NodeStmt {
+        let mut flags = false;
+        if let Some(Token { token_type: TokenType::Identifier, .. }) = self.peek(0) {
+            self.consume();
+            flags = true;
+        }
+        let _ = self.expect_token(tokenizer::TokenType::Enum, "Expected 'enum'.");
+        let name = match self.parse_primary_expression() {
+            NodeExpr::Ident(ident) => ident,
+            _ => panic!("Expected an identifier for the enum name."),
+        };
+        let members = self.parse_constants(&name.name);
+        NodeStmt::Enum(NodeEnum { name, flags, members })
+    }
+
+    fn parse_const(&mut self) -> NodeStmt {
+        self.consume();
+        let members = self.parse_constants("const");
+        for member in &members {
+            if member.value.is_none() {
+                panic!("Constant '{}' needs a value.", member.ident.name);
+            }
+        }
+        NodeStmt::Const(NodeConstBlock { members })
+    }
+
+    fn parse_align(&mut self) -> NodeStmt {
+        self.consume();
+        let expr = self.parse_expression();
+        NodeStmt::Align(NodeStmtAlign { expr })
+    }
+
+    fn parse_bits(&mut self) -> NodeStmt {
+        let loc = self.consume().unwrap().loc.clone();
+        let bits = match self.consume() {
+            Some(Token { token_type: TokenType::Number, value: Some(value), .. }) => parse_number(value),
+            _ => panic!("{}: Expected 16, 32 or 64 after 'bits'", loc),
+        };
+        // The libraries included from here on are the ones for this width
+        self.target = u32::try_from(bits).ok().and_then(Target::from_bits)
+            .unwrap_or_else(|| panic!("{}: 'bits' takes 16, 32 or 64, not {}", loc, bits));
+        NodeStmt::Bits(NodeStmtBits { bits: bits as u32, loc })
+    }
+
+    fn parse_org(&mut self) -> NodeStmt {
+        let loc = self.consume().unwrap().loc.clone();
+        let expr = self.parse_expression();
+        NodeStmt::Org(NodeStmtOrg { expr, loc })
+    }
+
+    fn parse_pad(&mut self) -> NodeStmt {
+        let loc = self.consume().unwrap().loc.clone();
         let expr = self.parse_expression();
-        NodeStmt::Assign(NodeStmtAssign { ident, expr })
+        NodeStmt::Pad(NodeStmtPad { expr, loc })
     }
 
     fn parse_if(&mut self) -> NodeStmt{
-        self.consume();
+        let loc = self.consume().unwrap().loc.clone();
 
         let open_paren = self.consume().unwrap();
         if open_paren.token_type != tokenizer::TokenType::Lparen {
@@ -494,12 +1220,13 @@ impl Parser {
         NodeStmt::If(NodeStmtIf {
             condition,
             body,
+            loc,
         })
     } 
 
 
     fn parse_while(&mut self) -> NodeStmt {
-        self.consume();
+        let loc = self.consume().unwrap().loc.clone();
 
         let open_paren = self.consume().unwrap();
         if open_paren.token_type != tokenizer::TokenType::Lparen {
@@ -531,14 +1258,15 @@ impl Parser {
         NodeStmt::While(NodeStmtWhile {
             condition,
             body,
+            loc,
         })
     }
 
 
     fn parse_push(&mut self) -> NodeStmt {
-        self.consume();
+        let loc = self.consume().unwrap().loc.clone();
         let expr = self.parse_expression();
-        NodeStmt::Push(NodeStmtPush { expr })
+        NodeStmt::Push(NodeStmtPush { expr, loc })
     }
 
     fn parse_pop(&mut self) -> NodeStmt {
@@ -551,14 +1279,14 @@ impl Parser {
     }
 
     fn parse_xor(&mut self) -> NodeStmt {
-        self.consume();
-        let ident = match self.parse_expression() {
-            NodeExpr::Ident(ident) => ident,
-            _ => panic!("Expected an identifier for the xor statement."),
-        };
+        let loc = self.consume().unwrap().loc.clone();
+        let dest = self.parse_expression();
+        if !matches!(dest, NodeExpr::Ident(_) | NodeExpr::Memory(_)) {
+            panic!("Expected an identifier or memory operand for the xor statement.");
+        }
         let _ = self.consume().unwrap(); 
         let expr = self.parse_expression();
-        NodeStmt::Xor(NodeStmtXor { ident, expr })
+        NodeStmt::Xor(NodeStmtXor { dest, expr, loc })
     }
 
     fn parse_define(&mut self) -> NodeStmt {
@@ -573,7 +1301,7 @@ impl Parser {
     }
 
     pub fn parse_statment(&mut self) -> Option<NodeStmt> {
-        while let Some(token) = self.peek(0)  {
+        if let Some(token) = self.peek(0)  {
             match token.token_type {
                 tokenizer::TokenType::Mov => {
                     return Some(self.parse_mov());
@@ -587,9 +1315,15 @@ impl Parser {
                 tokenizer::TokenType::Syscall => {
                     return Some(self.parse_syscall());
                 }
+                tokenizer::TokenType::Int => {
+                    return Some(self.parse_int());
+                }
                 tokenizer::TokenType::Function => {
                     return Some(self.parse_function());
                 }
+                tokenizer::TokenType::Extern => {
+                    return Some(self.parse_extern());
+                }
                 tokenizer::TokenType::Call => {
                     return Some(self.parse_call());
                 }
@@ -597,6 +1331,14 @@ impl Parser {
                     return Some(self.parse_section());
                 }
                 tokenizer::TokenType::Identifier => {
+                    let is_flags_enum = token.value.as_deref() == Some("flags")
+                        && matches!(self.peek(1), Some(Token { token_type: TokenType::Enum, .. }));
+                    if is_flags_enum {
+                        return Some(self.parse_enum());
+                    }
+                    if self.at_function_modifier() {
+                        return Some(self.parse_function());
+                    }
                     return Some(self.parse_assign());
                 }
                 tokenizer::TokenType::If => {
@@ -617,6 +1359,32 @@ impl Parser {
                 tokenizer::TokenType::Define => {
                     return Some(self.parse_define());
                 }
+                tokenizer::TokenType::Align => {
+                    return Some(self.parse_align());
+                }
+                tokenizer::TokenType::Bits => {
+                    return Some(self.parse_bits());
+                }
+                tokenizer::TokenType::Org => {
+                    return Some(self.parse_org());
+                }
+                tokenizer::TokenType::Pad => {
+                    return Some(self.parse_pad());
+                }
+                tokenizer::TokenType::BootSignature => {
+                    self.consume();
+                    return Some(NodeStmt::BootSignature);
+                }
+                tokenizer::TokenType::Struct
+                | tokenizer::TokenType::Packed => {
+                    return Some(self.parse_struct());
+                }
+                tokenizer::TokenType::Enum => {
+                    return Some(self.parse_enum());
+                }
+                tokenizer::TokenType::Const => {
+                    return Some(self.parse_const());
+                }
                 _ => {
                     panic!("Unexpected token {:?}", token);
                 }
@@ -627,53 +1395,218 @@ impl Parser {
         None
     }
 
+    /// Looks for an included file next to the file that includes it, then in the include
+    /// directories. Returns every location that was tried when the file is not found.
+    fn resolve_include(&self, path: &str, from: &Path) -> Result<PathBuf, Vec<PathBuf>> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return if path.is_file() { Ok(path.to_path_buf()) } else { Err(vec![path.to_path_buf()]) };
+        }
+
+        let mut candidates = Vec::new();
+        candidates.push(from.parent().unwrap_or(Path::new("")).join(path));
+        for dir in &self.include_dirs {
+            candidates.push(dir.join(path));
+        }
+
+        match candidates.iter().find(|candidate| candidate.is_file()) {
+            Some(found) => Ok(found.clone()),
+            None => Err(candidates),
+        }
+    }
+
+    /// Finds a library named by `include <name>` or `import name`. Bundled libraries are
+    /// keyed by `<name>` since they have no path on disk.
+    fn resolve_library(&self, name: &str, loc: &tokenizer::Loc) -> Result<(PathBuf, PathBuf, Option<String>), String> {
+        if let Some(dir) = self.library_dir() {
+            let path = dir.join(format!("{}.asmpp", name));
+            let canonical = path.canonicalize()
+                .map_err(|e| format!("{}: Failed to include library '{}' from '{}': {}", loc, name, path.display(), e))?;
+            return Ok((canonical, path, None));
+        }
+
+        match stdlib::bundled(name, self.target) {
+            Some(source) => {
+                let key = PathBuf::from(format!("<{}>", name));
+                Ok((key.clone(), key, Some(source.to_string())))
+            }
+            None => Err(format!("{}: Unknown library '{}', available libraries: {}", loc, name, stdlib::names(self.target).join(", "))),
+        }
+    }
+
     fn parse_include(&mut self) -> Result<(), String> {
-        let token = self.consume().expect("Expected 'include' directive");
-        if let TokenType::Include = token.token_type {
-            let path_token = self.consume().expect("Expected a file path after 'include'");
-            match &path_token.token_type {
-                TokenType::StringLit => {
-                    let path = &path_token.value.clone().unwrap();
-                    let content = std::fs::read_to_string(path)
-                        .map_err(|e| format!("Failed to include file '{}': {}", path, e))?;
-                    let mut tokenizer = tokenizer::Tokenizer::new(content);
-                    let included_tokens = tokenizer.tokenize();
-                    self.tokens.splice(self.index..self.index, included_tokens);
-                }
-                _ => return Err("Expected a string literal as file path for 'include'".into()),
+        let token = self.consume().expect("Expected 'include' directive").clone();
+        let path_token = self.consume().expect("Expected a file path after 'include'").clone();
+
+        let (canonical, resolved, content) = match (token.token_type, path_token.token_type) {
+            (TokenType::Include, TokenType::StringLit) => {
+                let path = &path_token.value.clone().unwrap();
+                let resolved = self.resolve_include(path, &path_token.loc.file).map_err(|tried| {
+                    let tried: Vec<String> = tried.iter().map(|candidate| format!("  {}", candidate.display())).collect();
+                    format!("{}: Failed to include file '{}', tried:\n{}", path_token.loc, path, tried.join("\n"))
+                })?;
+                let canonical = resolved.canonicalize()
+                    .map_err(|e| format!("{}: Failed to include file '{}': {}", path_token.loc, resolved.display(), e))?;
+                (canonical, resolved, None)
             }
-        } else {
-            return Err("Expected 'include' directive".into());
+            (TokenType::Include, TokenType::LibPath) => {
+                self.resolve_library(path_token.value.as_ref().unwrap(), &path_token.loc)?
+            }
+            (TokenType::Import, TokenType::Identifier) => {
+                let name = path_token.value.clone().unwrap();
+                if self.stdlib_dir.is_none() && stdlib::bundled(&name, self.target).is_some() {
+                    self.resolve_library(&name, &path_token.loc)?
+                } else {
+                    // `import net.http` looks for `net/http.asmpp` like an include would
+                    let path = format!("{}.asmpp", name.replace('.', "/"));
+                    let library = self.library_dir().map(|dir| dir.join(&path)).filter(|library| library.is_file());
+                    let resolved = match library {
+                        Some(library) => library,
+                        None => self.resolve_include(&path, &path_token.loc.file).map_err(|tried| {
+                            let tried: Vec<String> = tried.iter().map(|candidate| format!("  {}", candidate.display())).collect();
+                            format!("{}: Failed to import module '{}', tried:\n{}", path_token.loc, name, tried.join("\n"))
+                        })?,
+                    };
+                    let canonical = resolved.canonicalize()
+                        .map_err(|e| format!("{}: Failed to import module '{}': {}", path_token.loc, name, e))?;
+                    (canonical, resolved, None)
+                }
+            }
+            (TokenType::Include, _) => return Err(format!("{}: Expected a string literal or <library> after 'include'", path_token.loc)),
+            (TokenType::Import, _) => return Err(format!("{}: Expected a library name after 'import'", path_token.loc)),
+            _ => return Err(format!("{}: Expected 'include' directive", token.loc)),
+        };
+
+        if self.include_stack.iter().any(|(open, _)| *open == canonical) {
+            let mut chain: Vec<String> = self.include_stack.iter().map(|(_, written)| written.display().to_string()).collect();
+            chain.push(resolved.display().to_string());
+            return Err(format!("{}: Include cycle: {}", path_token.loc, chain.join(" -> ")));
         }
+        if !self.included.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let content = match content {
+            Some(content) => content,
+            None => std::fs::read_to_string(&resolved)
+                .map_err(|e| format!("{}: Failed to include file '{}': {}", path_token.loc, resolved.display(), e))?,
+        };
+        let mut tokenizer = tokenizer::Tokenizer::new(content, &resolved);
+        let mut included_tokens = tokenizer.tokenize();
+        // Marks where the included file ends so the include stack can be popped
+        included_tokens.push(Token { token_type: TokenType::EndInclude, value: None, loc: path_token.loc.clone() });
+        self.tokens.splice(self.index..self.index, included_tokens);
+        self.include_stack.push((canonical, resolved));
         Ok(())
     }
 
+    fn parse_pragma(&mut self) {
+        let loc = self.consume().expect("Expected '#pragma'").loc.clone();
+        match self.consume() {
+            // Every file is only included once, `#pragma once` states it explicitly
+            Some(Token { token_type: TokenType::Identifier, value: Some(name), .. }) if name == "once" => {}
+            Some(Token { value: Some(name), .. }) => panic!("{}: Unknown pragma '{}'", loc, name),
+            _ => panic!("{}: Expected a pragma name after '#pragma'", loc),
+        }
+    }
+
+    fn parse_module(&mut self) -> String {
+        let loc = self.consume().expect("Expected 'module'").loc.clone();
+        match self.consume() {
+            Some(Token { token_type: TokenType::Identifier, value: Some(name), .. }) if !name.contains('.') => name.clone(),
+            _ => panic!("{}: Expected a module name after 'module'", loc),
+        }
+    }
 
     pub fn parse_prog(&mut self) -> Node {
         let mut stmt = Vec::new();
+        // Module and `pub` flag of every entry in `stmt`, used to mangle data labels
+        let mut stmt_scopes = Vec::new();
+        // Module declared by each file on the include stack
+        let mut module_stack: Vec<Option<String>> = vec![None];
+        let mut public = false;
         let mut functions = Vec::new();
         let mut defines: Vec<NodeStmtDefine> = Vec::new();
+        let mut structs = Vec::new();
+        let mut enums = Vec::new();
+        let mut consts = Vec::new();
+        let mut externs = Vec::new();
+        let mut bits: Option<NodeStmtBits> = None;
+
+        if let Some(token) = self.peek(0) {
+            let root = token.loc.file.as_ref().clone();
+            if let Ok(canonical) = root.canonicalize() {
+                self.included.insert(canonical.clone());
+                self.include_stack.push((canonical, root));
+            }
+        }
 
         while let Some(token) = self.peek(0) {
             match token.token_type {
-                TokenType::Include => {
-                    self.parse_include().expect("Failed to parse include directive");
+                TokenType::Include | TokenType::Import => {
+                    let depth = self.include_stack.len();
+                    self.parse_include().unwrap_or_else(|e| panic!("{}", e));
+                    if self.include_stack.len() > depth {
+                        module_stack.push(None);
+                    }
+                    continue;
                 }
-                _ => break,
+                TokenType::EndInclude => {
+                    self.consume();
+                    self.include_stack.pop();
+                    module_stack.pop();
+                    continue;
+                }
+                TokenType::Pragma => {
+                    self.parse_pragma();
+                    continue;
+                }
+                TokenType::Module => {
+                    let name = self.parse_module();
+                    *module_stack.last_mut().unwrap() = Some(name);
+                    continue;
+                }
+                TokenType::Pub => {
+                    self.consume();
+                    public = true;
+                    continue;
+                }
+                _ => {}
             }
-            
-        }
 
-        
-        while let Some(node) = self.parse_statment() {
+            let node = match self.parse_statment() {
+                Some(node) => node,
+                None => break,
+            };
+            let module = module_stack.last().cloned().flatten();
+            let is_public = std::mem::take(&mut public);
             match node {
-                NodeStmt::Func(func) => functions.push(func),
+                NodeStmt::Func(mut func) => {
+                    func.module = module;
+                    func.public = is_public;
+                    functions.push(func);
+                }
                 NodeStmt::Define(define) => defines.push(define),
-                _ => stmt.push(node),
+                NodeStmt::Struct(structure) => structs.push(structure),
+                NodeStmt::Enum(enumeration) => enums.push(enumeration),
+                NodeStmt::Const(block) => consts.push(block),
+                NodeStmt::Extern(prototype) => externs.push(prototype),
+                NodeStmt::Bits(stmt) => {
+                    if let Some(previous) = bits.as_ref().filter(|previous| previous.bits != stmt.bits) {
+                        panic!("{}: 'bits {}' after 'bits {}' at {}, a program has a single width", stmt.loc, stmt.bits, previous.bits, previous.loc);
+                    }
+                    bits = Some(stmt);
+                }
+                _ => {
+                    stmt.push(node);
+                    stmt_scopes.push((module, is_public));
+                }
             }
         }
 
-        Node { stmt, functions, defines }
+        let mut node = Node { stmt, functions, defines, structs, enums, consts, externs, bits, diagnostics: Vec::new() };
+        node.diagnostics = modules::mangle(&mut node, &stmt_scopes);
+        node
     }
 
     fn peek(&self, offset: usize) -> Option<&Token> {
@@ -697,3 +1630,16 @@ impl Parser {
         }
     }
 }
+
+/// Decodes decimal, `0x` hex and `0b` binary literals; `_` may be used as a digit separator.
+fn parse_number(literal: &str) -> i64 {
+    let digits = literal.replace('_', "");
+    let parsed = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        u64::from_str_radix(bin, 2)
+    } else {
+        digits.parse::<u64>()
+    };
+    parsed.unwrap_or_else(|_| panic!("Invalid number literal '{}'", literal)) as i64
+}
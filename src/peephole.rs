@@ -0,0 +1,482 @@
+use std::collections::HashSet;
+
+use crate::ir::{Code, Function, Instruction, Item, Opcode, Operand, Program, Value};
+use crate::registers::{self, Register};
+use crate::target::Target;
+
+fn instruction(code: &[Code], index: usize) -> Option<&Instruction> {
+    match code.get(index) {
+        Some(Code::Instruction(instruction)) => Some(instruction),
+        _ => None,
+    }
+}
+
+// Index of the instruction after the one at `index`, past any source positions but not past a label
+fn next_instruction(code: &[Code], index: usize) -> Option<usize> {
+    let next = index + 1 + code.get(index + 1..)?.iter().take_while(|line| matches!(line, Code::Line(_))).count();
+    instruction(code, next).map(|_| next)
+}
+
+fn reads_flags(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::Jump(Some(_), _))
+}
+
+fn writes_all_flags(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::Binary(opcode, _, _) if *opcode != Opcode::Mov)
+}
+
+/// Whether the flags left by the instruction at `index` can never be read. Falls through
+/// labels, and gives up at anything it cannot follow: unconditional jumps and the end of the code.
+fn flags_dead_after(code: &[Code], index: usize) -> bool {
+    for line in &code[index + 1..] {
+        match line {
+            Code::Label(_) | Code::Line(_) => continue,
+            Code::Instruction(instruction) => {
+                if reads_flags(instruction) || matches!(instruction, Instruction::Jump(None, _)) {
+                    return false;
+                }
+                // The flags are not preserved across calls and returns
+                if writes_all_flags(instruction) || matches!(instruction, Instruction::Call(_) | Instruction::Ret) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// Whether writing the register clears the upper half of its 64-bit register, as a 32-bit
+// register does on x86-64
+fn clears_upper_half(register: &Register, target: Target) -> bool {
+    target == Target::X86_64 && register.size == 4
+}
+
+// `mov rax, rax` does nothing, but `mov eax, eax` clears the upper half of rax
+fn remove_self_moves(code: &mut Vec<Code>, target: Target) -> bool {
+    let before = code.len();
+    code.retain(|line| match line {
+        Code::Instruction(Instruction::Binary(Opcode::Mov, Operand::Register(dest), Operand::Register(src))) => {
+            dest != src || clears_upper_half(dest, target)
+        }
+        _ => true,
+    });
+    code.len() != before
+}
+
+// A `mov` that leaves its destination with the value it already has: `mov a, b` repeated, or
+// followed by `mov b, a` (unless b is a 32-bit register whose upper half that would clear)
+fn remove_redundant_moves(code: &mut Vec<Code>, target: Target) -> bool {
+    for index in 0..code.len() {
+        let Some(next) = next_instruction(code, index) else {
+            continue;
+        };
+        let (Some(first), Some(second)) = (instruction(code, index), instruction(code, next)) else {
+            continue;
+        };
+        let Instruction::Binary(Opcode::Mov, a, b) = first else {
+            continue;
+        };
+        let Some(dest) = a.register() else {
+            continue;
+        };
+        let repeated = first == second
+            && !matches!(b, Operand::Memory(_))
+            && b.register().is_none_or(|src| src.full != dest.full);
+        let moved_back = *second == Instruction::Binary(Opcode::Mov, b.clone(), a.clone())
+            && b.register().is_none_or(|src| !clears_upper_half(src, target));
+        if repeated || moved_back {
+            code.remove(next);
+            return true;
+        }
+    }
+    false
+}
+
+// `push x` straight followed by `pop y` is `mov y, x`, or nothing at all when x is y
+fn merge_push_pop(code: &mut Vec<Code>, target: Target) -> bool {
+    for index in 0..code.len() {
+        let Some(next) = next_instruction(code, index) else {
+            continue;
+        };
+        let (Some(Instruction::Push(src)), Some(Instruction::Pop(dest))) = (instruction(code, index), instruction(code, next)) else {
+            continue;
+        };
+        if src == dest {
+            code.remove(next);
+            code.remove(index);
+            return true;
+        }
+        // A register the size of a word can take anything push can; two memory operands would
+        // need a scratch register
+        if dest.register().is_some_and(|register| register.size == target.word_size()) {
+            let mov = Instruction::Binary(Opcode::Mov, dest.clone(), src.clone());
+            code.remove(next);
+            code[index] = Code::Instruction(mov);
+            return true;
+        }
+    }
+    false
+}
+
+fn rsp_adjustment(instruction: &Instruction, target: Target) -> Option<i64> {
+    let Instruction::Binary(opcode, Operand::Register(register), Operand::Immediate(Value::Number(amount))) = instruction else {
+        return None;
+    };
+    if *register != target.register("rsp") {
+        return None;
+    }
+    match opcode {
+        Opcode::Add => Some(*amount),
+        Opcode::Sub => Some(-amount),
+        _ => None,
+    }
+}
+
+fn adjust_rsp(total: i64, target: Target) -> Code {
+    let rsp = Operand::Register(target.register("rsp"));
+    let (opcode, amount) = if total > 0 { (Opcode::Add, total) } else { (Opcode::Sub, -total) };
+    Code::Instruction(Instruction::Binary(opcode, rsp, Operand::Immediate(Value::Number(amount))))
+}
+
+// `add rsp, 24` after one call and `sub rsp, 8` before the next become `add rsp, 16`
+fn merge_stack_adjustments(code: &mut Vec<Code>, target: Target) -> bool {
+    for index in 0..code.len() {
+        let Some(next) = next_instruction(code, index) else {
+            continue;
+        };
+        let (Some(first), Some(second)) = (instruction(code, index), instruction(code, next)) else {
+            continue;
+        };
+        let (Some(a), Some(b)) = (rsp_adjustment(first, target), rsp_adjustment(second, target)) else {
+            continue;
+        };
+        if !flags_dead_after(code, next) {
+            continue;
+        }
+        code.remove(next);
+        match a + b {
+            0 => {
+                code.remove(index);
+            }
+            total => code[index] = adjust_rsp(total, target),
+        }
+        return true;
+    }
+    false
+}
+
+// First instruction executed when jumping to `label`
+fn first_instruction_at<'a>(code: &'a [Code], label: &str) -> Option<&'a Instruction> {
+    let index = code.iter().position(|line| matches!(line, Code::Label(name) if name == label))?;
+    code[index..].iter().find_map(|line| match line {
+        Code::Label(_) | Code::Line(_) => None,
+        Code::Instruction(instruction) => Some(instruction),
+    })
+}
+
+// A jump to a label whose first instruction is `jmp target` goes to `target` directly
+fn thread_jumps(code: &mut [Code]) -> bool {
+    let mut changed = false;
+    for index in 0..code.len() {
+        let Some(Instruction::Jump(_, target)) = instruction(code, index) else {
+            continue;
+        };
+        let mut target = target.clone();
+        let mut visited = HashSet::new();
+        while visited.insert(target.clone()) {
+            match first_instruction_at(code, &target) {
+                Some(Instruction::Jump(None, next)) => target = next.clone(),
+                _ => break,
+            }
+        }
+        if let Code::Instruction(Instruction::Jump(_, jump_target)) = &mut code[index] {
+            if *jump_target != target {
+                *jump_target = target;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+// A jump to the label that directly follows it, as left behind by an empty `if` body
+fn remove_jumps_to_next(code: &mut Vec<Code>) -> bool {
+    for index in 0..code.len() {
+        let Some(Instruction::Jump(_, target)) = instruction(code, index) else {
+            continue;
+        };
+        let falls_into_target = code[index + 1..].iter()
+            .take_while(|line| matches!(line, Code::Label(_) | Code::Line(_)))
+            .any(|line| matches!(line, Code::Label(label) if label == target));
+        if falls_into_target {
+            code.remove(index);
+            return true;
+        }
+    }
+    false
+}
+
+// `cmp` only produces flags, once nothing reads them it can go
+fn remove_dead_compares(code: &mut Vec<Code>) -> bool {
+    for index in 0..code.len() {
+        let is_compare = matches!(instruction(code, index), Some(Instruction::Binary(Opcode::Cmp, _, _)));
+        if is_compare && flags_dead_after(code, index) {
+            code.remove(index);
+            return true;
+        }
+    }
+    false
+}
+
+// `mov reg, 0` is longer than `xor reg32, reg32`, which also clears the upper half but sets the flags
+fn zero_with_xor(code: &mut [Code]) -> bool {
+    let mut changed = false;
+    for index in 0..code.len() {
+        let Some(Instruction::Binary(Opcode::Mov, Operand::Register(register), Operand::Immediate(Value::Number(0)))) = instruction(code, index) else {
+            continue;
+        };
+        // Writing a 32-bit register clears the upper half, narrower ones only touch their own bits
+        let target = match register.size {
+            8 => registers::sibling(register, 4),
+            _ => Some(*register),
+        };
+        let Some(target) = target else {
+            continue;
+        };
+        if flags_dead_after(code, index) {
+            let xor = Instruction::Binary(Opcode::Xor, Operand::Register(target), Operand::Register(target));
+            code[index] = Code::Instruction(xor);
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Local labels (`.name`) that no jump refers to
+fn remove_dead_labels(code: &mut Vec<Code>) -> bool {
+    let referenced: HashSet<String> = code.iter()
+        .filter_map(|line| match line {
+            Code::Instruction(Instruction::Jump(_, target)) => Some(target.clone()),
+            _ => None,
+        })
+        .collect();
+    let before = code.len();
+    code.retain(|line| match line {
+        Code::Label(label) if label.starts_with('.') => referenced.contains(label),
+        _ => true,
+    });
+    code.len() != before
+}
+
+fn optimise_code(code: &mut Vec<Code>, target: Target) -> bool {
+    let mut changed = false;
+    loop {
+        let mut pass_changed = false;
+        pass_changed |= remove_self_moves(code, target);
+        pass_changed |= remove_redundant_moves(code, target);
+        pass_changed |= merge_push_pop(code, target);
+        pass_changed |= merge_stack_adjustments(code, target);
+        pass_changed |= thread_jumps(code);
+        pass_changed |= remove_jumps_to_next(code);
+        pass_changed |= remove_dead_compares(code);
+        pass_changed |= zero_with_xor(code);
+        pass_changed |= remove_dead_labels(code);
+        if !pass_changed {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+// Blocks that no path from the top of the function leads to, such as code after a `jmp`
+fn remove_unreachable_blocks(function: &mut Function) -> bool {
+    let reachable = function.cfg().reachable();
+    let before = function.blocks.len();
+    let mut index = 0;
+    function.blocks.retain(|_| {
+        index += 1;
+        reachable[index - 1]
+    });
+    function.blocks.len() != before
+}
+
+fn optimise_function(function: &mut Function, target: Target) {
+    loop {
+        let mut code = function.code();
+        let mut changed = optimise_code(&mut code, target);
+        *function = Function::new(function.name.clone(), code);
+        changed |= remove_unreachable_blocks(function);
+        if !changed {
+            break;
+        }
+    }
+}
+
+// Optimises the code outside of functions, a run of labels and instructions at a time
+fn optimise_top_level(items: Vec<Item>, target: Target) -> Vec<Item> {
+    let flush = |run: &mut Vec<Code>, result: &mut Vec<Item>| {
+        optimise_code(run, target);
+        result.extend(run.drain(..).map(|line| match line {
+            Code::Label(label) => Item::Label(label),
+            Code::Instruction(instruction) => Item::Instruction(instruction),
+            Code::Line(loc) => Item::Line(loc),
+        }));
+    };
+
+    let mut result = Vec::new();
+    let mut run = Vec::new();
+    // Code before the first `section` goes into `.text`, NASM's default; labels elsewhere belong to data
+    let mut in_text = true;
+    for item in items {
+        match item {
+            Item::Label(label) if in_text => run.push(Code::Label(label)),
+            Item::Instruction(instruction) => run.push(Code::Instruction(instruction)),
+            Item::Line(loc) => run.push(Code::Line(loc)),
+            item => {
+                flush(&mut run, &mut result);
+                if let Item::Section(name) = &item {
+                    in_text = name == "text";
+                }
+                result.push(item);
+            }
+        }
+    }
+    flush(&mut run, &mut result);
+    result
+}
+
+/// Runs every peephole pass over the code of `program` until none of them finds anything
+/// left to change.
+pub fn optimise(program: &mut Program) {
+    for item in &mut program.items {
+        if let Item::Function(function) = item {
+            optimise_function(function, program.target);
+        }
+    }
+    program.items = optimise_top_level(std::mem::take(&mut program.items), program.target);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Condition, Memory};
+    use crate::parser::DataType;
+
+    fn reg(name: &str) -> Operand {
+        Operand::Register(registers::lookup(name).unwrap())
+    }
+
+    fn binary(opcode: Opcode, dest: &str, value: i64) -> Code {
+        Code::Instruction(Instruction::Binary(opcode, reg(dest), Operand::Immediate(Value::Number(value))))
+    }
+
+    fn jump(condition: Option<Condition>, target: &str) -> Code {
+        Code::Instruction(Instruction::Jump(condition, target.to_string()))
+    }
+
+    fn label(name: &str) -> Code {
+        Code::Label(name.to_string())
+    }
+
+    fn push(operand: Operand) -> Code {
+        Code::Instruction(Instruction::Push(operand))
+    }
+
+    fn pop(operand: Operand) -> Code {
+        Code::Instruction(Instruction::Pop(operand))
+    }
+
+    fn ret() -> Code {
+        Code::Instruction(Instruction::Ret)
+    }
+
+    fn optimised(code: Vec<Code>) -> Vec<Code> {
+        let mut function = Function::new("f".to_string(), code);
+        optimise_function(&mut function, Target::X86_64);
+        function.code()
+    }
+
+    #[test]
+    fn threads_jumps_to_jumps() {
+        let before = vec![
+            binary(Opcode::Cmp, "rax", 1),
+            jump(Some(Condition::Equal), ".a"),
+            binary(Opcode::Add, "rbx", 1),
+            ret(),
+            label(".b"),
+            binary(Opcode::Add, "rcx", 1),
+            ret(),
+            label(".a"),
+            jump(None, ".b"),
+        ];
+        let after = vec![
+            binary(Opcode::Cmp, "rax", 1),
+            jump(Some(Condition::Equal), ".b"),
+            binary(Opcode::Add, "rbx", 1),
+            ret(),
+            label(".b"),
+            binary(Opcode::Add, "rcx", 1),
+            ret(),
+        ];
+        assert_eq!(optimised(before), after);
+    }
+
+    #[test]
+    fn removes_unreachable_blocks_and_dead_labels() {
+        let before = vec![
+            jump(None, ".end"),
+            binary(Opcode::Add, "rax", 1),
+            label(".unused"),
+            binary(Opcode::Add, "rbx", 1),
+            label(".end"),
+            ret(),
+        ];
+        assert_eq!(optimised(before), vec![ret()]);
+    }
+
+    #[test]
+    fn merges_push_and_pop() {
+        let before = vec![push(reg("rax")), pop(reg("rbx")), push(reg("rcx")), pop(reg("rcx")), ret()];
+        let after = vec![Code::Instruction(Instruction::Binary(Opcode::Mov, reg("rbx"), reg("rax"))), ret()];
+        assert_eq!(optimised(before), after);
+    }
+
+    #[test]
+    fn removes_compares_nothing_reads() {
+        let before = vec![binary(Opcode::Cmp, "rax", 1), binary(Opcode::Add, "rbx", 1), ret()];
+        assert_eq!(optimised(before), vec![binary(Opcode::Add, "rbx", 1), ret()]);
+    }
+
+    #[test]
+    fn keeps_referenced_labels_and_flags_read_after_a_label() {
+        let unchanged = [
+            vec![
+                label(".loop"),
+                binary(Opcode::Add, "rax", 1),
+                binary(Opcode::Cmp, "rax", 10),
+                jump(Some(Condition::Less), ".loop"),
+                ret(),
+            ],
+            vec![
+                binary(Opcode::Cmp, "rax", 1),
+                label(".next"),
+                jump(Some(Condition::Equal), ".done"),
+                binary(Opcode::Add, "rbx", 1),
+                jump(None, ".next"),
+                label(".done"),
+                ret(),
+            ],
+        ];
+        for code in unchanged {
+            assert_eq!(optimised(code.clone()), code);
+        }
+    }
+
+    // Moving memory to memory would need a scratch register
+    #[test]
+    fn keeps_push_and_pop_of_memory() {
+        let memory = |base: &str| Operand::Memory(Memory { size: Some(DataType::U64), base: registers::lookup(base), index: None, displacement: Vec::new() });
+        let code = vec![push(memory("rbx")), pop(memory("rcx")), ret()];
+        assert_eq!(optimised(code.clone()), code);
+    }
+}
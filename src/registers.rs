@@ -0,0 +1,65 @@
+/// A general purpose register of x86-64 at one of its widths.
+#[derive(Debug, PartialEq)]
+pub struct Register {
+    pub name: &'static str,
+    // The 64-bit register this one is part of, `ebx`, `bx` and `bl` all belong to `rbx`
+    pub full: &'static str,
+    // Width in bytes
+    pub size: usize,
+    // `ah`, `bh`, `ch` and `dh`, which cannot be encoded in an instruction with a REX prefix
+    pub high_byte: bool,
+    // `r8` ... `r15` at any width and `sil`, `dil`, `bpl`, `spl` can only be encoded with a REX prefix
+    pub rex: bool,
+}
+
+impl Register {
+    pub fn bits(&self) -> usize {
+        self.size * 8
+    }
+}
+
+const fn legacy(name: &'static str, full: &'static str, size: usize) -> Register {
+    Register { name, full, size, high_byte: false, rex: false }
+}
+
+const fn rex(name: &'static str, full: &'static str, size: usize) -> Register {
+    Register { name, full, size, high_byte: false, rex: true }
+}
+
+const fn high(name: &'static str, full: &'static str) -> Register {
+    Register { name, full, size: 1, high_byte: true, rex: false }
+}
+
+/// Every general purpose register of x86-64, at all widths.
+const REGISTERS: &[Register] = &[
+    legacy("rax", "rax", 8), legacy("eax", "rax", 4), legacy("ax", "rax", 2), legacy("al", "rax", 1), high("ah", "rax"),
+    legacy("rbx", "rbx", 8), legacy("ebx", "rbx", 4), legacy("bx", "rbx", 2), legacy("bl", "rbx", 1), high("bh", "rbx"),
+    legacy("rcx", "rcx", 8), legacy("ecx", "rcx", 4), legacy("cx", "rcx", 2), legacy("cl", "rcx", 1), high("ch", "rcx"),
+    legacy("rdx", "rdx", 8), legacy("edx", "rdx", 4), legacy("dx", "rdx", 2), legacy("dl", "rdx", 1), high("dh", "rdx"),
+    legacy("rsi", "rsi", 8), legacy("esi", "rsi", 4), legacy("si", "rsi", 2), rex("sil", "rsi", 1),
+    legacy("rdi", "rdi", 8), legacy("edi", "rdi", 4), legacy("di", "rdi", 2), rex("dil", "rdi", 1),
+    legacy("rbp", "rbp", 8), legacy("ebp", "rbp", 4), legacy("bp", "rbp", 2), rex("bpl", "rbp", 1),
+    legacy("rsp", "rsp", 8), legacy("esp", "rsp", 4), legacy("sp", "rsp", 2), rex("spl", "rsp", 1),
+    rex("r8", "r8", 8), rex("r8d", "r8", 4), rex("r8w", "r8", 2), rex("r8b", "r8", 1),
+    rex("r9", "r9", 8), rex("r9d", "r9", 4), rex("r9w", "r9", 2), rex("r9b", "r9", 1),
+    rex("r10", "r10", 8), rex("r10d", "r10", 4), rex("r10w", "r10", 2), rex("r10b", "r10", 1),
+    rex("r11", "r11", 8), rex("r11d", "r11", 4), rex("r11w", "r11", 2), rex("r11b", "r11", 1),
+    rex("r12", "r12", 8), rex("r12d", "r12", 4), rex("r12w", "r12", 2), rex("r12b", "r12", 1),
+    rex("r13", "r13", 8), rex("r13d", "r13", 4), rex("r13w", "r13", 2), rex("r13b", "r13", 1),
+    rex("r14", "r14", 8), rex("r14d", "r14", 4), rex("r14w", "r14", 2), rex("r14b", "r14", 1),
+    rex("r15", "r15", 8), rex("r15d", "r15", 4), rex("r15w", "r15", 2), rex("r15b", "r15", 1),
+];
+
+pub fn lookup(name: &str) -> Option<&'static Register> {
+    REGISTERS.iter().find(|register| register.name == name)
+}
+
+/// The register of the given width that is part of the same 64-bit register, `eax` for `rax`.
+/// The legacy high bytes are never returned.
+pub fn sibling(register: &Register, size: usize) -> Option<&'static Register> {
+    REGISTERS.iter().find(|other| other.full == register.full && other.size == size && !other.high_byte)
+}
+
+pub fn is_register(name: &str) -> bool {
+    lookup(name).is_some()
+}
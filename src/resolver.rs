@@ -0,0 +1,824 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::clobbers;
+use crate::consts;
+use crate::diagnostic::Diagnostic;
+use crate::layout::{self, StructLayout};
+use crate::operands::{self, Operand};
+use crate::parser::{DataType, Node, NodeExpr, NodeExprIdent, NodeFunc, NodeParam, NodeStmt, NodeStmtCall, NodeStruct, ParamType};
+use crate::registers;
+use crate::target::Target;
+use crate::tokenizer::Loc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Extern,
+    Data,
+    Define,
+    Constant,
+    Struct,
+}
+
+impl SymbolKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Extern => "extern function",
+            SymbolKind::Data => "data label",
+            SymbolKind::Define => "define",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Struct => "struct",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub loc: Loc,
+}
+
+/// Name resolution between parsing and code generation. Builds the table of every
+/// global symbol, then checks each name used in the program against it and against the
+/// parameters of the enclosing function and the register file.
+pub struct Resolver<'a> {
+    node: &'a Node,
+    symbols: HashMap<String, Symbol>,
+    layouts: HashMap<String, StructLayout>,
+    constants: HashMap<String, i64>,
+    data_types: HashMap<String, DataType>,
+    // Parameter list of every function and extern prototype, checked against each call
+    signatures: HashMap<String, &'a [NodeParam]>,
+    // Functions that never return, a call to one ends its block
+    noreturn: HashSet<String>,
+    // Parameters of the naked function being checked, declared for callers but not addressable
+    naked_parameters: HashSet<String>,
+    // Warn about callee-saved registers a function writes without listing them in `uses`
+    warn_clobbers: bool,
+    target: Target,
+    // Whether the output is a flat binary, the only kind of file `org` means anything in
+    flat: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+// What an argument is known to be, as far as type annotations are concerned
+enum ArgumentKind {
+    Address(String),
+    Number(Option<i64>),
+    Unknown,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(node: &'a Node) -> Resolver<'a> {
+        let (constants, mut diagnostics) = consts::collect_constants(&node.enums, &node.consts);
+        diagnostics.splice(0..0, node.diagnostics.iter().cloned());
+        Resolver {
+            node,
+            symbols: HashMap::new(),
+            layouts: layout::compute_layouts(&node.structs),
+            constants,
+            data_types: layout::data_label_types(&node.stmt),
+            signatures: HashMap::new(),
+            noreturn: HashSet::new(),
+            naked_parameters: HashSet::new(),
+            warn_clobbers: false,
+            target: Target::default(),
+            flat: false,
+            diagnostics,
+        }
+    }
+
+    pub fn set_warn_clobbers(&mut self, enabled: bool) {
+        self.warn_clobbers = enabled;
+    }
+
+    pub fn set_target(&mut self, target: Target) {
+        self.target = target;
+    }
+
+    pub fn set_flat(&mut self, flat: bool) {
+        self.flat = flat;
+    }
+
+    pub fn resolve(mut self) -> Vec<Diagnostic> {
+        self.declare_globals();
+        self.check_values();
+
+        for func in &self.node.functions {
+            let mut locals = HashSet::new();
+            for argument in &func.arguments {
+                self.check_parameter(&argument.ident, &locals);
+                locals.insert(argument.ident.name.clone());
+            }
+            if func.naked {
+                self.naked_parameters = std::mem::take(&mut locals);
+            }
+            self.check_body(&func.body, &locals);
+            self.naked_parameters.clear();
+            self.check_clobbers(func);
+        }
+
+        let no_locals = HashSet::new();
+        for stmt in &self.node.stmt {
+            self.check_stmt(stmt, &no_locals);
+        }
+
+        self.diagnostics
+    }
+
+    fn declare(&mut self, ident: &NodeExprIdent, kind: SymbolKind) {
+        if registers::is_register(&ident.name) {
+            self.diagnostics.push(Diagnostic::error(&ident.loc, format!("'{}' is a register and cannot be used as the name of a {}", ident.name, kind.describe())));
+            return;
+        }
+        if let Some(existing) = self.symbols.get(&ident.name) {
+            self.diagnostics.push(Diagnostic::error(&ident.loc, format!("duplicate {} '{}', already declared as {} at {}", kind.describe(), ident.name, existing.kind.describe(), existing.loc)));
+            return;
+        }
+        self.symbols.insert(ident.name.clone(), Symbol { kind, loc: ident.loc.clone() });
+    }
+
+    fn declare_globals(&mut self) {
+        let node = self.node;
+        for func in &node.functions {
+            self.declare(&func.name, SymbolKind::Function);
+            self.signatures.insert(func.name.name.clone(), &func.arguments);
+            if func.noreturn {
+                self.noreturn.insert(func.name.name.clone());
+            }
+        }
+        for prototype in &node.externs {
+            self.declare(&prototype.name, SymbolKind::Extern);
+            self.signatures.insert(prototype.name.name.clone(), &prototype.arguments);
+            if prototype.noreturn {
+                self.noreturn.insert(prototype.name.name.clone());
+            }
+        }
+        for define in &node.defines {
+            self.declare(&define.ident, SymbolKind::Define);
+        }
+        let mut structs = HashSet::new();
+        for structure in &node.structs {
+            self.declare(&structure.name, SymbolKind::Struct);
+            self.check_fields(structure, &structs);
+            structs.insert(structure.name.name.as_str());
+        }
+        for block in &node.consts {
+            for member in &block.members {
+                self.declare(&member.ident, SymbolKind::Constant);
+            }
+        }
+        for enumeration in &node.enums {
+            for member in &enumeration.members {
+                let qualified = NodeExprIdent { name: format!("{}.{}", enumeration.name.name, member.ident.name), loc: member.ident.loc.clone() };
+                self.declare(&qualified, SymbolKind::Constant);
+            }
+        }
+        for stmt in &node.stmt {
+            let label = match stmt {
+                NodeStmt::Assign(assign) => &assign.ident,
+                NodeStmt::Data(data) => &data.ident,
+                NodeStmt::Reserve(reserve) => &reserve.ident,
+                NodeStmt::Instance(instance) => &instance.ident,
+                _ => continue,
+            };
+            self.declare(label, SymbolKind::Data);
+            // The generator defines `<label>_len` and `<label>_size` next to every data label
+            for suffix in ["_len", "_size"] {
+                self.symbols.entry(format!("{}{}", label.name, suffix))
+                    .or_insert(Symbol { kind: SymbolKind::Constant, loc: label.loc.clone() });
+            }
+        }
+    }
+
+    // Values of constants, enum members and defines. Inside an enum the other members can be
+    // named without the enum's name
+    fn check_values(&mut self) {
+        let node = self.node;
+        let no_locals = HashSet::new();
+        for block in &node.consts {
+            for value in block.members.iter().filter_map(|member| member.value.as_ref()) {
+                self.check_expr(value, &no_locals);
+            }
+        }
+        for enumeration in &node.enums {
+            let members = enumeration.members.iter().map(|member| member.ident.name.clone()).collect();
+            for value in enumeration.members.iter().filter_map(|member| member.value.as_ref()) {
+                self.check_expr(value, &members);
+            }
+        }
+        for define in &node.defines {
+            self.check_expr(&define.expr, &no_locals);
+        }
+    }
+
+    // Fields have a data type or the type of a struct declared before theirs
+    fn check_fields(&mut self, structure: &NodeStruct, declared: &HashSet<&str>) {
+        for field in &structure.fields {
+            let type_name = &field.type_name.name;
+            if DataType::from_name(type_name).is_some() || declared.contains(type_name.as_str()) {
+                continue;
+            }
+            let message = if *type_name == structure.name.name {
+                format!("struct '{}' cannot contain itself", type_name)
+            } else if self.node.structs.iter().any(|other| other.name.name == *type_name) {
+                format!("struct '{}' is used by '{}.{}' before it is declared", type_name, structure.name.name, field.ident.name)
+            } else {
+                format!("unknown type '{}' for field '{}.{}'", type_name, structure.name.name, field.ident.name)
+            };
+            self.diagnostics.push(Diagnostic::error(&field.type_name.loc, message));
+        }
+    }
+
+    fn check_parameter(&mut self, argument: &NodeExprIdent, locals: &HashSet<String>) {
+        if registers::is_register(&argument.name) {
+            self.diagnostics.push(Diagnostic::error(&argument.loc, format!("'{}' is a register and cannot be used as a parameter name", argument.name)));
+        } else if locals.contains(&argument.name) {
+            self.diagnostics.push(Diagnostic::error(&argument.loc, format!("duplicate parameter '{}'", argument.name)));
+        } else if let Some(existing) = self.symbols.get(&argument.name).filter(|existing| existing.loc.file == argument.loc.file) {
+            // Only within one file: a library parameter must not stop programs from using its name
+            self.diagnostics.push(Diagnostic::error(&argument.loc, format!("parameter '{}' shadows the {} declared at {}", argument.name, existing.kind.describe(), existing.loc)));
+        }
+    }
+
+    fn check_clobbers(&mut self, func: &NodeFunc) {
+        if func.name.name == "_start" || func.naked || func.noreturn {
+            return;
+        }
+        let clobbered = clobbers::clobbered(func, self.target);
+        match &func.uses {
+            Some(uses) => {
+                for used in uses {
+                    if !registers::lookup(&used.name).is_some_and(|register| clobbers::is_callee_saved(register.full, self.target)) {
+                        let message = format!("'{}' is not a callee-saved register, 'uses' only takes {}", used.name, clobbers::callee_saved_names(self.target).join(", "));
+                        self.diagnostics.push(Diagnostic::error(&used.loc, message));
+                    }
+                }
+                let saved = clobbers::saved(func, self.target);
+                for register in clobbered.iter().filter(|register| !saved.contains(register)) {
+                    let message = format!("'{}' writes {} but does not list it in 'uses', the caller's value is not restored", func.name.name, register);
+                    self.diagnostics.push(Diagnostic::warning(&func.name.loc, message));
+                }
+            }
+            None if self.warn_clobbers && !clobbered.is_empty() => {
+                let (it, is) = if clobbered.len() == 1 { ("it", "it is") } else { ("them", "they are") };
+                let message = format!("'{}' writes {} without declaring {} with 'uses', {} saved and restored automatically", func.name.name, clobbered.join(", "), it, is);
+                self.diagnostics.push(Diagnostic::warning(&func.name.loc, message));
+            }
+            None => {}
+        }
+    }
+
+    // Closest symbol name of one of the kinds to a misspelt one, if any is close enough to be a
+    // likely typo
+    fn suggest(&self, name: &str, kinds: &[SymbolKind]) -> Option<&str> {
+        self.symbols.iter()
+            .filter(|(_, symbol)| kinds.contains(&symbol.kind))
+            .map(|(candidate, _)| (edit_distance(name, candidate), candidate.as_str()))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, candidate)| candidate)
+    }
+
+    fn undefined_name(&mut self, ident: &NodeExprIdent) {
+        let kinds = [SymbolKind::Function, SymbolKind::Extern, SymbolKind::Data, SymbolKind::Define, SymbolKind::Constant];
+        let message = match self.suggest(&ident.name, &kinds) {
+            Some(suggestion) => format!("undefined name '{}', did you mean '{}'?", ident.name, suggestion),
+            None => format!("undefined name '{}'", ident.name),
+        };
+        self.diagnostics.push(Diagnostic::error(&ident.loc, message));
+    }
+
+    fn check_register(&mut self, ident: &NodeExprIdent) {
+        if let Some(register) = registers::lookup(&ident.name) {
+            if !self.target.has_register(register) {
+                let message = format!("'{}' does not exist in {}-bit code", ident.name, self.target.bits());
+                self.diagnostics.push(Diagnostic::error(&ident.loc, message));
+            }
+        }
+    }
+
+    fn check_name(&mut self, ident: &NodeExprIdent, locals: &HashSet<String>) {
+        if registers::is_register(&ident.name) {
+            self.check_register(ident);
+            return;
+        }
+        if locals.contains(&ident.name) {
+            return;
+        }
+        match self.symbols.get(&ident.name) {
+            Some(Symbol { kind: SymbolKind::Struct, .. }) => {
+                self.diagnostics.push(Diagnostic::error(&ident.loc, format!("'{}' is a struct, use sizeof({}) for its size", ident.name, ident.name)));
+            }
+            Some(_) => {}
+            None if layout::resolve_field(&self.layouts, &ident.name).is_some() => {}
+            None if self.naked_parameters.contains(&ident.name) => {
+                let message = format!("parameter '{}' of a naked function has no frame to be read from, use [{} + N] instead", ident.name, self.target.register("rsp").name);
+                self.diagnostics.push(Diagnostic::error(&ident.loc, message));
+            }
+            None => self.undefined_name(ident),
+        }
+    }
+
+    fn check_expr(&mut self, expr: &NodeExpr, locals: &HashSet<String>) {
+        match expr {
+            NodeExpr::Ident(ident) => self.check_name(ident, locals),
+            NodeExpr::Number(_) | NodeExpr::String(_) => {}
+            NodeExpr::Memory(memory) => {
+                let base = &memory.base;
+                let is_data = matches!(self.symbols.get(&base.name), Some(Symbol { kind: SymbolKind::Data, .. }));
+                if locals.contains(&base.name) {
+                    self.diagnostics.push(Diagnostic::error(&base.loc, format!("parameter '{}' is in memory and cannot be used as the base of a memory operand, load it into a register first", base.name)));
+                } else if registers::is_register(&base.name) {
+                    self.check_register(base);
+                } else if !is_data {
+                    self.check_name(base, locals);
+                }
+                if let Some(offset) = &memory.offset {
+                    self.check_expr(offset, locals);
+                }
+                if let Some(field) = &memory.field {
+                    if layout::resolve_field(&self.layouts, &field.name).is_none() {
+                        self.diagnostics.push(Diagnostic::error(&field.loc, format!("unknown struct field '{}'", field.name)));
+                    }
+                }
+            }
+            NodeExpr::Len(len) => {
+                if let NodeExpr::Ident(ident) = len.ident.as_ref() {
+                    match self.symbols.get(&ident.name) {
+                        Some(Symbol { kind: SymbolKind::Data, .. }) => {}
+                        Some(symbol) => {
+                            let message = format!("len() needs a data label, '{}' is a {}", ident.name, symbol.kind.describe());
+                            self.diagnostics.push(Diagnostic::error(&ident.loc, message));
+                        }
+                        None => self.undefined_name(ident),
+                    }
+                } else {
+                    self.check_expr(&len.ident, locals);
+                }
+            }
+            NodeExpr::Sizeof(sizeof) => {
+                if let NodeExpr::Ident(ident) = sizeof.ident.as_ref() {
+                    match self.symbols.get(&ident.name) {
+                        Some(Symbol { kind: SymbolKind::Data | SymbolKind::Struct, .. }) => {}
+                        Some(symbol) => {
+                            let message = format!("sizeof() needs a data label or struct, '{}' is a {}", ident.name, symbol.kind.describe());
+                            self.diagnostics.push(Diagnostic::error(&ident.loc, message));
+                        }
+                        None => self.undefined_name(ident),
+                    }
+                } else {
+                    self.check_expr(&sizeof.ident, locals);
+                }
+            }
+            NodeExpr::Equal(cmp) => {
+                self.check_expr(&cmp.left, locals);
+                self.check_expr(&cmp.right, locals);
+            }
+            NodeExpr::Lesser(cmp) => {
+                self.check_expr(&cmp.left, locals);
+                self.check_expr(&cmp.right, locals);
+            }
+            NodeExpr::Greater(cmp) => {
+                self.check_expr(&cmp.left, locals);
+                self.check_expr(&cmp.right, locals);
+            }
+            NodeExpr::NotEqual(cmp) => {
+                self.check_expr(&cmp.left, locals);
+                self.check_expr(&cmp.right, locals);
+            }
+            NodeExpr::BitOr(bit_or) => {
+                self.check_expr(&bit_or.left, locals);
+                self.check_expr(&bit_or.right, locals);
+            }
+        }
+    }
+
+    fn classify_argument(&self, argument: &NodeExpr, locals: &HashSet<String>) -> ArgumentKind {
+        match argument {
+            NodeExpr::String(_) => ArgumentKind::Address("a string literal".to_string()),
+            NodeExpr::Ident(ident) if locals.contains(&ident.name) => ArgumentKind::Unknown,
+            NodeExpr::Ident(ident) => match self.symbols.get(&ident.name) {
+                Some(Symbol { kind: SymbolKind::Data, .. }) => ArgumentKind::Address(format!("data label '{}'", ident.name)),
+                Some(Symbol { kind: SymbolKind::Function | SymbolKind::Extern, .. }) => ArgumentKind::Address(format!("function '{}'", ident.name)),
+                Some(Symbol { kind: SymbolKind::Constant, .. }) => ArgumentKind::Number(consts::eval_expr(argument, &self.constants)),
+                _ => ArgumentKind::Unknown,
+            },
+            NodeExpr::Number(number) => ArgumentKind::Number(Some(number.value)),
+            NodeExpr::BitOr(_) => ArgumentKind::Number(consts::eval_expr(argument, &self.constants)),
+            NodeExpr::Len(_) | NodeExpr::Sizeof(_) => ArgumentKind::Number(None),
+            _ => ArgumentKind::Unknown,
+        }
+    }
+
+    fn operand_ident(&self, ident: &NodeExprIdent, locals: &HashSet<String>) -> Operand {
+        if locals.contains(&ident.name) {
+            // Parameters live in the caller's frame at `[rbp + N]`, a word each
+            return Operand::Memory { size: Some(self.target.word_size()), rex: false };
+        }
+        if let Some(register) = registers::lookup(&ident.name) {
+            return Operand::Register(register);
+        }
+        match self.symbols.get(&ident.name) {
+            Some(Symbol { kind: SymbolKind::Data, .. }) => Operand::Address(format!("data label '{}'", ident.name)),
+            Some(Symbol { kind: SymbolKind::Function | SymbolKind::Extern, .. }) => Operand::Address(format!("function '{}'", ident.name)),
+            Some(Symbol { kind: SymbolKind::Constant, .. }) => Operand::Immediate(self.constants.get(&ident.name).copied()),
+            Some(_) => Operand::Unknown,
+            None => match layout::resolve_field(&self.layouts, &ident.name) {
+                Some((offset, _)) => Operand::Immediate(Some(offset as i64)),
+                None => Operand::Unknown,
+            },
+        }
+    }
+
+    fn operand(&self, expr: &NodeExpr, locals: &HashSet<String>) -> Operand {
+        match expr {
+            NodeExpr::Ident(ident) => self.operand_ident(ident, locals),
+            NodeExpr::Number(number) => Operand::Immediate(Some(number.value)),
+            NodeExpr::BitOr(_) => Operand::Immediate(consts::eval_expr(expr, &self.constants)),
+            NodeExpr::Len(_) | NodeExpr::Sizeof(_) => Operand::Immediate(None),
+            NodeExpr::String(_) => Operand::Address("a string literal".to_string()),
+            NodeExpr::Memory(memory) => {
+                let size = match &memory.field {
+                    Some(field) => layout::resolve_field(&self.layouts, &field.name)
+                        .and_then(|(_, field)| field.data_type)
+                        .map(|data_type| data_type.size()),
+                    None => self.data_types.get(&memory.base.name).map(|data_type| data_type.size()),
+                };
+                let uses_rex = |name: &str| registers::lookup(name).is_some_and(|register| register.rex);
+                let rex = uses_rex(&memory.base.name) || matches!(memory.offset.as_deref(), Some(NodeExpr::Ident(index)) if uses_rex(&index.name));
+                Operand::Memory { size, rex }
+            }
+            NodeExpr::Equal(_) | NodeExpr::Lesser(_) | NodeExpr::Greater(_) | NodeExpr::NotEqual(_) => Operand::Unknown,
+        }
+    }
+
+    fn check_instruction(&mut self, mnemonic: &str, dest: &NodeExpr, src: &NodeExpr, loc: &Loc, locals: &HashSet<String>) {
+        let dest = self.operand(dest, locals);
+        let src = self.operand(src, locals);
+        if let Err(message) = operands::check_binary(mnemonic, &dest, &src, self.target.word_size()) {
+            self.diagnostics.push(Diagnostic::error(loc, message));
+        }
+    }
+
+    // `if` and `while` conditions compile to a `cmp` of their two sides
+    fn check_condition(&mut self, condition: &NodeExpr, loc: &Loc, locals: &HashSet<String>) {
+        let (left, right) = match condition {
+            NodeExpr::Equal(cmp) => (&cmp.left, &cmp.right),
+            NodeExpr::Lesser(cmp) => (&cmp.left, &cmp.right),
+            NodeExpr::Greater(cmp) => (&cmp.left, &cmp.right),
+            NodeExpr::NotEqual(cmp) => (&cmp.left, &cmp.right),
+            _ => return,
+        };
+        self.check_instruction("cmp", left, right, loc, locals);
+    }
+
+    // The body of an `if` or `while` may or may not run, so the code after it only knows how
+    // deep the stack is, and so how to align calls, when the body leaves it as it found it
+    fn check_stack_balance(&mut self, body: &[NodeStmt], statement: &str, loc: &Loc) {
+        let word = self.target.word_size() as i64;
+        let rsp = self.target.register("rsp").name;
+        let mut depth = 0;
+        for stmt in body {
+            match stmt {
+                NodeStmt::Push(_) => depth += word,
+                NodeStmt::Pop(_) => depth -= word,
+                NodeStmt::Add(add) => {
+                    if let (NodeExpr::Ident(dest), Some(bytes)) = (&add.dest, consts::eval_expr(&add.expr, &self.constants)) {
+                        if dest.name == rsp {
+                            depth -= bytes;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if depth != 0 {
+            let (amount, direction) = if depth > 0 { (depth, "deeper") } else { (-depth, "shallower") };
+            let message = format!("the body of this '{}' leaves the stack {} bytes {} than it found it, push and pop the same amount inside it", statement, amount, direction);
+            self.diagnostics.push(Diagnostic::error(loc, message));
+        }
+    }
+
+    fn check_call(&mut self, call: &NodeStmtCall, locals: &HashSet<String>) {
+        let Some(params) = self.signatures.get(&call.name.name).copied() else {
+            return;
+        };
+        let declared = &self.symbols[&call.name.name].loc;
+        if params.len() != call.arguments.len() {
+            let message = format!(
+                "'{}' takes {} argument{} but {} {} given (declared at {})",
+                call.name.name, params.len(), if params.len() == 1 { "" } else { "s" },
+                call.arguments.len(), if call.arguments.len() == 1 { "was" } else { "were" }, declared,
+            );
+            self.diagnostics.push(Diagnostic::error(&call.name.loc, message));
+            return;
+        }
+
+        for (argument, param) in call.arguments.iter().zip(params) {
+            let Some(param_type) = param.param_type else {
+                continue;
+            };
+            let expected = format!("parameter '{}: {}' of '{}'", param.ident.name, param_type.name(), call.name.name);
+            let message = match (param_type, self.classify_argument(argument, locals)) {
+                (ParamType::Int(_), ArgumentKind::Address(what)) => {
+                    format!("passing the address of {} to {}, which expects a number", what, expected)
+                }
+                // Zero is accepted as a null pointer
+                (ParamType::Ptr, ArgumentKind::Number(value)) if value != Some(0) => {
+                    format!("passing a number to {}, which expects an address", expected)
+                }
+                (ParamType::Int(data_type), ArgumentKind::Number(Some(value))) if !fits(value, data_type) => {
+                    format!("value {} does not fit in {}", value, expected)
+                }
+                _ => continue,
+            };
+            self.diagnostics.push(Diagnostic::warning(&call.name.loc, message));
+        }
+    }
+
+    // Checks a block of statements and warns about anything after a call that never returns
+    fn check_body(&mut self, body: &[NodeStmt], locals: &HashSet<String>) {
+        let mut after_noreturn: Option<&NodeExprIdent> = None;
+        for stmt in body {
+            if let Some(callee) = after_noreturn.take() {
+                let loc = stmt.loc().unwrap_or(&callee.loc);
+                let message = format!("unreachable code, '{}' does not return", callee.name);
+                self.diagnostics.push(Diagnostic::warning(loc, message));
+            }
+            self.check_stmt(stmt, locals);
+            if let NodeStmt::Call(call) = stmt {
+                if self.noreturn.contains(&call.name.name) {
+                    after_noreturn = Some(&call.name);
+                }
+            }
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &NodeStmt, locals: &HashSet<String>) {
+        match stmt {
+            NodeStmt::Mov(mov) => {
+                self.check_expr(&mov.dest, locals);
+                self.check_expr(&mov.expr, locals);
+                self.check_instruction("mov", &mov.dest, &mov.expr, &mov.loc, locals);
+            }
+            NodeStmt::Add(add) => {
+                self.check_expr(&add.dest, locals);
+                self.check_expr(&add.expr, locals);
+                self.check_instruction("add", &add.dest, &add.expr, &add.loc, locals);
+            }
+            NodeStmt::Xor(xor) => {
+                self.check_expr(&xor.dest, locals);
+                self.check_expr(&xor.expr, locals);
+                self.check_instruction("xor", &xor.dest, &xor.expr, &xor.loc, locals);
+            }
+            NodeStmt::Push(push) => {
+                self.check_expr(&push.expr, locals);
+                let operand = self.operand(&push.expr, locals);
+                if let Err(message) = operands::check_stack("push", &operand, self.target.word_size()) {
+                    self.diagnostics.push(Diagnostic::error(&push.loc, message));
+                }
+            }
+            NodeStmt::Pop(pop) => {
+                self.check_name(&pop.ident, locals);
+                let operand = self.operand_ident(&pop.ident, locals);
+                if let Err(message) = operands::check_stack("pop", &operand, self.target.word_size()) {
+                    self.diagnostics.push(Diagnostic::error(&pop.ident.loc, message));
+                }
+            }
+            NodeStmt::Syscall(syscall) if self.target == Target::X86 => {
+                self.diagnostics.push(Diagnostic::error(&syscall.loc, "'syscall' does not exist in 32-bit code, Linux takes system calls through 'int 0x80'".to_string()));
+            }
+            NodeStmt::Syscall(syscall) if self.target == Target::X86_16 => {
+                self.diagnostics.push(Diagnostic::error(&syscall.loc, "'syscall' does not exist in 16-bit code".to_string()));
+            }
+            NodeStmt::Org(org) => {
+                self.check_expr(&org.expr, locals);
+                if !self.flat {
+                    self.diagnostics.push(Diagnostic::error(&org.loc, "'org' only applies to flat binaries, use -f bin".to_string()));
+                } else if consts::eval_expr(&org.expr, &self.constants).is_none_or(|origin| origin < 0) {
+                    self.diagnostics.push(Diagnostic::error(&org.loc, "'org' needs a constant address".to_string()));
+                }
+            }
+            NodeStmt::Pad(pad) => {
+                self.check_expr(&pad.expr, locals);
+                if consts::eval_expr(&pad.expr, &self.constants).is_none_or(|size| size < 0) {
+                    self.diagnostics.push(Diagnostic::error(&pad.loc, "'pad' needs a constant size".to_string()));
+                }
+            }
+            NodeStmt::Int(int) => {
+                self.check_expr(&int.vector, locals);
+                if !consts::eval_expr(&int.vector, &self.constants).is_some_and(|vector| (0..=255).contains(&vector)) {
+                    self.diagnostics.push(Diagnostic::error(&int.loc, "'int' needs a constant interrupt vector from 0 to 255".to_string()));
+                }
+            }
+            NodeStmt::Call(call) => {
+                match self.symbols.get(&call.name.name) {
+                    Some(Symbol { kind: SymbolKind::Function | SymbolKind::Extern, .. }) => self.check_call(call, locals),
+                    Some(symbol) => {
+                        let message = format!("'{}' is a {}, not a function", call.name.name, symbol.kind.describe());
+                        self.diagnostics.push(Diagnostic::error(&call.name.loc, message));
+                    }
+                    None => {
+                        let message = match self.suggest(&call.name.name, &[SymbolKind::Function, SymbolKind::Extern]) {
+                            Some(suggestion) => format!("call to undefined function '{}', did you mean '{}'?", call.name.name, suggestion),
+                            None => format!("call to undefined function '{}'", call.name.name),
+                        };
+                        self.diagnostics.push(Diagnostic::error(&call.name.loc, message));
+                    }
+                }
+                for argument in &call.arguments {
+                    self.check_expr(argument, locals);
+                }
+            }
+            NodeStmt::Global(global) => {
+                match self.symbols.get(&global.ident.name) {
+                    Some(Symbol { kind: SymbolKind::Function | SymbolKind::Data, .. }) => {}
+                    _ => {
+                        let message = format!("'{}' is declared global but there is no function or data label with that name", global.ident.name);
+                        self.diagnostics.push(Diagnostic::error(&global.ident.loc, message));
+                    }
+                }
+            }
+            NodeStmt::If(if_stmt) => {
+                self.check_expr(&if_stmt.condition, locals);
+                self.check_condition(&if_stmt.condition, &if_stmt.loc, locals);
+                self.check_body(&if_stmt.body, locals);
+                self.check_stack_balance(&if_stmt.body, "if", &if_stmt.loc);
+            }
+            NodeStmt::While(while_stmt) => {
+                self.check_expr(&while_stmt.condition, locals);
+                self.check_condition(&while_stmt.condition, &while_stmt.loc, locals);
+                self.check_body(&while_stmt.body, locals);
+                self.check_stack_balance(&while_stmt.body, "while", &while_stmt.loc);
+            }
+            NodeStmt::Data(data) => {
+                for value in &data.values {
+                    self.check_expr(value, locals);
+                }
+            }
+            NodeStmt::Instance(instance) => {
+                match self.layouts.get(&instance.struct_name.name) {
+                    Some(layout) => {
+                        for field in &instance.fields {
+                            if layout.field(&field.ident.name).is_none() {
+                                let message = format!("struct '{}' has no field '{}'", layout.name, field.ident.name);
+                                self.diagnostics.push(Diagnostic::error(&field.ident.loc, message));
+                            }
+                        }
+                    }
+                    None => {
+                        let message = format!("unknown type '{}'", instance.struct_name.name);
+                        self.diagnostics.push(Diagnostic::error(&instance.struct_name.loc, message));
+                    }
+                }
+                for field in &instance.fields {
+                    self.check_expr(&field.expr, locals);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Whether a value fits an unsigned parameter of the given width; negative values are
+// accepted as their two's complement bit pattern
+fn fits(value: i64, data_type: DataType) -> bool {
+    match data_type {
+        DataType::U64 => true,
+        _ => {
+            let bits = data_type.size() as u32 * 8;
+            value >= -(1 << (bits - 1)) && value < (1 << bits)
+        }
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::parser::Parser;
+    use crate::tokenizer::Tokenizer;
+
+    // Every diagnostic for the source, as it is printed
+    fn resolve(source: &str) -> Vec<String> {
+        let tokens = Tokenizer::new(source.to_string(), Path::new("test.asmpp")).tokenize();
+        let node = Parser::new(tokens).parse_prog();
+        Resolver::new(&node).resolve().iter().map(|diagnostic| diagnostic.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_undefined_names_with_a_suggestion() {
+        let source = "\
+fn print(message: ptr) {
+}
+fn _start() {
+    call pritn(0)
+    mov rax, counter
+}
+";
+        assert_eq!(resolve(source), [
+            "test.asmpp:4:10: error: call to undefined function 'pritn', did you mean 'print'?",
+            "test.asmpp:5:14: error: undefined name 'counter'",
+        ]);
+    }
+
+    #[test]
+    fn reports_undefined_names_in_constant_values() {
+        let source = "\
+const {
+    LIMIT = 4
+    DOUBLE = LIMTI
+}
+enum Mode { READ, BOTH = READ | WRITE }
+define SIZE BREADTH
+";
+        assert_eq!(resolve(source), [
+            "test.asmpp:5:19: error: value of 'Mode.BOTH' is not a constant expression",
+            "test.asmpp:3:5: error: value of 'DOUBLE' is not a constant expression",
+            "test.asmpp:3:14: error: undefined name 'LIMTI', did you mean 'LIMIT'?",
+            "test.asmpp:5:33: error: undefined name 'WRITE'",
+            "test.asmpp:6:13: error: undefined name 'BREADTH'",
+        ]);
+    }
+
+    #[test]
+    fn reports_duplicates_at_the_second_declaration() {
+        let source = "\
+fn twice() {
+}
+section .data
+    twice: u8 = 1
+";
+        assert_eq!(resolve(source), [
+            "test.asmpp:4:5: error: duplicate data label 'twice', already declared as function at test.asmpp:1:4",
+        ]);
+    }
+
+    #[test]
+    fn reports_parameters_that_shadow_globals() {
+        let source = "\
+section .data
+    count: u64 = 0
+section .text
+fn advance(count: u64, step: u64, step: u64) {
+}
+";
+        assert_eq!(resolve(source), [
+            "test.asmpp:4:12: error: parameter 'count' shadows the data label declared at test.asmpp:2:5",
+            "test.asmpp:4:35: error: duplicate parameter 'step'",
+        ]);
+    }
+
+    #[test]
+    fn reports_calls_with_the_wrong_number_of_arguments() {
+        let source = "\
+fn pair(a: u64, b: u64) {
+}
+fn _start() {
+    call pair(1)
+}
+";
+        assert_eq!(resolve(source), [
+            "test.asmpp:4:10: error: 'pair' takes 2 arguments but 1 was given (declared at test.asmpp:1:4)",
+        ]);
+    }
+
+    #[test]
+    fn reports_bad_struct_fields() {
+        let source = "\
+struct Outer {
+    inner: Inner
+    self: Outer
+    wide: u128
+}
+struct Inner {
+    x: u8
+}
+section .data
+    point: Inner = { x: 1, y: 2 }
+";
+        assert_eq!(resolve(source), [
+            "test.asmpp:2:12: error: struct 'Inner' is used by 'Outer.inner' before it is declared",
+            "test.asmpp:3:11: error: struct 'Outer' cannot contain itself",
+            "test.asmpp:4:11: error: unknown type 'u128' for field 'Outer.wide'",
+            "test.asmpp:10:28: error: struct 'Inner' has no field 'y'",
+        ]);
+    }
+}
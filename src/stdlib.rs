@@ -0,0 +1,31 @@
+use crate::target::Target;
+
+/// Libraries compiled into the asmpp binary, reachable with `include <name>` or `import name`.
+/// They are versioned together with the compiler; `--stdlib <dir>` loads `<dir>/<name>.asmpp`
+/// from disk instead, which is meant for working on the libraries themselves.
+const LIBRARIES: &[(&str, &str)] = &[
+    ("std", include_str!("../lib/std.asmpp")),
+];
+
+/// The same libraries for 32-bit x86, which makes system calls with `int 0x80` and numbers
+/// them differently. On disk they are in `<dir>/x86`.
+const X86_LIBRARIES: &[(&str, &str)] = &[
+    ("std", include_str!("../lib/x86/std.asmpp")),
+];
+
+fn libraries(target: Target) -> &'static [(&'static str, &'static str)] {
+    match target {
+        Target::X86_64 => LIBRARIES,
+        Target::X86 => X86_LIBRARIES,
+        // Real mode code runs without an operating system to make calls to
+        Target::X86_16 => &[],
+    }
+}
+
+pub fn bundled(name: &str, target: Target) -> Option<&'static str> {
+    libraries(target).iter().find(|(library, _)| *library == name).map(|(_, source)| *source)
+}
+
+pub fn names(target: Target) -> Vec<&'static str> {
+    libraries(target).iter().map(|(library, _)| *library).collect()
+}
@@ -0,0 +1,101 @@
+use crate::parser::DataType;
+use crate::registers::{self, Register};
+
+/// The processor a program is compiled for, picked with `-f`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Target {
+    #[default]
+    X86_64,
+    // 32-bit x86, Linux system calls go through `int 0x80`
+    X86,
+    // Real mode, for boot sectors and other flat binaries that run without an operating system
+    X86_16,
+}
+
+impl Target {
+    /// The target of an output format, every format NASM knows that is not 32-bit is taken
+    /// to be x86-64.
+    pub fn from_format(format: &str) -> Target {
+        match format {
+            "elf32" | "win32" | "macho32" | "coff" => Target::X86,
+            // NASM starts flat binaries in 16-bit mode as well
+            "bin" => Target::X86_16,
+            _ => Target::X86_64,
+        }
+    }
+
+    /// The target of `bits n`.
+    pub fn from_bits(bits: u32) -> Option<Target> {
+        match bits {
+            64 => Some(Target::X86_64),
+            32 => Some(Target::X86),
+            16 => Some(Target::X86_16),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            Target::X86_64 => 64,
+            Target::X86 => 32,
+            Target::X86_16 => 16,
+        }
+    }
+
+    /// Size of a stack slot, a pointer and a parameter in bytes.
+    pub fn word_size(&self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    pub fn word_type(&self) -> DataType {
+        match self {
+            Target::X86_64 => DataType::U64,
+            Target::X86 => DataType::U32,
+            Target::X86_16 => DataType::U16,
+        }
+    }
+
+    /// Alignment of the stack pointer at a call. Both Linux ABIs want 16 bytes, real mode code
+    /// has no ABI and is not padded at all.
+    pub fn stack_alignment(&self) -> i64 {
+        match self {
+            Target::X86_64 | Target::X86 => 16,
+            Target::X86_16 => self.word_size() as i64,
+        }
+    }
+
+    /// The register of the width of a word that belongs to the 64-bit register `full`,
+    /// `ebp` for `rbp` on x86.
+    pub fn register(&self, full: &str) -> &'static Register {
+        registers::lookup(full)
+            .and_then(|register| registers::sibling(register, self.word_size()))
+            .unwrap_or_else(|| panic!("Unknown register '{}'", full))
+    }
+
+    /// Whether the register exists on this target: x86 has neither the 64-bit registers nor
+    /// any that needs a REX prefix. 16-bit code can still use the 32-bit registers.
+    pub fn has_register(&self, register: &Register) -> bool {
+        match self {
+            Target::X86_64 => true,
+            Target::X86 | Target::X86_16 => register.size <= 4 && !register.rex,
+        }
+    }
+
+    /// Emulation `ld` links objects of this target with. 16-bit code only ends up in flat
+    /// binaries, which are never linked.
+    pub fn ld_emulation(&self) -> &'static str {
+        match self {
+            Target::X86_64 => "elf_x86_64",
+            Target::X86 | Target::X86_16 => "elf_i386",
+        }
+    }
+
+    /// Directory below `lib` with the libraries of this target, the x86-64 ones are at the top.
+    pub fn library_dir(&self) -> Option<&'static str> {
+        match self {
+            Target::X86_64 => None,
+            Target::X86 => Some("x86"),
+            Target::X86_16 => Some("x86_16"),
+        }
+    }
+}
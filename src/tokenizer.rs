@@ -1,4 +1,6 @@
 use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TokenType {
@@ -14,6 +16,7 @@ pub enum TokenType {
     CurlyL,
     CurlyR,
     Syscall,
+    Int,
     Call,    
     Section,
     Colon,
@@ -29,14 +32,54 @@ pub enum TokenType {
     Pop,
     Len,
     Define,
-    Include
+    Include,
+    BracketL,
+    BracketR,
+    Assign,
+    Star,
+    Sizeof,
+    Align,
+    Struct,
+    Packed,
+    Dot,
+    Plus,
+    Minus,
+    Enum,
+    Const,
+    Pipe,
+    Pragma,
+    EndInclude,
+    Import,
+    LibPath,
+    Module,
+    Pub,
+    Extern,
+    Bits,
+    Org,
+    Pad,
+    BootSignature,
 }
 
 
+/// Position of a token in its source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loc {
+    pub file: Rc<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub value: Option<String>,
+    pub loc: Loc,
 }
 
 fn token_type_to_String(token_type: TokenType) -> String {
@@ -53,6 +96,7 @@ fn token_type_to_String(token_type: TokenType) -> String {
         TokenType::CurlyL => "CurlyL".to_string(),
         TokenType::CurlyR => "CurlyR".to_string(),
         TokenType::Syscall => "Syscall".to_string(),
+        TokenType::Int => "Int".to_string(),
         TokenType::Call => "Call".to_string(),
         TokenType::Section => "Section".to_string(),
         TokenType::Colon => "Colon".to_string(),
@@ -69,6 +113,31 @@ fn token_type_to_String(token_type: TokenType) -> String {
         TokenType::Len => "Len".to_string(),
         TokenType::Define => "Define".to_string(),
         TokenType::Include => "Include".to_string(),
+        TokenType::BracketL => "BracketL".to_string(),
+        TokenType::BracketR => "BracketR".to_string(),
+        TokenType::Assign => "Assign".to_string(),
+        TokenType::Star => "Star".to_string(),
+        TokenType::Sizeof => "Sizeof".to_string(),
+        TokenType::Align => "Align".to_string(),
+        TokenType::Struct => "Struct".to_string(),
+        TokenType::Packed => "Packed".to_string(),
+        TokenType::Dot => "Dot".to_string(),
+        TokenType::Plus => "Plus".to_string(),
+        TokenType::Minus => "Minus".to_string(),
+        TokenType::Enum => "Enum".to_string(),
+        TokenType::Const => "Const".to_string(),
+        TokenType::Pipe => "Pipe".to_string(),
+        TokenType::Pragma => "Pragma".to_string(),
+        TokenType::EndInclude => "EndInclude".to_string(),
+        TokenType::Import => "Import".to_string(),
+        TokenType::LibPath => "LibPath".to_string(),
+        TokenType::Module => "Module".to_string(),
+        TokenType::Pub => "Pub".to_string(),
+        TokenType::Extern => "Extern".to_string(),
+        TokenType::Bits => "Bits".to_string(),
+        TokenType::Org => "Org".to_string(),
+        TokenType::Pad => "Pad".to_string(),
+        TokenType::BootSignature => "BootSignature".to_string(),
     }
 }
 
@@ -82,21 +151,32 @@ impl fmt::Display for Token {
 pub struct Tokenizer {
     contents: String,
     index: usize,
+    file: Rc<PathBuf>,
+    line: usize,
+    column: usize,
 }
 
 impl Tokenizer {
-    pub fn new(contents: String) -> Tokenizer {
+    pub fn new(contents: String, file: &Path) -> Tokenizer {
         Tokenizer {
             contents,
             index: 0,
+            file: Rc::new(file.to_path_buf()),
+            line: 1,
+            column: 1,
         }
     }
 
+    fn loc(&self) -> Loc {
+        Loc { file: self.file.clone(), line: self.line, column: self.column }
+    }
+
 
     pub fn tokenize(&mut self) -> Vec<Token>{
         let mut tokens = Vec::new();
         let mut buffer = String::new();
         while let Some(c) = self.peek(0) {
+            let loc = self.loc();
             if c == ';' {
                 while let Some(c) = self.peek(0) {
                     if c != '\n' {
@@ -111,45 +191,69 @@ impl Tokenizer {
                 while let Some(c) = self.peek(0) {
                     if c.is_alphanumeric() || c == '_' {
                         buffer.push(self.consume());
+                    } else if c == '.' && self.peek(1).is_some_and(|next| next.is_alphabetic() || next == '_') {
+                        // Qualified names such as `Point.x` are a single identifier
+                        buffer.push(self.consume());
                     } else {
                         break;
                     }
                 }
 
                 match buffer.as_str() {
-                    "mov" => tokens.push(Token { token_type: TokenType::Mov, value: None }),
-                    "add" => tokens.push(Token { token_type: TokenType::Add, value: None }),
-                    "global" => tokens.push(Token { token_type: TokenType::Global, value: None }),
-                    "fn" => tokens.push(Token { token_type: TokenType::Function, value: None }),
-                    "syscall" => tokens.push(Token { token_type: TokenType::Syscall, value: None }),
-                    "call" => tokens.push(Token { token_type: TokenType::Call, value: None }),
-                    "section" => tokens.push(Token { token_type: TokenType::Section, value: None }),
-                    "eq" => tokens.push(Token { token_type: TokenType::Equal, value: None }),
-                    "lt" => tokens.push(Token { token_type: TokenType::Lesser, value: None }),
-                    "gt" => tokens.push(Token { token_type: TokenType::Greater, value: None }),
-                    "ne" => tokens.push(Token { token_type: TokenType::NotEqual, value: None }),
-                    "if" => tokens.push(Token { token_type: TokenType::If, value: None }),
-                    "while" => tokens.push(Token { token_type: TokenType::While, value: None }),
-                    "push" => tokens.push(Token { token_type: TokenType::Push, value: None }),
-                    "xor" => tokens.push(Token { token_type: TokenType::Xor, value: None }),
-                    "pop" => tokens.push(Token { token_type: TokenType::Pop, value: None }),
-                    "len" => tokens.push(Token { token_type: TokenType::Len, value: None }),
-                    "define" => tokens.push(Token { token_type: TokenType::Define, value: None }),
-                    "include" => tokens.push(Token { token_type: TokenType::Include, value: None }),
-                    _ => tokens.push(Token { token_type: TokenType::Identifier, value: Some(buffer.clone()) }),
+                    "mov" => tokens.push(Token { token_type: TokenType::Mov, value: None, loc: loc.clone() }),
+                    "add" => tokens.push(Token { token_type: TokenType::Add, value: None, loc: loc.clone() }),
+                    "global" => tokens.push(Token { token_type: TokenType::Global, value: None, loc: loc.clone() }),
+                    "fn" => tokens.push(Token { token_type: TokenType::Function, value: None, loc: loc.clone() }),
+                    "syscall" => tokens.push(Token { token_type: TokenType::Syscall, value: None, loc: loc.clone() }),
+                    "int" => tokens.push(Token { token_type: TokenType::Int, value: None, loc: loc.clone() }),
+                    "call" => tokens.push(Token { token_type: TokenType::Call, value: None, loc: loc.clone() }),
+                    "section" => tokens.push(Token { token_type: TokenType::Section, value: None, loc: loc.clone() }),
+                    "eq" => tokens.push(Token { token_type: TokenType::Equal, value: None, loc: loc.clone() }),
+                    "lt" => tokens.push(Token { token_type: TokenType::Lesser, value: None, loc: loc.clone() }),
+                    "gt" => tokens.push(Token { token_type: TokenType::Greater, value: None, loc: loc.clone() }),
+                    "ne" => tokens.push(Token { token_type: TokenType::NotEqual, value: None, loc: loc.clone() }),
+                    "if" => tokens.push(Token { token_type: TokenType::If, value: None, loc: loc.clone() }),
+                    "while" => tokens.push(Token { token_type: TokenType::While, value: None, loc: loc.clone() }),
+                    "push" => tokens.push(Token { token_type: TokenType::Push, value: None, loc: loc.clone() }),
+                    "xor" => tokens.push(Token { token_type: TokenType::Xor, value: None, loc: loc.clone() }),
+                    "pop" => tokens.push(Token { token_type: TokenType::Pop, value: None, loc: loc.clone() }),
+                    "len" => tokens.push(Token { token_type: TokenType::Len, value: None, loc: loc.clone() }),
+                    "define" => tokens.push(Token { token_type: TokenType::Define, value: None, loc: loc.clone() }),
+                    "include" => tokens.push(Token { token_type: TokenType::Include, value: None, loc: loc.clone() }),
+                    "import" => tokens.push(Token { token_type: TokenType::Import, value: None, loc: loc.clone() }),
+                    "module" => tokens.push(Token { token_type: TokenType::Module, value: None, loc: loc.clone() }),
+                    "pub" => tokens.push(Token { token_type: TokenType::Pub, value: None, loc: loc.clone() }),
+                    "extern" => tokens.push(Token { token_type: TokenType::Extern, value: None, loc: loc.clone() }),
+                    "sizeof" => tokens.push(Token { token_type: TokenType::Sizeof, value: None, loc: loc.clone() }),
+                    "align" => tokens.push(Token { token_type: TokenType::Align, value: None, loc: loc.clone() }),
+                    "struct" => tokens.push(Token { token_type: TokenType::Struct, value: None, loc: loc.clone() }),
+                    "packed" => tokens.push(Token { token_type: TokenType::Packed, value: None, loc: loc.clone() }),
+                    "enum" => tokens.push(Token { token_type: TokenType::Enum, value: None, loc: loc.clone() }),
+                    "const" => tokens.push(Token { token_type: TokenType::Const, value: None, loc: loc.clone() }),
+                    "bits" => tokens.push(Token { token_type: TokenType::Bits, value: None, loc: loc.clone() }),
+                    "org" => tokens.push(Token { token_type: TokenType::Org, value: None, loc: loc.clone() }),
+                    "pad" => tokens.push(Token { token_type: TokenType::Pad, value: None, loc: loc.clone() }),
+                    "boot_signature" => tokens.push(Token { token_type: TokenType::BootSignature, value: None, loc: loc.clone() }),
+                    _ => tokens.push(Token { token_type: TokenType::Identifier, value: Some(buffer.clone()), loc: loc.clone() }),
                 }
                 buffer.clear();
             }
-            else if c.is_digit(10){
+            else if c.is_ascii_digit() {
                 buffer.push(self.consume());
+                // Hex (0x) and binary (0b) literals keep their prefix, the parser decodes them
+                let mut hex = false;
+                if c == '0' && matches!(self.peek(0), Some('x') | Some('X') | Some('b') | Some('B')) {
+                    hex = matches!(self.peek(0), Some('x') | Some('X'));
+                    buffer.push(self.consume());
+                }
                 while let Some(c) = self.peek(0) {
-                    if c.is_digit(10) {
+                    if c.is_ascii_digit() || c == '_' || (hex && c.is_ascii_hexdigit()) {
                         buffer.push(self.consume());
                     } else {
                         break;
                     }
                 }
-                tokens.push(Token { token_type: TokenType::Number, value: Some(buffer.clone()) });
+                tokens.push(Token { token_type: TokenType::Number, value: Some(buffer.clone()), loc: loc.clone() });
                 buffer.clear();
             }
             else if c == '"' {
@@ -162,37 +266,98 @@ impl Tokenizer {
                     }
                 }
                 self.consume();
-                tokens.push(Token { token_type: TokenType::StringLit, value: Some(buffer.clone()) });
+                tokens.push(Token { token_type: TokenType::StringLit, value: Some(buffer.clone()), loc: loc.clone() });
                 buffer.clear();
             }
             else if c == '(' {
-                tokens.push(Token { token_type: TokenType::Lparen, value: None });
+                tokens.push(Token { token_type: TokenType::Lparen, value: None, loc: loc.clone() });
                 self.consume();
             }
             else if c == ')' {
-                tokens.push(Token { token_type: TokenType::Rparen, value: None });
+                tokens.push(Token { token_type: TokenType::Rparen, value: None, loc: loc.clone() });
                 self.consume();
             }
             else if c == ',' {
-                tokens.push(Token { token_type: TokenType::Comma, value: None });
+                tokens.push(Token { token_type: TokenType::Comma, value: None, loc: loc.clone() });
                 self.consume();
             }
             else if c == '{' {
-                tokens.push(Token { token_type: TokenType::CurlyL, value: None });
+                tokens.push(Token { token_type: TokenType::CurlyL, value: None, loc: loc.clone() });
                 self.consume();
             }
             else if c == '}' {
-                tokens.push(Token { token_type: TokenType::CurlyR, value: None });
+                tokens.push(Token { token_type: TokenType::CurlyR, value: None, loc: loc.clone() });
                 self.consume();
             }
             else if c == ':' {
-                tokens.push(Token { token_type: TokenType::Colon, value: None });
+                tokens.push(Token { token_type: TokenType::Colon, value: None, loc: loc.clone() });
+                self.consume();
+            }
+            else if c == '[' {
+                tokens.push(Token { token_type: TokenType::BracketL, value: None, loc: loc.clone() });
+                self.consume();
+            }
+            else if c == ']' {
+                tokens.push(Token { token_type: TokenType::BracketR, value: None, loc: loc.clone() });
+                self.consume();
+            }
+            else if c == '=' {
+                tokens.push(Token { token_type: TokenType::Assign, value: None, loc: loc.clone() });
+                self.consume();
+            }
+            else if c == '*' {
+                tokens.push(Token { token_type: TokenType::Star, value: None, loc: loc.clone() });
+                self.consume();
+            }
+            else if c == '.' {
+                tokens.push(Token { token_type: TokenType::Dot, value: None, loc: loc.clone() });
+                self.consume();
+            }
+            else if c == '+' {
+                tokens.push(Token { token_type: TokenType::Plus, value: None, loc: loc.clone() });
+                self.consume();
+            }
+            else if c == '-' {
+                tokens.push(Token { token_type: TokenType::Minus, value: None, loc: loc.clone() });
                 self.consume();
             }
-            else if c.is_whitespace() {
+            else if c == '<' {
+                // `<name>` names a bundled library, as in `include <std>`
+                self.consume();
+                while let Some(c) = self.peek(0) {
+                    if c != '>' && c != '\n' {
+                        buffer.push(self.consume());
+                    } else {
+                        break;
+                    }
+                }
+                if self.consume() != '>' {
+                    panic!("{}: Expected '>' to close the library name '<{}'", loc, buffer);
+                }
+                tokens.push(Token { token_type: TokenType::LibPath, value: Some(buffer.trim().to_string()), loc: loc.clone() });
+                buffer.clear();
+            }
+            else if c == '#' {
+                self.consume();
+                while let Some(c) = self.peek(0) {
+                    if c.is_alphanumeric() || c == '_' {
+                        buffer.push(self.consume());
+                    } else {
+                        break;
+                    }
+                }
+                if buffer != "pragma" {
+                    panic!("{}: Unknown directive '#{}'", loc, buffer);
+                }
+                tokens.push(Token { token_type: TokenType::Pragma, value: None, loc: loc.clone() });
+                buffer.clear();
+            }
+            else if c == '|' {
+                tokens.push(Token { token_type: TokenType::Pipe, value: None, loc: loc.clone() });
                 self.consume();
             }
             else {
+                // Whitespace and unknown characters are skipped
                 self.consume();
             }
         }
@@ -214,6 +379,12 @@ impl Tokenizer {
         if self.index < self.contents.len(){
             let c = self.contents[self.index..].chars().next().unwrap();
             self.index += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
             c
         }
         else {